@@ -1,5 +1,34 @@
-use crate::lib::config::AppDescConfig;
+use crate::datasource::ds::{DataSource, DataSourceError, SourceOptions};
+use crate::lib::context::AppContext;
 use crate::lib::prompt::PromptData;
+use futures::future::LocalBoxFuture;
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug, Default)]
+pub struct AppDescConfig {
+    pub order_no: u8,
+    pub description: String,
+    #[serde(flatten)]
+    pub options: SourceOptions,
+}
+
+impl DataSource for AppDescConfig {
+    fn name(&self) -> &str {
+        "App description"
+    }
+
+    fn order_no(&self) -> u8 {
+        self.order_no
+    }
+
+    fn options(&self) -> &SourceOptions {
+        &self.options
+    }
+
+    fn fetch_data<'a>(&'a self, _: &'a AppContext) -> LocalBoxFuture<'a, Result<Vec<PromptData>, DataSourceError>> {
+        Box::pin(async move { Ok(vec![fetch_data(self)]) })
+    }
+}
 
 pub fn fetch_data(config: &AppDescConfig) -> PromptData {
     PromptData {
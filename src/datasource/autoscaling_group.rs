@@ -0,0 +1,335 @@
+use crate::datasource::ds::{DataSource, DataSourceError, SourceOptions};
+use crate::lib::aws::{fetch_pages_until, millis};
+use crate::lib::context::{AppContext, DateTimeRange};
+use crate::lib::prompt::PromptData;
+use aws_sdk_autoscaling::operation::describe_auto_scaling_groups::DescribeAutoScalingGroupsOutput;
+use aws_sdk_autoscaling::operation::describe_scaling_activities::DescribeScalingActivitiesOutput;
+use aws_sdk_autoscaling::types::{Activity, AutoScalingGroup, LaunchTemplateSpecification};
+use aws_sdk_autoscaling::Client;
+use aws_smithy_types::DateTime;
+use csv::Writer;
+use futures::future::LocalBoxFuture;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+#[derive(Deserialize, Debug, Default)]
+pub struct AutoScalingGroupConfig {
+    pub order_no: u8,
+    pub auto_scaling_group_name: String,
+    #[serde(flatten)]
+    pub options: SourceOptions,
+}
+
+impl DataSource for AutoScalingGroupConfig {
+    fn name(&self) -> &str {
+        "Auto Scaling group"
+    }
+
+    fn order_no(&self) -> u8 {
+        self.order_no
+    }
+
+    fn options(&self) -> &SourceOptions {
+        &self.options
+    }
+
+    fn resource_dimensions<'a>(&'a self, _: &'a AppContext) -> LocalBoxFuture<'a, Result<Vec<(String, String)>, DataSourceError>> {
+        Box::pin(async move { Ok(vec![("AutoScalingGroupName".to_string(), self.auto_scaling_group_name.clone())]) })
+    }
+
+    fn fetch_data<'a>(&'a self, context: &'a AppContext) -> LocalBoxFuture<'a, Result<Vec<PromptData>, DataSourceError>> {
+        Box::pin(async move {
+            let client = context.aws.autoscaling(&self.options.scope()).await;
+            Ok(vec![fetch_data(&client, self, &context.range).await?])
+        })
+    }
+}
+
+pub trait AutoScalingClient {
+    async fn describe_auto_scaling_groups(&self, name: &str) -> Result<DescribeAutoScalingGroupsOutput, Box<dyn Error>>;
+
+    async fn describe_scaling_activities(&self, name: &str, next_token: Option<String>) -> Result<DescribeScalingActivitiesOutput, Box<dyn Error>>;
+}
+
+impl AutoScalingClient for Client {
+    async fn describe_auto_scaling_groups(&self, name: &str) -> Result<DescribeAutoScalingGroupsOutput, Box<dyn Error>> {
+        Ok(self.describe_auto_scaling_groups()
+            .auto_scaling_group_names(name)
+            .send()
+            .await?)
+    }
+
+    async fn describe_scaling_activities(&self, name: &str, next_token: Option<String>) -> Result<DescribeScalingActivitiesOutput, Box<dyn Error>> {
+        Ok(self.describe_scaling_activities()
+            .auto_scaling_group_name(name)
+            .set_next_token(next_token)
+            .send()
+            .await?)
+    }
+}
+
+pub async fn fetch_data(client: &impl AutoScalingClient, config: &AutoScalingGroupConfig, range: &DateTimeRange) -> Result<PromptData, Box<dyn Error>> {
+    let group = client.describe_auto_scaling_groups(&config.auto_scaling_group_name).await?
+        .auto_scaling_groups
+        .unwrap_or_default()
+        .into_iter()
+        .next()
+        .ok_or(DataSourceError::NotFound { resource: "Auto Scaling group", name: config.auto_scaling_group_name.clone() })?;
+
+    let activities = fetch_activities(client, config, range).await?;
+
+    Ok(PromptData {
+        description: build_description(config, &group),
+        data: Some(format!("Instances:\n{}\nScaling activities:\n{}", extract_instances(&group)?, extract_activities(&activities, range)?)),
+    })
+}
+
+/// Activities started within the range, oldest first
+async fn fetch_activities(client: &impl AutoScalingClient, config: &AutoScalingGroupConfig, range: &DateTimeRange) -> Result<Vec<Activity>, Box<dyn Error>> {
+    // Activities come newest first, older pages are outside the range
+    let reached_start = |pages: &[DescribeScalingActivitiesOutput]| pages.last().is_some_and(|page| {
+        page.activities().iter().any(|activity| millis(activity.start_time()).is_some_and(|millis| millis < range.start_time))
+    });
+    let (pages, _) = fetch_pages_until(
+        |next_token| client.describe_scaling_activities(&config.auto_scaling_group_name, next_token),
+        DescribeScalingActivitiesOutput::next_token,
+        reached_start,
+    ).await?;
+
+    let mut activities: Vec<Activity> = pages.into_iter()
+        .flat_map(|page| page.activities.unwrap_or_default())
+        .filter(|activity| millis(activity.start_time()).is_some_and(|millis| range.start_time <= millis && millis <= range.end_time))
+        .collect();
+    activities.reverse();
+    Ok(activities)
+}
+
+fn describe_launch_template(launch_template: &LaunchTemplateSpecification) -> String {
+    let name = launch_template.launch_template_name().or(launch_template.launch_template_id()).unwrap_or_default();
+    format!("`{name}` version {}", launch_template.version().unwrap_or("$Default"))
+}
+
+fn build_description(config: &AutoScalingGroupConfig, group: &AutoScalingGroup) -> Vec<String> {
+    let mut description = vec![
+        "Information: [Auto Scaling Group]".to_string(),
+        format!("Name: [`{}`]", &config.auto_scaling_group_name),
+        format!(
+            "Capacity: [min {}, max {}, desired {}]",
+            group.min_size().unwrap_or_default(),
+            group.max_size().unwrap_or_default(),
+            group.desired_capacity().unwrap_or_default(),
+        ),
+    ];
+
+    let launch_template = group.launch_template()
+        .or(group.mixed_instances_policy()
+            .and_then(|policy| policy.launch_template())
+            .and_then(|launch_template| launch_template.launch_template_specification()));
+    match (launch_template, group.launch_configuration_name()) {
+        (Some(launch_template), _) => description.push(format!("Launch template: [{}]", describe_launch_template(launch_template))),
+        (None, Some(launch_configuration)) => description.push(format!("Launch configuration: [`{launch_configuration}`]")),
+        (None, None) => {},
+    }
+
+    description.push(format!(
+        "Health check: [{}, grace period {} seconds]",
+        group.health_check_type().unwrap_or_default(),
+        group.health_check_grace_period().unwrap_or_default(),
+    ));
+
+    let mut states: BTreeMap<&str, usize> = BTreeMap::new();
+    for instance in group.instances() {
+        *states.entry(instance.lifecycle_state().map(|state| state.as_str()).unwrap_or_default()).or_default() += 1;
+    }
+    let states = states.iter()
+        .map(|(state, count)| format!("{count} {state}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    description.push(format!("Instances: [{}]", if states.is_empty() { "none" } else { &states }));
+
+    let suspended = group.suspended_processes()
+        .iter()
+        .filter_map(|process| process.process_name())
+        .collect::<Vec<_>>()
+        .join(", ");
+    if !suspended.is_empty() {
+        description.push(format!("Suspended processes: [{suspended}]"));
+    }
+
+    description
+}
+
+fn extract_instances(group: &AutoScalingGroup) -> Result<String, Box<dyn Error>> {
+    if group.instances().is_empty() {
+        return Ok("No instances\n".to_string());
+    }
+
+    let mut csv_writer = Writer::from_writer(Vec::new());
+    csv_writer.write_record(["instance_id", "instance_type", "availability_zone", "lifecycle_state", "health_status", "launch_template_version"])?;
+
+    for instance in group.instances() {
+        csv_writer.write_record([
+            instance.instance_id().unwrap_or_default(),
+            instance.instance_type().unwrap_or_default(),
+            instance.availability_zone().unwrap_or_default(),
+            instance.lifecycle_state().map(|state| state.as_str()).unwrap_or_default(),
+            instance.health_status().unwrap_or_default(),
+            instance.launch_template().and_then(|launch_template| launch_template.version()).unwrap_or_default(),
+        ])?;
+    }
+
+    Ok(String::from_utf8(csv_writer.into_inner()?)?)
+}
+
+/// The cause tells why capacity changed, e.g. an alarm, a health check or a manual change
+fn extract_activities(activities: &[Activity], range: &DateTimeRange) -> Result<String, Box<dyn Error>> {
+    if activities.is_empty() {
+        return Ok("No scaling activities found\n".to_string());
+    }
+
+    let format = |date_time: Option<&DateTime>| millis(date_time).map(|millis| range.format_timestamp(millis)).unwrap_or_default();
+
+    let mut csv_writer = Writer::from_writer(Vec::new());
+    csv_writer.write_record(["start_time", "end_time", "status", "description", "cause", "status_message"])?;
+
+    for activity in activities {
+        csv_writer.write_record([
+            &format(activity.start_time()),
+            &format(activity.end_time()),
+            activity.status_code().map(|status| status.as_str()).unwrap_or_default(),
+            activity.description().unwrap_or_default(),
+            activity.cause().unwrap_or_default(),
+            activity.status_message().unwrap_or_default(),
+        ])?;
+    }
+
+    Ok(String::from_utf8(csv_writer.into_inner()?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_autoscaling::types::{Instance, LifecycleState, ScalingActivityStatusCode, SuspendedProcess};
+    use std::cell::RefCell;
+
+    const MINUTE: i64 = 60 * 1000;
+
+    /// Returns `pages` of activities in order, each with a next token but the last one
+    struct MockAutoScalingClient {
+        pages: RefCell<Vec<Vec<Activity>>>,
+    }
+
+    impl AutoScalingClient for MockAutoScalingClient {
+        async fn describe_auto_scaling_groups(&self, name: &str) -> Result<DescribeAutoScalingGroupsOutput, Box<dyn Error>> {
+            if name != "web-asg" {
+                return Ok(DescribeAutoScalingGroupsOutput::builder().build());
+            }
+
+            let instance = |id: &str, state: LifecycleState, health: &str| Instance::builder()
+                .instance_id(id)
+                .instance_type("t3.medium")
+                .availability_zone("us-east-1a")
+                .lifecycle_state(state)
+                .health_status(health)
+                .launch_template(LaunchTemplateSpecification::builder().launch_template_name("web").version("7").build())
+                .build();
+
+            Ok(DescribeAutoScalingGroupsOutput::builder()
+                .auto_scaling_groups(AutoScalingGroup::builder()
+                    .auto_scaling_group_name(name)
+                    .min_size(2)
+                    .max_size(6)
+                    .desired_capacity(3)
+                    .launch_template(LaunchTemplateSpecification::builder().launch_template_name("web").version("$Latest").build())
+                    .health_check_type("ELB")
+                    .health_check_grace_period(300)
+                    .instances(instance("i-0a1", LifecycleState::InService, "Healthy"))
+                    .instances(instance("i-0b2", LifecycleState::InService, "Healthy"))
+                    .instances(instance("i-0c3", LifecycleState::Pending, "Healthy"))
+                    .suspended_processes(SuspendedProcess::builder().process_name("AZRebalance").build())
+                    .build())
+                .build())
+        }
+
+        async fn describe_scaling_activities(&self, _: &str, _: Option<String>) -> Result<DescribeScalingActivitiesOutput, Box<dyn Error>> {
+            let mut pages = self.pages.borrow_mut();
+            let page = pages.remove(0);
+
+            Ok(DescribeScalingActivitiesOutput::builder()
+                .set_activities(Some(page))
+                .set_next_token(if pages.is_empty() { None } else { Some("next".to_string()) })
+                .build())
+        }
+    }
+
+    fn activity(start_time: i64, description: &str, cause: &str) -> Activity {
+        Activity::builder()
+            .start_time(DateTime::from_millis(start_time))
+            .end_time(DateTime::from_millis(start_time + MINUTE))
+            .status_code(ScalingActivityStatusCode::Successful)
+            .description(description)
+            .cause(cause)
+            .build()
+    }
+
+    fn config() -> AutoScalingGroupConfig {
+        AutoScalingGroupConfig {
+            order_no: 1,
+            auto_scaling_group_name: "web-asg".to_string(),
+            ..AutoScalingGroupConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data() {
+        let client = MockAutoScalingClient {
+            pages: RefCell::new(vec![
+                vec![activity(8 * MINUTE, "Launching a new EC2 instance: i-0c3", "an alarm changed the desired capacity from 2 to 3")],
+                vec![activity(2 * MINUTE, "Terminating EC2 instance: i-0z9", "an instance was taken out of service in response to an ELB system health check failure"), activity(-MINUTE, "Outside", "Outside")],
+                vec![activity(-2 * MINUTE, "Never fetched", "Never fetched")],
+            ]),
+        };
+        let range = DateTimeRange { start_time: 0, end_time: 10 * MINUTE, ..DateTimeRange::default() };
+
+        let prompt_data = fetch_data(&client, &config(), &range).await.expect("Should fetch data");
+
+        let expected = [
+            "Instances:\n",
+            "instance_id,instance_type,availability_zone,lifecycle_state,health_status,launch_template_version\n",
+            "i-0a1,t3.medium,us-east-1a,InService,Healthy,7\n",
+            "i-0b2,t3.medium,us-east-1a,InService,Healthy,7\n",
+            "i-0c3,t3.medium,us-east-1a,Pending,Healthy,7\n",
+            "\nScaling activities:\n",
+            "start_time,end_time,status,description,cause,status_message\n",
+            "1970-01-01 00:02:00 UTC,1970-01-01 00:03:00 UTC,Successful,Terminating EC2 instance: i-0z9,an instance was taken out of service in response to an ELB system health check failure,\n",
+            "1970-01-01 00:08:00 UTC,1970-01-01 00:09:00 UTC,Successful,Launching a new EC2 instance: i-0c3,an alarm changed the desired capacity from 2 to 3,\n",
+        ].join("");
+
+        assert_eq!(prompt_data.description, vec![
+            "Information: [Auto Scaling Group]",
+            "Name: [`web-asg`]",
+            "Capacity: [min 2, max 6, desired 3]",
+            "Launch template: [`web` version $Latest]",
+            "Health check: [ELB, grace period 300 seconds]",
+            "Instances: [2 InService, 1 Pending]",
+            "Suspended processes: [AZRebalance]",
+        ]);
+        assert_eq!(prompt_data.data, Some(expected));
+        assert_eq!(client.pages.borrow().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_not_found() {
+        let client = MockAutoScalingClient { pages: RefCell::new(vec![]) };
+        let config = AutoScalingGroupConfig {
+            auto_scaling_group_name: "unknown".to_string(),
+            ..AutoScalingGroupConfig::default()
+        };
+        let range = DateTimeRange::default();
+
+        let err = fetch_data(&client, &config, &range).await.expect_err("Should not find group");
+
+        assert_eq!(err.to_string(), "Unable to find Auto Scaling group with name: unknown");
+    }
+}
@@ -0,0 +1,637 @@
+use crate::datasource::ds::{DataSource, DataSourceError, SourceOptions};
+use crate::lib::aws::{fetch_all_pages, fetch_pages_until, millis};
+use crate::lib::context::{AppContext, DateTimeRange};
+use crate::lib::prompt::PromptData;
+use aws_sdk_cloudwatch::operation::describe_alarm_history::DescribeAlarmHistoryOutput;
+use aws_sdk_cloudwatch::operation::describe_alarms::DescribeAlarmsOutput;
+use aws_sdk_cloudwatch::types::{AlarmHistoryItem, AlarmType, CompositeAlarm, Dimension, HistoryItemType, MetricAlarm, StateValue};
+use aws_sdk_cloudwatch::Client;
+use aws_smithy_types::DateTime;
+use csv::Writer;
+use futures::future::{join_all, LocalBoxFuture};
+use futures::{stream, StreamExt};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+
+const DEFAULT_MAX_TRANSITIONS: usize = 20;
+
+/// DescribeAlarmHistory returns at most 100 items per page
+const MAX_RECORDS_PER_PAGE: usize = 100;
+
+#[derive(Deserialize, Debug, Default)]
+pub struct CloudwatchAlarmConfig {
+    pub order_no: u8,
+    pub alarm_name_prefix: Option<String>,
+    /// Only the alarms watching the resources of the other data sources, e.g. the RDS instance or the Lambda function
+    #[serde(default)]
+    pub match_resources: bool,
+    /// Per alarm, the latest ones are kept
+    pub max_transitions: Option<usize>,
+    #[serde(flatten)]
+    pub options: SourceOptions,
+}
+
+impl DataSource for CloudwatchAlarmConfig {
+    fn name(&self) -> &str {
+        "Cloudwatch alarms"
+    }
+
+    fn order_no(&self) -> u8 {
+        self.order_no
+    }
+
+    fn options(&self) -> &SourceOptions {
+        &self.options
+    }
+
+    fn fetch_data<'a>(&'a self, context: &'a AppContext) -> LocalBoxFuture<'a, Result<Vec<PromptData>, DataSourceError>> {
+        Box::pin(async move {
+            let mut resources: Vec<(String, String)> = Vec::new();
+            if self.match_resources {
+                // A source unable to resolve its resources reports the failure itself
+                let dimensions = join_all(context.data_sources.iter().map(|data_source| data_source.resource_dimensions(context))).await;
+                resources = dimensions.into_iter().flat_map(Result::unwrap_or_default).collect();
+                resources.sort();
+                resources.dedup();
+            }
+
+            let client = context.aws.cloudwatch(&self.options.scope()).await;
+            Ok(vec![fetch_data(&client, self, &resources, &context.range, context.concurrency).await?])
+        })
+    }
+}
+
+pub trait CloudwatchAlarmClient {
+    async fn describe_alarms(&self, alarm_name_prefix: Option<&str>, next_token: Option<String>) -> Result<DescribeAlarmsOutput, Box<dyn Error>>;
+
+    async fn describe_alarm_history(&self, alarm_name: &str, alarm_type: AlarmType, range: &DateTimeRange, max_records: usize, next_token: Option<String>) -> Result<DescribeAlarmHistoryOutput, Box<dyn Error>>;
+}
+
+impl CloudwatchAlarmClient for Client {
+    async fn describe_alarms(&self, alarm_name_prefix: Option<&str>, next_token: Option<String>) -> Result<DescribeAlarmsOutput, Box<dyn Error>> {
+        Ok(self.describe_alarms()
+            .set_alarm_name_prefix(alarm_name_prefix.map(str::to_string))
+            .alarm_types(AlarmType::MetricAlarm)
+            .alarm_types(AlarmType::CompositeAlarm)
+            .set_next_token(next_token)
+            .send()
+            .await?)
+    }
+
+    async fn describe_alarm_history(&self, alarm_name: &str, alarm_type: AlarmType, range: &DateTimeRange, max_records: usize, next_token: Option<String>) -> Result<DescribeAlarmHistoryOutput, Box<dyn Error>> {
+        Ok(self.describe_alarm_history()
+            .alarm_name(alarm_name)
+            // Without it only the history of metric alarms is returned
+            .alarm_types(alarm_type)
+            .history_item_type(HistoryItemType::StateUpdate)
+            .start_date(DateTime::from_millis(range.start_time))
+            .end_date(DateTime::from_millis(range.end_time))
+            .max_records(max_records as i32)
+            .set_next_token(next_token)
+            .send()
+            .await?)
+    }
+}
+
+/// Metric alarms watch a metric, composite alarms combine the states of other alarms with a rule
+enum Alarm {
+    Metric(Box<MetricAlarm>),
+    Composite(Box<CompositeAlarm>),
+}
+
+impl Alarm {
+    fn name(&self) -> &str {
+        match self {
+            Alarm::Metric(alarm) => alarm.alarm_name(),
+            Alarm::Composite(alarm) => alarm.alarm_name(),
+        }.unwrap_or_default()
+    }
+
+    fn alarm_type(&self) -> AlarmType {
+        match self {
+            Alarm::Metric(_) => AlarmType::MetricAlarm,
+            Alarm::Composite(_) => AlarmType::CompositeAlarm,
+        }
+    }
+
+    fn state(&self) -> Option<&StateValue> {
+        match self {
+            Alarm::Metric(alarm) => alarm.state_value(),
+            Alarm::Composite(alarm) => alarm.state_value(),
+        }
+    }
+
+    fn state_updated(&self) -> Option<&DateTime> {
+        match self {
+            Alarm::Metric(alarm) => alarm.state_updated_timestamp(),
+            Alarm::Composite(alarm) => alarm.state_updated_timestamp(),
+        }
+    }
+
+    fn state_reason(&self) -> &str {
+        match self {
+            Alarm::Metric(alarm) => alarm.state_reason(),
+            Alarm::Composite(alarm) => alarm.state_reason(),
+        }.unwrap_or_default()
+    }
+}
+
+pub async fn fetch_data(client: &impl CloudwatchAlarmClient, config: &CloudwatchAlarmConfig, resources: &[(String, String)], range: &DateTimeRange, concurrency: usize) -> Result<PromptData, Box<dyn Error>> {
+    if config.alarm_name_prefix.is_none() && !config.match_resources {
+        return Err(DataSourceError::InvalidConfig("alarm_name_prefix or match_resources must be provided".to_string()).into());
+    }
+
+    let mut alarms = fetch_alarms(client, config).await?;
+    if config.match_resources {
+        retain_matching(&mut alarms, resources);
+    }
+
+    let mut description = build_description(config, resources, &alarms);
+    if alarms.is_empty() {
+        return Ok(PromptData { description, data: Some("No matching alarms found\n".to_string()) });
+    }
+
+    let max_transitions = config.max_transitions.unwrap_or(DEFAULT_MAX_TRANSITIONS);
+    let fetched: Vec<_> = stream::iter(&alarms)
+        .map(|alarm| fetch_transitions(client, alarm, range, max_transitions))
+        .buffered(concurrency.max(1))
+        .collect()
+        .await;
+
+    let mut transitions = Vec::new();
+    let mut truncated = false;
+    for result in fetched {
+        let (items, more) = result?;
+        transitions.extend(items);
+        truncated |= more;
+    }
+
+    if truncated {
+        description.push(format!("Note: [Only the latest {max_transitions} state transitions of each alarm are included]"));
+    }
+
+    transitions.sort_by_key(|item| millis(item.timestamp()));
+
+    Ok(PromptData {
+        description,
+        data: Some(format!("Alarms:\n{}\nState transitions:\n{}", extract_alarms(&alarms, range)?, extract_transitions(&transitions, range)?)),
+    })
+}
+
+/// Without a prefix every alarm is paged through, `match_resources` then keeps those watching the resources
+async fn fetch_alarms(client: &impl CloudwatchAlarmClient, config: &CloudwatchAlarmConfig) -> Result<Vec<Alarm>, Box<dyn Error>> {
+    let pages = fetch_all_pages(|next_token| client.describe_alarms(config.alarm_name_prefix.as_deref(), next_token), DescribeAlarmsOutput::next_token).await?;
+
+    let mut alarms = Vec::new();
+    let mut composite_alarms = Vec::new();
+    for page in pages {
+        alarms.extend(page.metric_alarms.unwrap_or_default().into_iter().map(|alarm| Alarm::Metric(Box::new(alarm))));
+        composite_alarms.extend(page.composite_alarms.unwrap_or_default().into_iter().map(|alarm| Alarm::Composite(Box::new(alarm))));
+    }
+
+    alarms.append(&mut composite_alarms);
+    Ok(alarms)
+}
+
+/// History items come newest first, returns whether older ones were left out
+async fn fetch_transitions(client: &impl CloudwatchAlarmClient, alarm: &Alarm, range: &DateTimeRange, max_transitions: usize) -> Result<(Vec<AlarmHistoryItem>, bool), Box<dyn Error>> {
+    let max_records = max_transitions.clamp(1, MAX_RECORDS_PER_PAGE);
+    let (pages, left) = fetch_pages_until(
+        |next_token| client.describe_alarm_history(alarm.name(), alarm.alarm_type(), range, max_records, next_token),
+        DescribeAlarmHistoryOutput::next_token,
+        |pages| pages.iter().map(|page| page.alarm_history_items().len()).sum::<usize>() >= max_transitions,
+    ).await?;
+
+    let mut items: Vec<AlarmHistoryItem> = pages.into_iter().flat_map(|page| page.alarm_history_items.unwrap_or_default()).collect();
+    let truncated = left || items.len() > max_transitions;
+    items.truncate(max_transitions);
+    Ok((items, truncated))
+}
+
+/// Keeps the metric alarms watching one of the resources, and the composite alarms built on the kept alarms
+fn retain_matching(alarms: &mut Vec<Alarm>, resources: &[(String, String)]) {
+    let mut matched: HashSet<&str> = alarms.iter()
+        .filter(|alarm| matches!(alarm, Alarm::Metric(alarm) if watched_dimensions(alarm).any(|dimension| matches_resource(dimension, resources))))
+        .map(Alarm::name)
+        .collect();
+
+    // A composite alarm can be built on another composite alarm
+    loop {
+        let composed: Vec<&str> = alarms.iter()
+            .filter_map(|alarm| match alarm {
+                Alarm::Composite(composite) if !matched.contains(alarm.name()) => Some((alarm.name(), composite.alarm_rule().unwrap_or_default())),
+                _ => None,
+            })
+            .filter(|(_, rule)| rule_alarm_names(rule).any(|name| matched.contains(name)))
+            .map(|(name, _)| name)
+            .collect();
+        if composed.is_empty() {
+            break;
+        }
+        matched.extend(composed);
+    }
+
+    let matched: HashSet<String> = matched.into_iter().map(str::to_string).collect();
+    alarms.retain(|alarm| matched.contains(alarm.name()));
+}
+
+/// The alarms a composite alarm rule refers to, by name or ARN, e.g. `ALARM("db-cpu") OR OK(arn:aws:cloudwatch:eu-west-1:123456789012:alarm:web-5xx)`
+fn rule_alarm_names(rule: &str) -> impl Iterator<Item = &str> {
+    rule.split(['(', ')'])
+        .map(|part| part.trim().trim_matches('"'))
+        .map(|part| part.rsplit_once(":alarm:").map_or(part, |(_, name)| name))
+}
+
+/// The dimensions of the alarm's metric, or of the metrics used by its metric math
+fn watched_dimensions(alarm: &MetricAlarm) -> impl Iterator<Item = &Dimension> {
+    alarm.dimensions()
+        .iter()
+        .chain(alarm.metrics()
+            .iter()
+            .filter_map(|query| query.metric_stat())
+            .filter_map(|metric_stat| metric_stat.metric())
+            .flat_map(|metric| metric.dimensions()))
+}
+
+fn matches_resource(dimension: &Dimension, resources: &[(String, String)]) -> bool {
+    let (Some(name), Some(value)) = (dimension.name(), dimension.value()) else {
+        return false;
+    };
+
+    resources.iter().any(|(resource_name, resource_value)| resource_name == name && resource_value == value)
+}
+
+fn build_description(config: &CloudwatchAlarmConfig, resources: &[(String, String)], alarms: &[Alarm]) -> Vec<String> {
+    let mut description = vec!["Information: [Cloudwatch Alarms]".to_string()];
+
+    if let Some(alarm_name_prefix) = &config.alarm_name_prefix {
+        description.push(format!("Alarm name prefix: [`{alarm_name_prefix}`]"));
+    }
+
+    if config.match_resources {
+        let resources = resources.iter()
+            .map(|(name, value)| format!("`{name}:{value}`"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        description.push(format!("Resources: [{resources}]"));
+    }
+
+    let mut states: BTreeMap<&str, usize> = BTreeMap::new();
+    for alarm in alarms {
+        *states.entry(alarm.state().map(|state| state.as_str()).unwrap_or_default()).or_default() += 1;
+    }
+    if !states.is_empty() {
+        let states = states.iter()
+            .map(|(state, count)| format!("{count} {state}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        description.push(format!("Current states: [{states}]"));
+    }
+
+    description
+}
+
+/// The threshold definition, e.g. `p99 > 2 for 3 of 5 datapoints of 60s`
+fn format_condition(alarm: &MetricAlarm) -> String {
+    let comparison = match alarm.comparison_operator().map(|operator| operator.as_str()) {
+        Some("GreaterThanOrEqualToThreshold") => ">=",
+        Some("GreaterThanThreshold") => ">",
+        Some("LessThanThreshold") => "<",
+        Some("LessThanOrEqualToThreshold") => "<=",
+        Some("LessThanLowerOrGreaterThanUpperThreshold") => "outside band",
+        Some("LessThanLowerThreshold") => "below band",
+        Some("GreaterThanUpperThreshold") => "above band",
+        Some(operator) => operator,
+        None => "",
+    };
+
+    // Anomaly detection alarms compare with the band of another metric rather than a threshold
+    let threshold = match (alarm.threshold_metric_id(), alarm.threshold()) {
+        (Some(threshold_metric_id), _) => format!("`{threshold_metric_id}`"),
+        (None, Some(threshold)) => threshold.to_string(),
+        (None, None) => String::new(),
+    };
+
+    let statistic = alarm.extended_statistic().or(alarm.statistic().map(|statistic| statistic.as_str()));
+    let mut condition = [statistic.unwrap_or_default(), comparison, &threshold]
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let evaluation_periods = alarm.evaluation_periods().unwrap_or_default();
+    let datapoints_to_alarm = alarm.datapoints_to_alarm().unwrap_or(evaluation_periods);
+    condition.push_str(&format!(" for {datapoints_to_alarm} of {evaluation_periods} datapoints"));
+    if let Some(period) = alarm.period() {
+        condition.push_str(&format!(" of {period}s"));
+    }
+
+    condition
+}
+
+fn format_metric(alarm: &MetricAlarm) -> String {
+    match (alarm.namespace(), alarm.metric_name()) {
+        (Some(namespace), Some(metric_name)) => format!("{namespace} {metric_name}"),
+        _ => alarm.metrics()
+            .iter()
+            .find(|query| query.return_data() != Some(false) && query.expression().is_some())
+            .and_then(|query| query.expression())
+            .map(|expression| format!("expression `{expression}`"))
+            .unwrap_or_default(),
+    }
+}
+
+/// A composite alarm has its rule as condition
+fn extract_alarms(alarms: &[Alarm], range: &DateTimeRange) -> Result<String, Box<dyn Error>> {
+    let mut csv_writer = Writer::from_writer(Vec::new());
+    csv_writer.write_record(["alarm_name", "state", "state_updated", "metric", "dimensions", "condition", "treat_missing_data", "state_reason"])?;
+
+    for alarm in alarms {
+        let (metric, dimensions, condition, treat_missing_data) = match alarm {
+            Alarm::Metric(alarm) => {
+                let dimensions = alarm.dimensions()
+                    .iter()
+                    .map(|dimension| format!("{}={}", dimension.name().unwrap_or_default(), dimension.value().unwrap_or_default()))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                (format_metric(alarm), dimensions, format_condition(alarm), alarm.treat_missing_data().unwrap_or_default())
+            }
+            Alarm::Composite(alarm) => ("composite".to_string(), String::new(), alarm.alarm_rule().unwrap_or_default().to_string(), ""),
+        };
+
+        csv_writer.write_record([
+            alarm.name(),
+            alarm.state().map(|state| state.as_str()).unwrap_or_default(),
+            &millis(alarm.state_updated()).map(|millis| range.format_timestamp(millis)).unwrap_or_default(),
+            &metric,
+            &dimensions,
+            &condition,
+            treat_missing_data,
+            alarm.state_reason(),
+        ])?;
+    }
+
+    Ok(String::from_utf8(csv_writer.into_inner()?)?)
+}
+
+fn extract_transitions(transitions: &[AlarmHistoryItem], range: &DateTimeRange) -> Result<String, Box<dyn Error>> {
+    if transitions.is_empty() {
+        return Ok("No state transitions within the time range\n".to_string());
+    }
+
+    let mut csv_writer = Writer::from_writer(Vec::new());
+    csv_writer.write_record(["timestamp", "alarm_name", "summary"])?;
+
+    for item in transitions {
+        csv_writer.write_record([
+            &millis(item.timestamp()).map(|millis| range.format_timestamp(millis)).unwrap_or_default(),
+            item.alarm_name().unwrap_or_default(),
+            item.history_summary().unwrap_or_default(),
+        ])?;
+    }
+
+    Ok(String::from_utf8(csv_writer.into_inner()?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_cloudwatch::types::{ComparisonOperator, Metric, MetricDataQuery, MetricStat, Statistic};
+    use std::cell::RefCell;
+
+    const MINUTE: i64 = 60 * 1000;
+
+    type Pages = Vec<Vec<(i64, &'static str)>>;
+
+    /// Returns `transitions` per alarm name, one page at a time
+    struct MockCloudwatchAlarmClient {
+        alarms: Vec<MetricAlarm>,
+        composite_alarms: Vec<CompositeAlarm>,
+        transitions: RefCell<BTreeMap<&'static str, Pages>>,
+        prefixes: RefCell<Vec<Option<String>>>,
+        history_types: RefCell<Vec<(String, AlarmType)>>,
+    }
+
+    impl MockCloudwatchAlarmClient {
+        fn new(transitions: BTreeMap<&'static str, Pages>) -> Self {
+            MockCloudwatchAlarmClient {
+                alarms: vec![
+                    alarm("prod-db-main-cpu", "DBInstanceIdentifier", "db-main", StateValue::Alarm)
+                        .namespace("AWS/RDS")
+                        .metric_name("CPUUtilization")
+                        .statistic(Statistic::Average)
+                        .comparison_operator(ComparisonOperator::GreaterThanOrEqualToThreshold)
+                        .threshold(80.0)
+                        .build(),
+                    alarm("prod-web-lb-5xx", "LoadBalancer", "app/web-lb/50dc6c495c0c9188", StateValue::Ok)
+                        .namespace("AWS/ApplicationELB")
+                        .metric_name("HTTPCode_Target_5XX_Count")
+                        .statistic(Statistic::Sum)
+                        .comparison_operator(ComparisonOperator::GreaterThanThreshold)
+                        .threshold(10.0)
+                        .datapoints_to_alarm(2)
+                        .treat_missing_data("notBreaching")
+                        .build(),
+                    alarm("prod-other-errors", "FunctionName", "other", StateValue::Ok).build(),
+                ],
+                composite_alarms: vec![
+                    composite_alarm("prod-web-health", "ALARM(prod-other-errors) OR ALARM(arn:aws:cloudwatch:eu-west-1:123456789012:alarm:prod-web-lb-5xx)"),
+                    composite_alarm("prod-service-health", "ALARM(\"prod-web-health\")"),
+                    composite_alarm("prod-other-health", "ALARM(prod-other-errors)"),
+                ],
+                transitions: RefCell::new(transitions),
+                prefixes: RefCell::new(Vec::new()),
+                history_types: RefCell::new(Vec::new()),
+            }
+        }
+    }
+
+    impl CloudwatchAlarmClient for MockCloudwatchAlarmClient {
+        async fn describe_alarms(&self, alarm_name_prefix: Option<&str>, _: Option<String>) -> Result<DescribeAlarmsOutput, Box<dyn Error>> {
+            self.prefixes.borrow_mut().push(alarm_name_prefix.map(str::to_string));
+            let alarm_name_prefix = alarm_name_prefix.unwrap_or_default();
+            let alarms = self.alarms.iter()
+                .filter(|alarm| alarm.alarm_name().unwrap_or_default().starts_with(alarm_name_prefix))
+                .cloned()
+                .collect();
+            let composite_alarms = self.composite_alarms.iter()
+                .filter(|alarm| alarm.alarm_name().unwrap_or_default().starts_with(alarm_name_prefix))
+                .cloned()
+                .collect();
+
+            Ok(DescribeAlarmsOutput::builder()
+                .set_metric_alarms(Some(alarms))
+                .set_composite_alarms(Some(composite_alarms))
+                .build())
+        }
+
+        async fn describe_alarm_history(&self, alarm_name: &str, alarm_type: AlarmType, _: &DateTimeRange, _: usize, _: Option<String>) -> Result<DescribeAlarmHistoryOutput, Box<dyn Error>> {
+            self.history_types.borrow_mut().push((alarm_name.to_string(), alarm_type));
+            let mut transitions = self.transitions.borrow_mut();
+            let pages = transitions.get_mut(alarm_name).filter(|pages| !pages.is_empty());
+            let page = pages.map(|pages| pages.remove(0)).unwrap_or_default();
+            let has_more = transitions.get(alarm_name).is_some_and(|pages| !pages.is_empty());
+
+            let mut output = DescribeAlarmHistoryOutput::builder();
+            for (timestamp, summary) in page {
+                output = output.alarm_history_items(AlarmHistoryItem::builder()
+                    .alarm_name(alarm_name)
+                    .timestamp(DateTime::from_millis(timestamp))
+                    .history_item_type(HistoryItemType::StateUpdate)
+                    .history_summary(summary)
+                    .build());
+            }
+            if has_more {
+                output = output.next_token("next");
+            }
+
+            Ok(output.build())
+        }
+    }
+
+    fn alarm(name: &str, dimension_name: &str, dimension_value: &str, state: StateValue) -> aws_sdk_cloudwatch::types::builders::MetricAlarmBuilder {
+        MetricAlarm::builder()
+            .alarm_name(name)
+            .state_value(state)
+            .state_reason("Threshold Crossed")
+            .state_updated_timestamp(DateTime::from_millis(5 * MINUTE))
+            .dimensions(Dimension::builder().name(dimension_name).value(dimension_value).build())
+            .period(300)
+            .evaluation_periods(3)
+    }
+
+    fn composite_alarm(name: &str, rule: &str) -> CompositeAlarm {
+        CompositeAlarm::builder()
+            .alarm_name(name)
+            .alarm_rule(rule)
+            .state_value(StateValue::Ok)
+            .state_reason("All alarms OK")
+            .state_updated_timestamp(DateTime::from_millis(6 * MINUTE))
+            .build()
+    }
+
+    fn resources() -> Vec<(String, String)> {
+        vec![
+            ("DBInstanceIdentifier".to_string(), "db-main".to_string()),
+            ("LoadBalancer".to_string(), "app/web-lb/50dc6c495c0c9188".to_string()),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_match_resources() {
+        let client = MockCloudwatchAlarmClient::new(BTreeMap::from([
+            ("prod-db-main-cpu", vec![vec![(5 * MINUTE, "Alarm updated from OK to ALARM")]]),
+            ("prod-web-lb-5xx", vec![vec![(7 * MINUTE, "Alarm updated from ALARM to OK"), (2 * MINUTE, "Alarm updated from OK to ALARM")]]),
+            ("prod-web-health", vec![vec![(8 * MINUTE, "Alarm updated from ALARM to OK")]]),
+        ]));
+        let config = CloudwatchAlarmConfig { match_resources: true, ..CloudwatchAlarmConfig::default() };
+        let range = DateTimeRange::default();
+
+        let prompt_data = fetch_data(&client, &config, &resources(), &range, 2).await.expect("Should fetch data");
+
+        let expected = [
+            "Alarms:\n",
+            "alarm_name,state,state_updated,metric,dimensions,condition,treat_missing_data,state_reason\n",
+            "prod-db-main-cpu,ALARM,1970-01-01 00:05:00 UTC,AWS/RDS CPUUtilization,DBInstanceIdentifier=db-main,Average >= 80 for 3 of 3 datapoints of 300s,,Threshold Crossed\n",
+            "prod-web-lb-5xx,OK,1970-01-01 00:05:00 UTC,AWS/ApplicationELB HTTPCode_Target_5XX_Count,LoadBalancer=app/web-lb/50dc6c495c0c9188,Sum > 10 for 2 of 3 datapoints of 300s,notBreaching,Threshold Crossed\n",
+            "prod-web-health,OK,1970-01-01 00:06:00 UTC,composite,,ALARM(prod-other-errors) OR ALARM(arn:aws:cloudwatch:eu-west-1:123456789012:alarm:prod-web-lb-5xx),,All alarms OK\n",
+            "prod-service-health,OK,1970-01-01 00:06:00 UTC,composite,,\"ALARM(\"\"prod-web-health\"\")\",,All alarms OK\n",
+            "\nState transitions:\n",
+            "timestamp,alarm_name,summary\n",
+            "1970-01-01 00:02:00 UTC,prod-web-lb-5xx,Alarm updated from OK to ALARM\n",
+            "1970-01-01 00:05:00 UTC,prod-db-main-cpu,Alarm updated from OK to ALARM\n",
+            "1970-01-01 00:07:00 UTC,prod-web-lb-5xx,Alarm updated from ALARM to OK\n",
+            "1970-01-01 00:08:00 UTC,prod-web-health,Alarm updated from ALARM to OK\n",
+        ].join("");
+
+        assert_eq!(prompt_data.description, vec![
+            "Information: [Cloudwatch Alarms]",
+            "Resources: [`DBInstanceIdentifier:db-main`, `LoadBalancer:app/web-lb/50dc6c495c0c9188`]",
+            "Current states: [1 ALARM, 3 OK]",
+        ]);
+        assert_eq!(prompt_data.data, Some(expected));
+        assert_eq!(*client.prefixes.borrow(), vec![None]);
+        assert_eq!(*client.history_types.borrow(), vec![
+            ("prod-db-main-cpu".to_string(), AlarmType::MetricAlarm),
+            ("prod-web-lb-5xx".to_string(), AlarmType::MetricAlarm),
+            ("prod-web-health".to_string(), AlarmType::CompositeAlarm),
+            ("prod-service-health".to_string(), AlarmType::CompositeAlarm),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_prefix_max_transitions() {
+        let client = MockCloudwatchAlarmClient::new(BTreeMap::from([
+            ("prod-db-main-cpu", vec![vec![(5 * MINUTE, "Alarm updated from OK to ALARM")], vec![(MINUTE, "Alarm updated from ALARM to OK")]]),
+        ]));
+        let config = CloudwatchAlarmConfig {
+            alarm_name_prefix: Some("prod-db-".to_string()),
+            max_transitions: Some(1),
+            ..CloudwatchAlarmConfig::default()
+        };
+        let range = DateTimeRange::default();
+
+        let prompt_data = fetch_data(&client, &config, &[], &range, 2).await.expect("Should fetch data");
+
+        assert_eq!(*client.prefixes.borrow(), vec![Some("prod-db-".to_string())]);
+        assert_eq!(prompt_data.description, vec![
+            "Information: [Cloudwatch Alarms]",
+            "Alarm name prefix: [`prod-db-`]",
+            "Current states: [1 ALARM]",
+            "Note: [Only the latest 1 state transitions of each alarm are included]",
+        ]);
+        assert!(prompt_data.data.unwrap().ends_with("timestamp,alarm_name,summary\n1970-01-01 00:05:00 UTC,prod-db-main-cpu,Alarm updated from OK to ALARM\n"));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_no_alarms() {
+        let client = MockCloudwatchAlarmClient::new(BTreeMap::new());
+        let config = CloudwatchAlarmConfig { alarm_name_prefix: Some("unknown".to_string()), ..CloudwatchAlarmConfig::default() };
+        let range = DateTimeRange::default();
+
+        let prompt_data = fetch_data(&client, &config, &[], &range, 2).await.expect("Should fetch data");
+
+        assert_eq!(prompt_data.data, Some("No matching alarms found\n".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_invalid_config() {
+        let client = MockCloudwatchAlarmClient::new(BTreeMap::new());
+        let range = DateTimeRange::default();
+
+        let err = fetch_data(&client, &CloudwatchAlarmConfig::default(), &[], &range, 2).await.expect_err("Should reject config");
+
+        assert_eq!(err.to_string(), "Invalid configuration: alarm_name_prefix or match_resources must be provided");
+        assert!(client.prefixes.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_format_condition() {
+        let metric_math = MetricAlarm::builder()
+            .comparison_operator(ComparisonOperator::LessThanLowerOrGreaterThanUpperThreshold)
+            .threshold_metric_id("ad1")
+            .evaluation_periods(2)
+            .metrics(MetricDataQuery::builder()
+                .id("m1")
+                .metric_stat(MetricStat::builder()
+                    .metric(Metric::builder().dimensions(Dimension::builder().name("FunctionName").value("checkout").build()).build())
+                    .build())
+                .build())
+            .build();
+
+        assert_eq!(format_condition(&metric_math), "outside band `ad1` for 2 of 2 datapoints");
+        assert!(watched_dimensions(&metric_math).any(|dimension| matches_resource(dimension, &[("FunctionName".to_string(), "checkout".to_string())])));
+    }
+
+    #[test]
+    fn test_rule_alarm_names() {
+        let rule = "ALARM(\"db-cpu\") AND NOT OK(arn:aws:cloudwatch:eu-west-1:123456789012:alarm:web-5xx)";
+
+        let names: Vec<&str> = rule_alarm_names(rule).collect();
+
+        assert!(names.contains(&"db-cpu"));
+        assert!(names.contains(&"web-5xx"));
+        assert!(!names.contains(&"db"));
+    }
+}
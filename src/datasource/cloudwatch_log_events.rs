@@ -0,0 +1,370 @@
+use crate::datasource::ds::{DataSource, DataSourceError, SourceOptions};
+use crate::lib::context::{AppContext, DateTimeRange};
+use crate::lib::prompt::PromptData;
+use aws_sdk_cloudwatchlogs::operation::filter_log_events::FilterLogEventsOutput;
+use aws_sdk_cloudwatchlogs::types::FilteredLogEvent;
+use aws_sdk_cloudwatchlogs::Client;
+use futures::future::LocalBoxFuture;
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::error::Error;
+
+/// Raw lines add up quickly, keep the prompt small unless asked otherwise
+const DEFAULT_MAX_EVENTS: usize = 100;
+const DEFAULT_MAX_LINE_LENGTH: usize = 300;
+
+/// FilterLogEvents returns at most 10000 events per page
+const MAX_EVENTS_PER_PAGE: usize = 10000;
+
+/// Pages may be empty while a filter pattern scans the log streams, bounds the scan of a single window
+const MAX_PAGES_PER_WINDOW: usize = 20;
+
+/// The range is scanned backwards from its end, in windows starting at this fraction of the range and doubling
+const FIRST_WINDOW_FRACTION: i64 = 16;
+
+#[derive(Deserialize, Debug, Default)]
+pub struct CloudwatchLogEventsConfig {
+    pub order_no: u8,
+    pub description: String,
+    pub log_group_name: String,
+    /// CloudWatch Logs filter pattern syntax, every event otherwise
+    pub filter_pattern: Option<String>,
+    pub log_stream_name_prefix: Option<String>,
+    pub max_events: Option<usize>,
+    /// Longer lines are cut, e.g. a huge JSON payload on a single line
+    pub max_line_length: Option<usize>,
+    #[serde(flatten)]
+    pub options: SourceOptions,
+}
+
+impl DataSource for CloudwatchLogEventsConfig {
+    fn name(&self) -> &str {
+        "Cloudwatch log events"
+    }
+
+    fn order_no(&self) -> u8 {
+        self.order_no
+    }
+
+    fn options(&self) -> &SourceOptions {
+        &self.options
+    }
+
+    fn fetch_data<'a>(&'a self, context: &'a AppContext) -> LocalBoxFuture<'a, Result<Vec<PromptData>, DataSourceError>> {
+        Box::pin(async move {
+            let client = context.aws.cloudwatch_logs(&self.options.scope()).await;
+            Ok(vec![fetch_data(&client, self, &context.range).await?])
+        })
+    }
+}
+
+pub trait CloudwatchLogEventsClient {
+    async fn filter_log_events(&self, config: &CloudwatchLogEventsConfig, range: &DateTimeRange, limit: usize, next_token: Option<String>) -> Result<FilterLogEventsOutput, Box<dyn Error>>;
+}
+
+impl CloudwatchLogEventsClient for Client {
+    async fn filter_log_events(&self, config: &CloudwatchLogEventsConfig, range: &DateTimeRange, limit: usize, next_token: Option<String>) -> Result<FilterLogEventsOutput, Box<dyn Error>> {
+        Ok(self.filter_log_events()
+            .log_group_name(&config.log_group_name)
+            .set_filter_pattern(config.filter_pattern.clone())
+            .set_log_stream_name_prefix(config.log_stream_name_prefix.clone())
+            .start_time(range.start_time)
+            .end_time(range.end_time)
+            .limit(limit as i32)
+            .set_next_token(next_token)
+            .send()
+            .await?)
+    }
+}
+
+pub async fn fetch_data(client: &impl CloudwatchLogEventsClient, config: &CloudwatchLogEventsConfig, range: &DateTimeRange) -> Result<PromptData, Box<dyn Error>> {
+    if config.log_group_name.is_empty() {
+        return Err(DataSourceError::InvalidConfig("log_group_name must be provided".to_string()).into());
+    }
+
+    let max_events = config.max_events.unwrap_or(DEFAULT_MAX_EVENTS);
+
+    // Events come oldest first, the windows closest to the end of the range are fetched first to keep the latest events.
+    // One event past `max_events` tells whether older events were left out
+    let wanted = max_events + 1;
+    let mut events: VecDeque<FilteredLogEvent> = VecDeque::new();
+    let mut incomplete = false;
+    let mut end_time = range.end_time;
+    let mut span = ((range.end_time - range.start_time) / FIRST_WINDOW_FRACTION).max(1);
+
+    while events.len() < wanted && end_time >= range.start_time {
+        let window = DateTimeRange {
+            start_time: (end_time - span + 1).max(range.start_time),
+            end_time,
+            time_zone: range.time_zone,
+        };
+
+        let (window_events, window_incomplete) = fetch_window(client, config, &window, wanted - events.len()).await?;
+        for event in window_events.into_iter().rev() {
+            events.push_front(event);
+        }
+        incomplete |= window_incomplete;
+
+        end_time = window.start_time - 1;
+        span *= 2;
+    }
+
+    let mut description = build_description(config);
+    if events.len() > max_events {
+        events.drain(..events.len() - max_events);
+        description.push(format!("Note: [Only the latest {max_events} matching events are included]"));
+    }
+    if incomplete {
+        description.push(format!("Note: [Scanning stopped after {MAX_PAGES_PER_WINDOW} pages, some events may be missing]"));
+    }
+
+    let events: Vec<FilteredLogEvent> = events.into();
+
+    let max_line_length = config.max_line_length.unwrap_or(DEFAULT_MAX_LINE_LENGTH);
+    Ok(PromptData {
+        description,
+        data: Some(format_events(&events, range, max_line_length)),
+    })
+}
+
+/// The latest `max_events` events of the window, and whether the page limit was reached
+async fn fetch_window(client: &impl CloudwatchLogEventsClient, config: &CloudwatchLogEventsConfig, window: &DateTimeRange, max_events: usize) -> Result<(VecDeque<FilteredLogEvent>, bool), Box<dyn Error>> {
+    let mut events = VecDeque::new();
+    let mut next_token = None;
+
+    for _ in 0..MAX_PAGES_PER_WINDOW {
+        let output = client.filter_log_events(config, window, max_events.min(MAX_EVENTS_PER_PAGE), next_token).await?;
+        for event in output.events.unwrap_or_default() {
+            if events.len() == max_events {
+                events.pop_front();
+            }
+            events.push_back(event);
+        }
+
+        next_token = output.next_token;
+        if next_token.is_none() {
+            return Ok((events, false));
+        }
+    }
+
+    Ok((events, true))
+}
+
+fn build_description(config: &CloudwatchLogEventsConfig) -> Vec<String> {
+    let mut description = vec![
+        "Information: [Cloudwatch Log Events]".to_string(),
+        format!("Description: [{}]", &config.description),
+        format!("Log Group: [`{}`]", &config.log_group_name),
+    ];
+
+    if let Some(filter_pattern) = &config.filter_pattern {
+        description.push(format!("Filter pattern: [`{filter_pattern}`]"));
+    }
+
+    if let Some(log_stream_name_prefix) = &config.log_stream_name_prefix {
+        description.push(format!("Log stream prefix: [`{log_stream_name_prefix}`]"));
+    }
+
+    description
+}
+
+/// One line per event prefixed with its time and log stream, the lines of multi-line events (e.g. stack traces) are kept
+fn format_events(events: &[FilteredLogEvent], range: &DateTimeRange, max_line_length: usize) -> String {
+    if events.is_empty() {
+        return "No matching log events found\n".to_string();
+    }
+
+    let mut log = String::new();
+
+    for event in events {
+        let timestamp = range.format_timestamp(event.timestamp().unwrap_or_default());
+        let log_stream_name = event.log_stream_name().unwrap_or_default();
+        let message = event.message().unwrap_or_default().trim_end();
+
+        log.push_str(&format!("{timestamp} [{log_stream_name}]"));
+        for (index, line) in message.lines().enumerate() {
+            log.push_str(if index == 0 { " " } else { "\n    " });
+            log.push_str(&truncate(line, max_line_length));
+        }
+        log.push('\n');
+    }
+
+    log
+}
+
+fn truncate(line: &str, max_length: usize) -> String {
+    match line.char_indices().nth(max_length) {
+        Some((end, _)) => format!("{}... [truncated]", &line[..end]),
+        None => line.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    const MINUTE: i64 = 60 * 1000;
+
+    /// Returns the events within the window, `page_size` per page at most, or empty pages forever when `endless`
+    struct MockCloudwatchLogEventsClient {
+        events: Vec<(i64, &'static str)>,
+        page_size: usize,
+        endless: bool,
+        calls: RefCell<usize>,
+        limits: RefCell<Vec<usize>>,
+    }
+
+    impl MockCloudwatchLogEventsClient {
+        fn new(events: Vec<(i64, &'static str)>) -> Self {
+            MockCloudwatchLogEventsClient {
+                events,
+                page_size: 2,
+                endless: false,
+                calls: RefCell::new(0),
+                limits: RefCell::new(Vec::new()),
+            }
+        }
+    }
+
+    impl CloudwatchLogEventsClient for MockCloudwatchLogEventsClient {
+        async fn filter_log_events(&self, _: &CloudwatchLogEventsConfig, range: &DateTimeRange, limit: usize, next_token: Option<String>) -> Result<FilterLogEventsOutput, Box<dyn Error>> {
+            *self.calls.borrow_mut() += 1;
+            self.limits.borrow_mut().push(limit);
+            let page_size = self.page_size.min(limit);
+            if self.endless {
+                return Ok(FilterLogEventsOutput::builder().next_token("next").build());
+            }
+
+            let matching: Vec<&(i64, &str)> = self.events.iter()
+                .filter(|(timestamp, _)| range.start_time <= *timestamp && *timestamp <= range.end_time)
+                .collect();
+            let offset: usize = next_token.map(|token| token.parse().unwrap()).unwrap_or(0);
+
+            let mut output = FilterLogEventsOutput::builder();
+            for (timestamp, message) in matching.iter().skip(offset).take(page_size) {
+                output = output.events(FilteredLogEvent::builder()
+                    .timestamp(*timestamp)
+                    .log_stream_name("web/1")
+                    .message(*message)
+                    .build());
+            }
+            if offset + page_size < matching.len() {
+                output = output.next_token((offset + page_size).to_string());
+            }
+
+            Ok(output.build())
+        }
+    }
+
+    fn config() -> CloudwatchLogEventsConfig {
+        CloudwatchLogEventsConfig {
+            description: "Errors of the web app".to_string(),
+            log_group_name: "/ecs/web".to_string(),
+            filter_pattern: Some("ERROR".to_string()),
+            ..CloudwatchLogEventsConfig::default()
+        }
+    }
+
+    fn range() -> DateTimeRange {
+        DateTimeRange { start_time: 0, end_time: 60 * MINUTE, ..DateTimeRange::default() }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data() {
+        let client = MockCloudwatchLogEventsClient::new(vec![
+            (5 * MINUTE, "ERROR Request failed\njava.lang.NullPointerException\n\tat App.handle(App.java:42)\n"),
+            (6 * MINUTE, "ERROR Timeout"),
+        ]);
+
+        let prompt_data = fetch_data(&client, &config(), &range()).await.expect("Should fetch data");
+
+        let expected = [
+            "1970-01-01 00:05:00 UTC [web/1] ERROR Request failed\n",
+            "    java.lang.NullPointerException\n",
+            "    \tat App.handle(App.java:42)\n",
+            "1970-01-01 00:06:00 UTC [web/1] ERROR Timeout\n",
+        ].join("");
+
+        assert_eq!(prompt_data.description, vec![
+            "Information: [Cloudwatch Log Events]",
+            "Description: [Errors of the web app]",
+            "Log Group: [`/ecs/web`]",
+            "Filter pattern: [`ERROR`]",
+        ]);
+        assert_eq!(prompt_data.data, Some(expected));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_keeps_latest_events() {
+        let client = MockCloudwatchLogEventsClient::new(vec![
+            (MINUTE, "first"),
+            (20 * MINUTE, "second"),
+            (40 * MINUTE, "third"),
+            (50 * MINUTE, "fourth"),
+            (59 * MINUTE, "fifth"),
+        ]);
+        let config = CloudwatchLogEventsConfig {
+            max_events: Some(3),
+            ..config()
+        };
+
+        let prompt_data = fetch_data(&client, &config, &range()).await.expect("Should fetch data");
+
+        let data = prompt_data.data.unwrap();
+        let messages: Vec<&str> = data.lines().map(|line| line.rsplit(' ').next().unwrap()).collect();
+        assert_eq!(messages, vec!["third", "fourth", "fifth"]);
+        assert_eq!(prompt_data.description[4], "Note: [Only the latest 3 matching events are included]");
+        // A window per event, each asking for the events still needed plus one
+        assert_eq!(*client.limits.borrow(), vec![4, 3, 2, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_exactly_max_events() {
+        let client = MockCloudwatchLogEventsClient::new(vec![
+            (50 * MINUTE, "first"),
+            (58 * MINUTE, "second"),
+            (59 * MINUTE, "third"),
+        ]);
+        let config = CloudwatchLogEventsConfig {
+            max_events: Some(3),
+            ..config()
+        };
+
+        let prompt_data = fetch_data(&client, &config, &range()).await.expect("Should fetch data");
+
+        assert_eq!(prompt_data.data.unwrap().lines().count(), 3);
+        assert_eq!(prompt_data.description.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_stops_scanning_empty_pages() {
+        let client = MockCloudwatchLogEventsClient {
+            endless: true,
+            ..MockCloudwatchLogEventsClient::new(vec![])
+        };
+        let range = DateTimeRange { start_time: 0, end_time: 7, ..DateTimeRange::default() };
+
+        let prompt_data = fetch_data(&client, &config(), &range).await.expect("Should fetch data");
+
+        assert_eq!(*client.calls.borrow(), 4 * MAX_PAGES_PER_WINDOW);
+        assert_eq!(prompt_data.description[4], format!("Note: [Scanning stopped after {MAX_PAGES_PER_WINDOW} pages, some events may be missing]"));
+        assert_eq!(prompt_data.data, Some("No matching log events found\n".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_no_events() {
+        let client = MockCloudwatchLogEventsClient::new(vec![]);
+
+        let prompt_data = fetch_data(&client, &config(), &range()).await.expect("Should fetch data");
+
+        assert_eq!(prompt_data.description.len(), 4);
+        assert_eq!(prompt_data.data, Some("No matching log events found\n".to_string()));
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("short", 10), "short");
+        assert_eq!(truncate("é long line", 6), "é long... [truncated]");
+    }
+}
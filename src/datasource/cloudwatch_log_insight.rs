@@ -1,26 +1,116 @@
-use crate::lib::config::CloudwatchLogInsightConfig;
-use crate::lib::context::DateTimeRange;
+use crate::datasource::ds::{DataSource, DataSourceError, SourceOptions};
+use crate::lib::args::parse_duration;
+use crate::lib::aws::{fetch_all_pages, fetch_pages_until};
+use crate::lib::context::{AppContext, DateTimeRange};
 use crate::lib::prompt::PromptData;
+use crate::lib::summary::{comparison_records, delta_record, summarize, COMPARISON_HEADERS};
+use aws_sdk_cloudwatchlogs::operation::describe_log_groups::DescribeLogGroupsOutput;
+use aws_sdk_cloudwatchlogs::operation::describe_query_definitions::DescribeQueryDefinitionsOutput;
 use aws_sdk_cloudwatchlogs::operation::get_query_results::GetQueryResultsOutput;
 use aws_sdk_cloudwatchlogs::operation::start_query::StartQueryOutput;
-use aws_sdk_cloudwatchlogs::types::QueryStatus;
+use aws_sdk_cloudwatchlogs::types::{QueryStatistics, QueryStatus, ResultField};
 use aws_sdk_cloudwatchlogs::Client;
 use csv::Writer;
+use futures::future::LocalBoxFuture;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
 use std::error::Error;
 use std::time::Duration;
-use tokio::time::sleep;
+use tokio::time::{sleep, timeout};
+use tokio_util::sync::CancellationToken;
 use QueryStatus::{Cancelled, Complete, Failed, Running, Scheduled, Timeout, UnknownValue};
 
+#[derive(Deserialize, Debug, Default)]
+pub struct CloudwatchLogInsightConfig {
+    pub order_no: u8,
+    pub description: String,
+    #[serde(default)]
+    pub log_group_name: String,
+    /// Queried together with `log_group_name`
+    #[serde(default)]
+    pub log_group_names: Vec<String>,
+    /// Queries every log group starting with this prefix
+    pub log_group_prefix: Option<String>,
+    #[serde(default)]
+    pub query: String,
+    /// A saved query used instead of `query`, its log groups are queried as well
+    pub query_definition_name: Option<String>,
+    /// Selects and orders the columns, every field of the results otherwise
+    #[serde(default)]
+    pub result_columns: Vec<String>,
+    /// How long a query may run before being stopped, e.g. `90s` or `5m`
+    #[serde(default, deserialize_with = "deserialize_timeout")]
+    pub timeout: Option<Duration>,
+    #[serde(flatten)]
+    pub options: SourceOptions,
+}
+
+impl DataSource for CloudwatchLogInsightConfig {
+    fn name(&self) -> &str {
+        "Cloudwatch log insight"
+    }
+
+    fn order_no(&self) -> u8 {
+        self.order_no
+    }
+
+    fn options(&self) -> &SourceOptions {
+        &self.options
+    }
+
+    fn fetch_data<'a>(&'a self, context: &'a AppContext) -> LocalBoxFuture<'a, Result<Vec<PromptData>, DataSourceError>> {
+        Box::pin(async move {
+            let client = context.aws.cloudwatch_logs(&self.options.scope()).await;
+            Ok(vec![fetch_data(&client, self, &context.range, context.baseline.as_ref(), &context.cancel).await?])
+        })
+    }
+}
+
+/// Rejected when the configuration is loaded, a zero timeout would stop every query right away
+fn deserialize_timeout<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    let Some(timeout) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+
+    match parse_duration(&timeout) {
+        Ok(timeout) if timeout.is_zero() => Err(D::Error::custom("timeout must be greater than 0")),
+        Ok(timeout) => Ok(Some(timeout)),
+        Err(err) => Err(D::Error::custom(format!("timeout: {err}"))),
+    }
+}
+
 pub trait CloudwatchLogsClient {
-    async fn start_query(&self, log_group_name: &str, query: &str, start_time: i64, end_time: i64) -> Result<StartQueryOutput, Box<dyn Error>>;
+    async fn describe_log_groups(&self, log_group_prefix: &str, next_token: Option<String>) -> Result<DescribeLogGroupsOutput, Box<dyn Error>>;
+
+    async fn describe_query_definitions(&self, name_prefix: &str, next_token: Option<String>) -> Result<DescribeQueryDefinitionsOutput, Box<dyn Error>>;
+
+    async fn start_query(&self, log_group_names: &[String], query: &str, start_time: i64, end_time: i64) -> Result<StartQueryOutput, Box<dyn Error>>;
 
     async fn get_query_results(&self, query_id: String) -> Result<GetQueryResultsOutput, Box<dyn Error>>;
+
+    async fn stop_query(&self, query_id: String) -> Result<(), Box<dyn Error>>;
 }
 
 impl CloudwatchLogsClient for Client {
-    async fn start_query(&self, log_group_name: &str, query: &str, start_time: i64, end_time: i64) -> Result<StartQueryOutput, Box<dyn Error>> {
+    async fn describe_log_groups(&self, log_group_prefix: &str, next_token: Option<String>) -> Result<DescribeLogGroupsOutput, Box<dyn Error>> {
+        Ok(self.describe_log_groups()
+            .log_group_name_prefix(log_group_prefix)
+            .set_next_token(next_token)
+            .send()
+            .await?)
+    }
+
+    async fn describe_query_definitions(&self, name_prefix: &str, next_token: Option<String>) -> Result<DescribeQueryDefinitionsOutput, Box<dyn Error>> {
+        Ok(self.describe_query_definitions()
+            .query_definition_name_prefix(name_prefix)
+            .set_next_token(next_token)
+            .send()
+            .await?)
+    }
+
+    async fn start_query(&self, log_group_names: &[String], query: &str, start_time: i64, end_time: i64) -> Result<StartQueryOutput, Box<dyn Error>> {
         Ok(self.start_query()
-            .log_group_name(log_group_name)
+            .set_log_group_names(Some(log_group_names.to_vec()))
             .query_string(query)
             .start_time(start_time)
             .end_time(end_time)
@@ -34,78 +124,331 @@ impl CloudwatchLogsClient for Client {
             .send()
             .await?)
     }
+
+    async fn stop_query(&self, query_id: String) -> Result<(), Box<dyn Error>> {
+        self.stop_query()
+            .query_id(query_id)
+            .send()
+            .await?;
+        Ok(())
+    }
+}
+
+/// With a `baseline`, the query is also run on that window to be compared with. Running queries are stopped once `cancel` is
+pub async fn fetch_data(client: &impl CloudwatchLogsClient, config: &CloudwatchLogInsightConfig, range: &DateTimeRange, baseline: Option<&DateTimeRange>, cancel: &CancellationToken) -> Result<PromptData, Box<dyn Error>> {
+    let (query, definition_log_group_names) = resolve_query(client, config).await?;
+    let log_group_names = resolve_log_group_names(client, config, definition_log_group_names).await?;
+
+    let output = run_queries(client, config, &query, &log_group_names, range, cancel).await?;
+    let mut description = build_description(config, &log_group_names);
+    description.extend(output.statistics().map(|statistics| format!("Statistics: [{}]", describe_statistics(statistics))));
+
+    let comparison = match baseline {
+        Some(baseline) => {
+            let baseline_output = run_queries(client, config, &query, &log_group_names, baseline, cancel).await?;
+            description.push(format!("Baseline: [{}]", baseline.describe()));
+            description.extend(baseline_output.statistics().map(|statistics| format!("Baseline statistics: [{}]", describe_statistics(statistics))));
+            Some(compare_results(&output, &baseline_output)?)
+        },
+        None => None
+    };
+
+    let data = extract_to_csv(output, config)?
+        .map(|data| match &comparison {
+            Some(comparison) => format!("{data}\nBaseline comparison:\n{comparison}"),
+            None => data
+        });
+
+    Ok(PromptData { description, data })
+}
+
+/// StartQuery accepts at most 50 log groups
+const MAX_LOG_GROUPS_PER_QUERY: usize = 50;
+
+/// Added to each row when the log groups are split between queries, e.g. `51-60` for the rows of the second query
+const CHUNK_FIELD: &str = "log_groups";
+
+/// Log Insights itself gives up after 60 minutes, most queries should be done well before
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// The poll delay doubles from the first to the max delay, so that short queries return quickly
+const FIRST_POLL_DELAY: Duration = Duration::from_millis(500);
+const MAX_POLL_DELAY: Duration = Duration::from_secs(5);
+
+/// The inline `query`, or the query string and log groups of the saved query named `query_definition_name`
+async fn resolve_query(client: &impl CloudwatchLogsClient, config: &CloudwatchLogInsightConfig) -> Result<(String, Vec<String>), Box<dyn Error>> {
+    let name = match (&config.query_definition_name, config.query.is_empty()) {
+        (None, false) => return Ok((config.query.clone(), Vec::new())),
+        (Some(name), true) => name,
+        (Some(_), false) => return Err(DataSourceError::InvalidConfig("Only one of query or query_definition_name must be provided".to_string()).into()),
+        (None, true) => return Err(DataSourceError::InvalidConfig("One of query or query_definition_name must be provided".to_string()).into()),
+    };
+
+    // The name is only filtered by prefix, e.g. `errors` also returns `errors-by-url`
+    let find = |page: &DescribeQueryDefinitionsOutput| page.query_definitions()
+        .iter()
+        .find(|query_definition| query_definition.name() == Some(name.as_str()))
+        .cloned();
+    let (pages, _) = fetch_pages_until(
+        |next_token| client.describe_query_definitions(name, next_token),
+        DescribeQueryDefinitionsOutput::next_token,
+        |pages| pages.last().and_then(find).is_some(),
+    ).await?;
+
+    let query_definition = pages.last()
+        .and_then(find)
+        .ok_or(DataSourceError::NotFound { resource: "query definition", name: name.clone() })?;
+    let query = query_definition.query_string().ok_or(DataSourceError::MissingField("Query string"))?;
+    Ok((query.to_string(), query_definition.log_group_names().to_vec()))
 }
 
-pub async fn fetch_data(client: impl CloudwatchLogsClient, config: &CloudwatchLogInsightConfig, range: &DateTimeRange) -> Result<PromptData, Box<dyn Error>> {
+/// `log_group_name`, `log_group_names`, those of the query definition and the log groups matching `log_group_prefix`, without duplicates
+async fn resolve_log_group_names(client: &impl CloudwatchLogsClient, config: &CloudwatchLogInsightConfig, definition_log_group_names: Vec<String>) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut log_group_names: Vec<String> = Vec::new();
+    log_group_names.extend(Some(&config.log_group_name).filter(|name| !name.is_empty()).cloned());
+    log_group_names.extend(config.log_group_names.iter().cloned());
+    log_group_names.extend(definition_log_group_names);
+
+    if let Some(prefix) = &config.log_group_prefix {
+        let pages = fetch_all_pages(|next_token| client.describe_log_groups(prefix, next_token), DescribeLogGroupsOutput::next_token).await?;
+        let found: Vec<String> = pages.iter()
+            .flat_map(|page| page.log_groups())
+            .filter_map(|log_group| log_group.log_group_name())
+            .map(str::to_string)
+            .collect();
+
+        if found.is_empty() {
+            return Err(DataSourceError::NotFound { resource: "log group", name: format!("{prefix}*") }.into());
+        }
+        log_group_names.extend(found);
+    }
+
+    let mut unique = Vec::new();
+    for log_group_name in log_group_names {
+        if !unique.contains(&log_group_name) {
+            unique.push(log_group_name);
+        }
+    }
+
+    if unique.is_empty() {
+        return Err(DataSourceError::InvalidConfig("One of log_group_name, log_group_names or log_group_prefix must be provided".to_string()).into());
+    }
+
+    Ok(unique)
+}
+
+/// Beyond the limit of log groups per query, the query is run for each chunk and their statistics added up.
+/// Aggregates such as `stats count(*)` are per chunk, so each row is labelled with the positions of the log groups of its chunk
+async fn run_queries(client: &impl CloudwatchLogsClient, config: &CloudwatchLogInsightConfig, query: &str, log_group_names: &[String], range: &DateTimeRange, cancel: &CancellationToken) -> Result<GetQueryResultsOutput, Box<dyn Error>> {
+    let query_timeout = config.timeout.unwrap_or(DEFAULT_TIMEOUT);
+
+    let mut results = Vec::new();
+    let mut statistics: Option<QueryStatistics> = None;
+
+    let chunked = log_group_names.len() > MAX_LOG_GROUPS_PER_QUERY;
+    for (index, chunk) in log_group_names.chunks(MAX_LOG_GROUPS_PER_QUERY).enumerate() {
+        let output = run_query(client, query, chunk, range, query_timeout, cancel).await?;
+        statistics = match (statistics, output.statistics) {
+            (Some(total), Some(chunk)) => Some(QueryStatistics::builder()
+                .records_matched(total.records_matched + chunk.records_matched)
+                .records_scanned(total.records_scanned + chunk.records_scanned)
+                .bytes_scanned(total.bytes_scanned + chunk.bytes_scanned)
+                .build()),
+            (total, chunk) => total.or(chunk),
+        };
+
+        let mut rows = output.results.unwrap_or_default();
+        if chunked {
+            let first = index * MAX_LOG_GROUPS_PER_QUERY + 1;
+            let label = format!("{first}-{}", first + chunk.len() - 1);
+            for row in &mut rows {
+                row.insert(0, ResultField::builder().field(CHUNK_FIELD).value(&label).build());
+            }
+        }
+        results.extend(rows);
+    }
+
+    Ok(GetQueryResultsOutput::builder()
+        .status(Complete)
+        .set_results(Some(results))
+        .set_statistics(statistics)
+        .build())
+}
+
+/// Stops the query when it takes longer than `query_timeout`, or when the run is cancelled, rather than leaving it running
+async fn run_query(client: &impl CloudwatchLogsClient, query: &str, log_group_names: &[String], range: &DateTimeRange, query_timeout: Duration, cancel: &CancellationToken) -> Result<GetQueryResultsOutput, Box<dyn Error>> {
     let start_time = range.start_time;
     let end_time = range.end_time;
 
     let response = client.start_query(
-        &config.log_group_name,
-        &config.query,
+        log_group_names,
+        query,
         start_time,
         end_time
     ).await?;
 
-    let query_id = response.query_id().expect("Query Id is missing from response");
+    let query_id = response.query_id().ok_or(DataSourceError::MissingField("Query Id"))?;
+
+    let interrupted = tokio::select! {
+        result = timeout(query_timeout, poll_query(client, query_id)) => match result {
+            Ok(result) => return result,
+            Err(_) => DataSourceError::QueryTimeout(query_timeout.as_secs()),
+        },
+        _ = cancel.cancelled() => DataSourceError::Interrupted,
+    };
 
+    // The query is given up on either way, a failure to stop it must not hide why
+    let _ = client.stop_query(String::from(query_id)).await;
+    Err(interrupted.into())
+}
+
+async fn poll_query(client: &impl CloudwatchLogsClient, query_id: &str) -> Result<GetQueryResultsOutput, Box<dyn Error>> {
+    let mut delay = FIRST_POLL_DELAY;
     let mut poll_response;
 
     loop {
         poll_response = client.get_query_results(String::from(query_id)).await?;
 
-        match poll_response.status().unwrap() {
+        let status = poll_response.status().ok_or(DataSourceError::MissingField("Query status"))?;
+
+        match status {
             Complete => break,
-            Running | Scheduled => sleep(Duration::from_secs(1)).await,
-            Cancelled | Failed | Timeout | UnknownValue | &_ => panic!("Unexpected status: {}", poll_response.status().unwrap()),
+            Running | Scheduled => {
+                sleep(delay).await;
+                delay = (delay * 2).min(MAX_POLL_DELAY);
+            },
+            Cancelled | Failed | Timeout | UnknownValue | &_ => return Err(DataSourceError::QueryStatus(status.to_string()).into()),
         }
     }
 
-    Ok(PromptData {
-        description: build_description(config),
-        data: extract_to_csv(poll_response, config)?
-    })
+    Ok(poll_response)
 }
 
-fn build_description(config: &CloudwatchLogInsightConfig) -> Vec<String> {
-    vec![
+fn describe_statistics(statistics: &QueryStatistics) -> String {
+    format!(
+        "records matched: {}, records scanned: {}, bytes scanned: {}",
+        statistics.records_matched, statistics.records_scanned, statistics.bytes_scanned
+    )
+}
+
+fn build_description(config: &CloudwatchLogInsightConfig, log_group_names: &[String]) -> Vec<String> {
+    let log_groups = log_group_names.iter()
+        .map(|log_group_name| format!("`{log_group_name}`"))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let mut description = vec![
         "Information: [Cloudwatch Log Insights]".to_string(),
         format!("Description: [{}]", &config.description),
-        format!("Log Group: [`{}`]", &config.log_group_name),
-    ]
+    ];
+
+    if let Some(query_definition_name) = &config.query_definition_name {
+        description.push(format!("Query Definition: [`{query_definition_name}`]"));
+    }
+
+    match log_group_names.len() {
+        1 => description.push(format!("Log Group: [{log_groups}]")),
+        _ => description.push(format!("Log Groups: [{log_groups}]")),
+    }
+
+    if log_group_names.len() > MAX_LOG_GROUPS_PER_QUERY {
+        let queries = log_group_names.len().div_ceil(MAX_LOG_GROUPS_PER_QUERY);
+        description.push(format!("Note: [Ran as {queries} queries of up to {MAX_LOG_GROUPS_PER_QUERY} log groups, aggregates are per query, the `{CHUNK_FIELD}` column gives the positions of the log groups of each row]"));
+    }
+
+    description
 }
 
-fn extract_to_csv(output: GetQueryResultsOutput, config: &CloudwatchLogInsightConfig) -> Result<Option<String>, Box<dyn Error>> {
+/// Number of rows, and statistics of every column which only has numbers (e.g. `stats count(*) by bin(5m)`)
+fn compare_results(current: &GetQueryResultsOutput, baseline: &GetQueryResultsOutput) -> Result<String, Box<dyn Error>> {
     let mut csv_writer = Writer::from_writer(Vec::new());
-    csv_writer.write_record(&config.result_columns)?;
-    let mut rows = 0;
+    csv_writer.write_record(COMPARISON_HEADERS)?;
+    csv_writer.write_record(delta_record("rows", "count", Some(current.results().len() as f64), Some(baseline.results().len() as f64)))?;
 
-    let mut columns_iter = config.result_columns.clone().into_iter().cycle();
-    let mut column = columns_iter.next().unwrap();
+    let current_columns = numeric_columns(current);
+    let baseline_columns = numeric_columns(baseline);
 
-    for result in output.results() {
-        let mut values : Vec<String> = Vec::new();
+    for (column, values) in &current_columns {
+        let Some((_, baseline_values)) = baseline_columns.iter().find(|(baseline_column, _)| baseline_column == column) else {
+            continue;
+        };
 
-        // Discard '@ptr' from result
-        for result_field in result.iter().filter(|r| r.field().unwrap() != "@ptr") {
-            let field = result_field.field().unwrap();
+        let current_summary = summarize(values);
+        let baseline_summary = summarize(baseline_values);
+        for record in comparison_records(column, current_summary.as_ref(), baseline_summary.as_ref()) {
+            csv_writer.write_record(record)?;
+        }
+    }
 
-            if column == field {
-                values.push(result_field.value().unwrap().parse().unwrap());
-                column = columns_iter.next().unwrap();
-            } else {
-                panic!("Expected column not matched! Expected: {column}, Actual: {field}");
+    Ok(String::from_utf8(csv_writer.into_inner()?)?)
+}
+
+/// Values of each numeric column, indexed by row
+fn numeric_columns(output: &GetQueryResultsOutput) -> Vec<(String, Vec<(i64, f64)>)> {
+    let mut columns: Vec<(String, Vec<(i64, f64)>)> = Vec::new();
+    let mut non_numeric: Vec<String> = Vec::new();
+
+    for (row, result) in output.results().iter().enumerate() {
+        for result_field in result.iter().filter(|r| r.field() != Some("@ptr")) {
+            let field = result_field.field().unwrap_or_default().to_string();
+
+            match result_field.value().unwrap_or_default().parse::<f64>() {
+                Ok(value) => match columns.iter_mut().find(|(column, _)| *column == field) {
+                    Some((_, values)) => values.push((row as i64, value)),
+                    None => columns.push((field, vec![(row as i64, value)])),
+                },
+                Err(_) => non_numeric.push(field),
             }
         }
-
-        csv_writer.write_record(values)?;
-        rows += 1;
     }
 
-    if rows == 0 {
+    columns.retain(|(column, _)| !non_numeric.contains(column));
+    columns
+}
+
+/// Columns are inferred from the fields of every row, `result_columns` selects and orders them.
+/// A selected column no row has is left empty, e.g. an error field when no request failed
+fn extract_to_csv(output: GetQueryResultsOutput, config: &CloudwatchLogInsightConfig) -> Result<Option<String>, Box<dyn Error>> {
+    // Discard '@ptr' from result
+    let rows: Vec<Vec<(&str, &str)>> = output.results().iter()
+        .map(|result| result.iter()
+            .filter_map(|result_field| Some((result_field.field()?, result_field.value().unwrap_or_default())))
+            .filter(|(field, _)| *field != "@ptr")
+            .collect())
+        .collect();
+
+    if rows.is_empty() {
         return Ok(Some("No applicable data found\n".to_string()))
     }
 
+    // Rows don't always have every field, e.g. when a parsed field didn't match
+    let mut inferred: Vec<&str> = Vec::new();
+    for (field, _) in rows.iter().flatten() {
+        if !inferred.contains(field) {
+            inferred.push(field);
+        }
+    }
+
+    let mut columns: Vec<&str> = match config.result_columns.is_empty() {
+        true => inferred,
+        false => config.result_columns.iter().map(String::as_str).collect(),
+    };
+
+    // Rows of queries split by log groups must keep telling which log groups they come from
+    let chunked = rows.iter().flatten().any(|(field, _)| *field == CHUNK_FIELD);
+    if chunked && !columns.contains(&CHUNK_FIELD) {
+        columns.insert(0, CHUNK_FIELD);
+    }
+
+    let mut csv_writer = Writer::from_writer(Vec::new());
+    csv_writer.write_record(&columns)?;
+
+    for row in &rows {
+        let values = columns.iter()
+            .map(|column| row.iter().find(|(field, _)| field == column).map(|(_, value)| *value).unwrap_or_default());
+        csv_writer.write_record(values)?;
+    }
+
     let csv = String::from_utf8(csv_writer.into_inner()?)?;
     Ok(Some(csv))
 }
@@ -113,23 +456,61 @@ fn extract_to_csv(output: GetQueryResultsOutput, config: &CloudwatchLogInsightCo
 #[cfg(test)]
 mod tests {
     use super::*;
-    use aws_sdk_cloudwatchlogs::types::ResultField;
+    use aws_sdk_cloudwatchlogs::types::{LogGroup, QueryDefinition, ResultField};
     use std::cell::RefCell;
 
     struct MockCloudwatchLogsClient {
-        status_queue: RefCell<Vec<QueryStatus>>
+        status_queue: RefCell<Vec<QueryStatus>>,
+        log_groups: Vec<&'static str>,
+        query_definitions: Vec<QueryDefinition>,
+        started_queries: RefCell<Vec<usize>>,
+        stopped_queries: RefCell<Vec<String>>,
+        fail_stop: bool,
     }
 
     impl MockCloudwatchLogsClient {
         fn new(statuses: Vec<QueryStatus>) -> Self {
             MockCloudwatchLogsClient {
-                status_queue: RefCell::new(statuses)
+                status_queue: RefCell::new(statuses),
+                log_groups: Vec::new(),
+                query_definitions: Vec::new(),
+                started_queries: RefCell::new(Vec::new()),
+                stopped_queries: RefCell::new(Vec::new()),
+                fail_stop: false,
             }
         }
     }
 
     impl CloudwatchLogsClient for MockCloudwatchLogsClient {
-        async fn start_query(&self, _: &str, _: &str, _: i64, _: i64) -> Result<StartQueryOutput, Box<dyn Error>> {
+        /// Returns one log group per page
+        async fn describe_log_groups(&self, prefix: &str, next_token: Option<String>) -> Result<DescribeLogGroupsOutput, Box<dyn Error>> {
+            let matching: Vec<&str> = self.log_groups.iter().copied().filter(|name| name.starts_with(prefix)).collect();
+            let page: usize = next_token.map(|token| token.parse().unwrap()).unwrap_or(0);
+
+            let mut output = DescribeLogGroupsOutput::builder();
+            if let Some(name) = matching.get(page) {
+                output = output.log_groups(LogGroup::builder().log_group_name(*name).build());
+            }
+            if page + 1 < matching.len() {
+                output = output.next_token((page + 1).to_string());
+            }
+
+            Ok(output.build())
+        }
+
+        async fn describe_query_definitions(&self, name_prefix: &str, _: Option<String>) -> Result<DescribeQueryDefinitionsOutput, Box<dyn Error>> {
+            let matching: Vec<QueryDefinition> = self.query_definitions.iter()
+                .filter(|query_definition| query_definition.name().unwrap_or_default().starts_with(name_prefix))
+                .cloned()
+                .collect();
+
+            Ok(DescribeQueryDefinitionsOutput::builder()
+                .set_query_definitions(Some(matching))
+                .build())
+        }
+
+        async fn start_query(&self, log_group_names: &[String], _: &str, _: i64, _: i64) -> Result<StartQueryOutput, Box<dyn Error>> {
+            self.started_queries.borrow_mut().push(log_group_names.len());
             Ok(StartQueryOutput::builder()
                 .query_id("query_id".to_string())
                 .build())
@@ -141,6 +522,11 @@ mod tests {
 
             Ok(GetQueryResultsOutput::builder()
                 .status(query_status.clone())
+                .statistics(QueryStatistics::builder()
+                    .records_matched(3.0)
+                    .records_scanned(10.0)
+                    .bytes_scanned(2048.0)
+                    .build())
                 .results(vec![
                     ResultField::builder()
                         .field("@ptr")
@@ -185,6 +571,14 @@ mod tests {
                 ])
                 .build())
         }
+
+        async fn stop_query(&self, query_id: String) -> Result<(), Box<dyn Error>> {
+            self.stopped_queries.borrow_mut().push(query_id);
+            match self.fail_stop {
+                true => Err("Query is already stopped".into()),
+                false => Ok(()),
+            }
+        }
     }
 
     #[test]
@@ -195,7 +589,7 @@ mod tests {
             ..CloudwatchLogInsightConfig::default()
         };
 
-        let description = build_description(&config);
+        let description = build_description(&config, &["log-group-name".to_string()]);
 
         assert_eq!(description.len(), 3);
         assert_eq!(description[0], "Information: [Cloudwatch Log Insights]".to_string());
@@ -220,12 +614,14 @@ mod tests {
     async fn test_fetch_data() {
         let client = MockCloudwatchLogsClient::new(vec![Scheduled, Running, Complete]);
         let config = CloudwatchLogInsightConfig {
+            log_group_name: "log-group-name".to_string(),
+            query: "fields @message".to_string(),
             result_columns: vec!["column1".to_string(), "column2".to_string()],
             ..CloudwatchLogInsightConfig::default()
         };
         let range = DateTimeRange::default();
 
-        let prompt_data = fetch_data(client, &config, &range).await.expect("Should extract to csv");
+        let prompt_data = fetch_data(&client, &config, &range, None, &CancellationToken::new()).await.expect("Should extract to csv");
 
         let expected = [
             "column1,column2\n",
@@ -238,45 +634,304 @@ mod tests {
     }
 
     #[tokio::test]
-    #[should_panic(expected = "Expected column not matched! Expected: columnB, Actual: column2")]
-    async fn test_extract_to_csv_mismatch_column() {
+    async fn test_extract_to_csv_missing_column() {
         let client = MockCloudwatchLogsClient::new(vec![Complete]);
         let config = CloudwatchLogInsightConfig {
+            log_group_name: "log-group-name".to_string(),
+            query: "fields @message".to_string(),
             result_columns: vec!["column1".to_string(), "columnB".to_string()],
             ..CloudwatchLogInsightConfig::default()
         };
         let range = DateTimeRange::default();
 
-        fetch_data(client, &config, &range).await.expect("Should extract to csv");
+        let prompt_data = fetch_data(&client, &config, &range, None, &CancellationToken::new()).await.expect("Should extract to csv");
+
+        let expected = [
+            "column1,columnB\n",
+            "row1-column1,\n",
+            "row2-column1,\n",
+            "row3-column1,\n",
+        ].join("");
+
+        assert_eq!(prompt_data.data, Some(expected));
+    }
+
+    #[test]
+    fn test_extract_to_csv_infers_columns() {
+        let field = |field: &str, value: &str| ResultField::builder().field(field).value(value).build();
+        let output = || GetQueryResultsOutput::builder()
+            .results(vec![field("@ptr", "discarded"), field("status", "200"), field("url", "/")])
+            .results(vec![field("@ptr", "discarded"), field("status", "500"), field("error", "timeout")])
+            .build();
+        let selected = CloudwatchLogInsightConfig {
+            result_columns: vec!["error".to_string(), "status".to_string()],
+            ..CloudwatchLogInsightConfig::default()
+        };
+
+        let inferred = extract_to_csv(output(), &CloudwatchLogInsightConfig::default()).expect("Should extract to csv");
+        let selected = extract_to_csv(output(), &selected).expect("Should extract to csv");
+
+        assert_eq!(inferred, Some("status,url,error\n200,/,\n500,,timeout\n".to_string()));
+        assert_eq!(selected, Some("error,status\n,200\ntimeout,500\n".to_string()));
     }
 
     #[tokio::test]
-    #[should_panic(expected = "Unexpected status: Failed")]
     async fn test_fetch_data_failed() {
         let client = MockCloudwatchLogsClient::new(vec![Failed]);
-        let config = CloudwatchLogInsightConfig::default();
+        let config = CloudwatchLogInsightConfig {
+            log_group_name: "log-group-name".to_string(),
+            query: "fields @message".to_string(),
+            ..CloudwatchLogInsightConfig::default()
+        };
         let range = DateTimeRange::default();
 
-        fetch_data(client, &config, &range).await.expect("Should extract to csv");
+        let err = fetch_data(&client, &config, &range, None, &CancellationToken::new()).await.expect_err("Should not extract to csv");
+
+        assert_eq!(err.to_string(), "Unexpected status: Failed");
+        assert!(matches!(DataSourceError::from(err), DataSourceError::QueryStatus(_)));
     }
 
     #[tokio::test]
-    #[should_panic(expected = "Unexpected status: Timeout")]
     async fn test_fetch_data_timeout() {
         let client = MockCloudwatchLogsClient::new(vec![Timeout]);
-        let config = CloudwatchLogInsightConfig::default();
+        let config = CloudwatchLogInsightConfig {
+            log_group_name: "log-group-name".to_string(),
+            query: "fields @message".to_string(),
+            ..CloudwatchLogInsightConfig::default()
+        };
         let range = DateTimeRange::default();
 
-        fetch_data(client, &config, &range).await.expect("Should extract to csv");
+        let err = fetch_data(&client, &config, &range, None, &CancellationToken::new()).await.expect_err("Should not extract to csv");
+
+        assert_eq!(err.to_string(), "Unexpected status: Timeout");
+        assert!(matches!(DataSourceError::from(err), DataSourceError::QueryStatus(_)));
     }
 
     #[tokio::test]
-    #[should_panic(expected = "Unexpected status: Unknown")]
     async fn test_fetch_data_unknown_value() {
         let client = MockCloudwatchLogsClient::new(vec![UnknownValue]);
-        let config = CloudwatchLogInsightConfig::default();
+        let config = CloudwatchLogInsightConfig {
+            log_group_name: "log-group-name".to_string(),
+            query: "fields @message".to_string(),
+            ..CloudwatchLogInsightConfig::default()
+        };
+        let range = DateTimeRange::default();
+
+        let err = fetch_data(&client, &config, &range, None, &CancellationToken::new()).await.expect_err("Should not extract to csv");
+
+        assert_eq!(err.to_string(), "Unexpected status: Unknown");
+        assert!(matches!(DataSourceError::from(err), DataSourceError::QueryStatus(_)));
+    }
+
+    /// Polled after 500ms then 1s, the query completes too late
+    #[tokio::test]
+    async fn test_fetch_data_stops_query_on_timeout() {
+        let client = MockCloudwatchLogsClient::new(vec![Scheduled, Running, Complete]);
+        let config = CloudwatchLogInsightConfig {
+            log_group_name: "log-group-name".to_string(),
+            query: "fields @message".to_string(),
+            timeout: Some(Duration::from_secs(1)),
+            ..CloudwatchLogInsightConfig::default()
+        };
+        let range = DateTimeRange::default();
+
+        let err = fetch_data(&client, &config, &range, None, &CancellationToken::new()).await.expect_err("Should time out");
+
+        assert_eq!(err.to_string(), "Query did not complete within 1s, it was stopped");
+        assert_eq!(*client.stopped_queries.borrow(), vec!["query_id"]);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_timeout_when_stop_fails() {
+        let client = MockCloudwatchLogsClient {
+            fail_stop: true,
+            ..MockCloudwatchLogsClient::new(vec![Scheduled, Running, Complete])
+        };
+        let config = CloudwatchLogInsightConfig {
+            log_group_name: "log-group-name".to_string(),
+            query: "fields @message".to_string(),
+            timeout: Some(Duration::from_secs(1)),
+            ..CloudwatchLogInsightConfig::default()
+        };
         let range = DateTimeRange::default();
 
-        fetch_data(client, &config, &range).await.expect("Should extract to csv");
+        let err = fetch_data(&client, &config, &range, None, &CancellationToken::new()).await.expect_err("Should time out");
+
+        assert!(matches!(DataSourceError::from(err), DataSourceError::QueryTimeout(1)));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_stops_query_when_cancelled() {
+        let client = MockCloudwatchLogsClient::new(vec![Scheduled, Running, Complete]);
+        let config = CloudwatchLogInsightConfig {
+            log_group_name: "log-group-name".to_string(),
+            query: "fields @message".to_string(),
+            ..CloudwatchLogInsightConfig::default()
+        };
+        let range = DateTimeRange::default();
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        let err = fetch_data(&client, &config, &range, None, &cancel).await.expect_err("Should be interrupted");
+
+        assert!(matches!(DataSourceError::from(err), DataSourceError::Interrupted));
+        assert_eq!(*client.stopped_queries.borrow(), vec!["query_id"]);
+    }
+
+    #[test]
+    fn test_parse_timeout() {
+        let parse = |timeout: &str| toml::from_str::<CloudwatchLogInsightConfig>(&format!("order_no = 1\ndescription = ''\ntimeout = '{timeout}'"));
+
+        assert_eq!(parse("5m").expect("Should parse timeout").timeout, Some(Duration::from_secs(300)));
+        assert!(parse("0").is_err());
+        assert!(parse("0s").is_err());
+        assert!(parse("soon").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_with_baseline() {
+        let client = MockCloudwatchLogsClient::new(vec![Complete, Complete]);
+        let config = CloudwatchLogInsightConfig {
+            log_group_name: "log-group-name".to_string(),
+            query: "fields @message".to_string(),
+            result_columns: vec!["column1".to_string(), "column2".to_string()],
+            ..CloudwatchLogInsightConfig::default()
+        };
+        let range = DateTimeRange::default();
+        let baseline = DateTimeRange::default();
+
+        let prompt_data = fetch_data(&client, &config, &range, Some(&baseline), &CancellationToken::new()).await.expect("Should fetch data");
+
+        let comparison = [
+            "\nBaseline comparison:\n",
+            "column,statistic,current,baseline,delta,delta_percent\n",
+            "rows,count,3,3,0,0\n",
+        ].join("");
+
+        assert_eq!(prompt_data.description[3], "Statistics: [records matched: 3, records scanned: 10, bytes scanned: 2048]");
+        assert_eq!(prompt_data.description[4], "Baseline: [1970-01-01 00:00:00 UTC to 1970-01-01 00:00:00 UTC]");
+        assert_eq!(prompt_data.description[5], "Baseline statistics: [records matched: 3, records scanned: 10, bytes scanned: 2048]");
+        assert!(prompt_data.data.unwrap().ends_with(&comparison));
+    }
+
+    #[test]
+    fn test_numeric_columns() {
+        let row = |count: &str| vec![
+            ResultField::builder().field("@ptr").value("discarded").build(),
+            ResultField::builder().field("bin(5m)").value("2023-10-12 09:30:00.000").build(),
+            ResultField::builder().field("count").value(count).build(),
+        ];
+        let output = GetQueryResultsOutput::builder()
+            .results(row("3"))
+            .results(row("5"))
+            .build();
+
+        let columns = numeric_columns(&output);
+
+        assert_eq!(columns, vec![("count".to_string(), vec![(0, 3.0), (1, 5.0)])]);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_log_group_names() {
+        let client = MockCloudwatchLogsClient {
+            log_groups: vec!["/ecs/web-1", "/ecs/web-2", "/ecs/worker-1", "/rds/db"],
+            ..MockCloudwatchLogsClient::new(vec![])
+        };
+        let config = CloudwatchLogInsightConfig {
+            log_group_name: "/ecs/web-1".to_string(),
+            log_group_names: vec!["/rds/db".to_string()],
+            log_group_prefix: Some("/ecs/".to_string()),
+            ..CloudwatchLogInsightConfig::default()
+        };
+
+        let log_group_names = resolve_log_group_names(&client, &config, Vec::new()).await.expect("Should resolve log groups");
+
+        assert_eq!(log_group_names, vec!["/ecs/web-1", "/rds/db", "/ecs/web-2", "/ecs/worker-1"]);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_with_query_definition() {
+        let query_definition = |name: &str, log_group_name: &str| QueryDefinition::builder()
+            .name(name)
+            .query_string(format!("fields {name}"))
+            .log_group_names(log_group_name)
+            .build();
+        let client = MockCloudwatchLogsClient {
+            query_definitions: vec![query_definition("errors-by-url", "/ecs/web"), query_definition("errors", "/ecs/worker")],
+            ..MockCloudwatchLogsClient::new(vec![Complete])
+        };
+        let config = CloudwatchLogInsightConfig {
+            log_group_name: "/ecs/web".to_string(),
+            query_definition_name: Some("errors".to_string()),
+            ..CloudwatchLogInsightConfig::default()
+        };
+        let range = DateTimeRange::default();
+
+        let (query, log_group_names) = resolve_query(&client, &config).await.expect("Should resolve query");
+        let prompt_data = fetch_data(&client, &config, &range, None, &CancellationToken::new()).await.expect("Should fetch data");
+
+        assert_eq!(query, "fields errors");
+        assert_eq!(log_group_names, vec!["/ecs/worker"]);
+        assert_eq!(prompt_data.description[2], "Query Definition: [`errors`]");
+        assert_eq!(prompt_data.description[3], "Log Groups: [`/ecs/web`, `/ecs/worker`]");
+    }
+
+    #[tokio::test]
+    async fn test_resolve_query_invalid() {
+        let client = MockCloudwatchLogsClient::new(vec![]);
+        let missing = CloudwatchLogInsightConfig {
+            query_definition_name: Some("errors".to_string()),
+            ..CloudwatchLogInsightConfig::default()
+        };
+        let both = CloudwatchLogInsightConfig {
+            query: "fields @message".to_string(),
+            query_definition_name: Some("errors".to_string()),
+            ..CloudwatchLogInsightConfig::default()
+        };
+
+        let missing = resolve_query(&client, &missing).await.expect_err("Should not find query definition");
+        let both = resolve_query(&client, &both).await.expect_err("Should not accept both");
+        let neither = resolve_query(&client, &CloudwatchLogInsightConfig::default()).await.expect_err("Should require a query");
+
+        assert_eq!(missing.to_string(), "Unable to find query definition with name: errors");
+        assert!(matches!(DataSourceError::from(both), DataSourceError::InvalidConfig(_)));
+        assert!(matches!(DataSourceError::from(neither), DataSourceError::InvalidConfig(_)));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_log_group_names_without_match() {
+        let client = MockCloudwatchLogsClient::new(vec![]);
+        let config = CloudwatchLogInsightConfig {
+            log_group_prefix: Some("/ecs/".to_string()),
+            ..CloudwatchLogInsightConfig::default()
+        };
+
+        let err = resolve_log_group_names(&client, &config, Vec::new()).await.expect_err("Should not resolve log groups");
+
+        assert!(matches!(DataSourceError::from(err), DataSourceError::NotFound { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_chunks_log_groups() {
+        let client = MockCloudwatchLogsClient::new(vec![Complete, Complete]);
+        let config = CloudwatchLogInsightConfig {
+            log_group_names: (0..60).map(|index| format!("/ecs/web-{index}")).collect(),
+            query: "fields @message".to_string(),
+            result_columns: vec!["column1".to_string(), "column2".to_string()],
+            ..CloudwatchLogInsightConfig::default()
+        };
+        let range = DateTimeRange::default();
+
+        let prompt_data = fetch_data(&client, &config, &range, None, &CancellationToken::new()).await.expect("Should fetch data");
+
+        assert_eq!(*client.started_queries.borrow(), vec![50, 10]);
+        assert!(prompt_data.description[2].starts_with("Log Groups: [`/ecs/web-0`, `/ecs/web-1`"));
+        assert_eq!(prompt_data.description[3], "Note: [Ran as 2 queries of up to 50 log groups, aggregates are per query, the `log_groups` column gives the positions of the log groups of each row]");
+        assert_eq!(prompt_data.description[4], "Statistics: [records matched: 6, records scanned: 20, bytes scanned: 4096]");
+
+        let data = prompt_data.data.unwrap();
+        assert_eq!(data.lines().count(), 7);
+        assert!(data.starts_with("log_groups,column1,column2\n1-50,row1-column1,row1-column2\n"));
+        assert!(data.ends_with("51-60,row3-column1,row3-column2\n"));
     }
 }
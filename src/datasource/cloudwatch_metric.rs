@@ -1,71 +1,671 @@
+use crate::datasource::ds::{DataSource, DataSourceError, SourceOptions};
 use crate::datasource::ec2::{fetch_instances, Ec2Client};
-use crate::lib::config::CloudwatchMetricConfig;
-use crate::lib::context::DateTimeRange;
+use crate::lib::anomaly::{detect, Thresholds};
+use crate::lib::aws::{fetch_all_pages, AwsScope};
+use crate::lib::context::{AppContext, DateTimeRange};
 use crate::lib::prompt::PromptData;
+use crate::lib::summary::{comparison_records, round, summarize, top_spikes, COMPARISON_HEADERS, SUMMARY_HEADERS};
 use aws_sdk_cloudwatch::operation::get_metric_data::GetMetricDataOutput;
-use aws_sdk_cloudwatch::types::{Dimension, Metric, MetricDataQuery, MetricStat};
+use aws_sdk_cloudwatch::types::{Dimension, MessageData, Metric, MetricDataQuery, MetricDataResult, MetricStat, StatusCode};
 use aws_sdk_cloudwatch::Client;
 use aws_smithy_types::DateTime;
 use csv::Writer;
+use futures::future::{join_all, LocalBoxFuture};
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
+use std::rc::Rc;
+use tokio::sync::OnceCell;
+
+#[derive(Deserialize, Debug, Default)]
+pub struct CloudwatchMetricConfig {
+    pub order_no: u8,
+    /// Shorthand for a single entry of `dimensions`
+    #[serde(default)]
+    pub dimension_name: String,
+    #[serde(default)]
+    pub dimension_value: String,
+    #[serde(default)]
+    pub dimensions: BTreeMap<String, String>,
+    pub metric_identifier: String,
+    #[serde(default)]
+    pub metric_namespace: String,
+    #[serde(default)]
+    pub metric_name: String,
+    /// Metric math or `SEARCH(...)`, replaces the namespace/metric/dimension of this block
+    pub expression: Option<String>,
+    /// Metrics only used as inputs of `expression`
+    #[serde(default)]
+    pub metrics: Vec<ExpressionMetricConfig>,
+    pub metric_stat: Option<String>,
+    pub metric_stats: Option<Vec<String>>,
+    pub metric_unit: Option<String>,
+    pub period: Option<i32>,
+    pub max_datapoints: Option<i64>,
+    /// Sends statistics of each column instead of every datapoint
+    #[serde(default)]
+    pub summary: bool,
+    /// Highest datapoints listed per column in summary mode
+    pub top_spikes: Option<usize>,
+    /// Also sends the series averaged down to this number of rows in summary mode
+    pub downsample: Option<usize>,
+    /// Flags outliers and level shifts of each column
+    #[serde(default)]
+    pub detect_anomalies: bool,
+    /// Flags datapoints above this value
+    pub warn_above: Option<f64>,
+    /// Flags datapoints below this value
+    pub warn_below: Option<f64>,
+    #[serde(flatten)]
+    pub options: SourceOptions,
+}
+
+/// An input of a metric math expression, e.g. `m1` of `m1/m2*100`
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct ExpressionMetricConfig {
+    pub id: String,
+    pub metric_namespace: String,
+    pub metric_name: String,
+    pub metric_stat: String,
+    #[serde(default)]
+    pub dimension_name: String,
+    #[serde(default)]
+    pub dimension_value: String,
+    #[serde(default)]
+    pub dimensions: BTreeMap<String, String>,
+}
+
+impl ExpressionMetricConfig {
+    fn dimensions(&self) -> Result<Vec<Dimension>, DataSourceError> {
+        merge_dimensions(&self.dimension_name, &self.dimension_value, &self.dimensions)
+    }
+}
+
+impl CloudwatchMetricConfig {
+    fn validate(&self) -> Result<(), DataSourceError> {
+        if self.expression.is_some() {
+            return Ok(());
+        }
+
+        if !self.metrics.is_empty() {
+            return Err(DataSourceError::InvalidConfig("metrics can only be used with an expression".to_string()));
+        }
+
+        let required = [
+            ("metric_namespace", &self.metric_namespace),
+            ("metric_name", &self.metric_name),
+        ];
+        match required.iter().find(|(_, value)| value.is_empty()) {
+            Some((field, _)) => Err(DataSourceError::InvalidConfig(format!("{field} must be provided when there is no expression"))),
+            None => Ok(())
+        }
+    }
+
+    fn dimensions(&self) -> Result<Vec<Dimension>, DataSourceError> {
+        merge_dimensions(&self.dimension_name, &self.dimension_value, &self.dimensions)
+    }
+
+    /// Inputs are referenced by id in the expression, GetMetricData requires ids to match `^[a-z][a-zA-Z0-9_]*$`
+    fn validate_input_ids(&self) -> Result<(), DataSourceError> {
+        let mut ids = HashSet::new();
+        for input in &self.metrics {
+            let mut chars = input.id.chars();
+            if !chars.next().is_some_and(|c| c.is_ascii_lowercase()) || !chars.all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err(DataSourceError::InvalidConfig(format!("metrics id `{}` must start with a lowercase letter, followed by letters, digits or underscores", input.id)));
+            }
+            if !ids.insert(&input.id) {
+                return Err(DataSourceError::InvalidConfig(format!("Duplicate metrics id: {}", input.id)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// A standard metric attached by another data source, summarized with anomaly detection to keep the prompt compact
+    pub fn attached(order_no: u8, metric_identifier: &str, metric_namespace: &str, metric_name: &str, stats: &[&str], dimensions: BTreeMap<String, String>, options: &SourceOptions) -> Self {
+        CloudwatchMetricConfig {
+            order_no,
+            dimensions,
+            metric_identifier: metric_identifier.to_string(),
+            metric_namespace: metric_namespace.to_string(),
+            metric_name: metric_name.to_string(),
+            metric_stats: Some(stats.iter().map(|stat| stat.to_string()).collect()),
+            summary: true,
+            detect_anomalies: true,
+            options: options.clone(),
+            ..CloudwatchMetricConfig::default()
+        }
+    }
+
+    fn stats(&self) -> Result<Vec<String>, DataSourceError> {
+        match (&self.metric_stats, &self.metric_stat) {
+            (Some(stats), _) if !stats.is_empty() => Ok(stats.clone()),
+            (_, Some(stat)) => Ok(vec![stat.clone()]),
+            _ => Err(DataSourceError::InvalidConfig("Either metric_stat or metric_stats must be provided".to_string()))
+        }
+    }
+}
+
+/// The `dimension_name`/`dimension_value` pair comes first, followed by the `dimensions` table
+fn merge_dimensions(name: &str, value: &str, dimensions: &BTreeMap<String, String>) -> Result<Vec<Dimension>, DataSourceError> {
+    let mut merged = Vec::new();
+
+    match (name.is_empty(), value.is_empty()) {
+        (false, false) => merged.push(Dimension::builder().name(name).value(value).build()),
+        (true, true) => {},
+        _ => return Err(DataSourceError::InvalidConfig("dimension_name and dimension_value must be provided together".to_string()))
+    }
+
+    merged.extend(dimensions.iter().map(|(name, value)| Dimension::builder().name(name).value(value).build()));
+    Ok(merged)
+}
+
+/// Every `[[cloudwatch_metric]]` shares the same batch, so that their queries are sent together
+pub fn build_data_sources(values: Vec<toml::Value>) -> Result<Vec<Box<dyn DataSource>>, Box<dyn Error>> {
+    let configs = values.into_iter()
+        .map(|value| value.try_into::<CloudwatchMetricConfig>())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut identifiers = HashSet::new();
+    for config in &configs {
+        if !identifiers.insert(&config.metric_identifier) {
+            return Err(format!("Duplicate metric_identifier: {}", config.metric_identifier).into());
+        }
+        config.validate_input_ids()?;
+    }
+
+    let batch = Rc::new(MetricBatch {
+        configs,
+        results: OnceCell::new(),
+    });
+
+    Ok((0..batch.configs.len())
+        .map(|index| Box::new(CloudwatchMetricSource { index, batch: batch.clone() }) as Box<dyn DataSource>)
+        .collect())
+}
+
+type MetricResults = RefCell<HashMap<usize, Result<Vec<PromptData>, DataSourceError>>>;
+
+struct MetricBatch {
+    configs: Vec<CloudwatchMetricConfig>,
+    /// Filled in by whichever source is fetched first, then taken by each source
+    results: OnceCell<MetricResults>,
+}
+
+impl MetricBatch {
+    async fn take(&self, index: usize, context: &AppContext) -> Result<Vec<PromptData>, DataSourceError> {
+        let results = self.results.get_or_init(|| self.fetch_all(context)).await;
+
+        results.borrow_mut()
+            .remove(&index)
+            .unwrap_or(Err(DataSourceError::MissingField("Metric data")))
+    }
+
+    async fn fetch_all(&self, context: &AppContext) -> MetricResults {
+        // Queries can only be sent together when using the same profile/region
+        let mut groups: HashMap<AwsScope, Vec<usize>> = HashMap::new();
+        for (index, config) in self.configs.iter().enumerate() {
+            groups.entry(config.options.scope()).or_default().push(index);
+        }
+
+        let fetches = groups.into_iter().map(|(scope, indexes)| async move {
+            let client = context.aws.cloudwatch(&scope).await;
+            let ec2_client = context.aws.ec2(&scope).await;
+            let configs: Vec<&CloudwatchMetricConfig> = indexes.iter().map(|index| &self.configs[*index]).collect();
+
+            match fetch_data(client, ec2_client, &configs, &context.range, context.baseline.as_ref(), context.now).await {
+                Ok(results) => indexes.into_iter()
+                    .zip(results)
+                    .map(|(index, result)| (index, result.map_err(DataSourceError::from)))
+                    .collect::<Vec<_>>(),
+                Err(err) => indexes.into_iter()
+                    .map(|index| (index, Err(DataSourceError::Other(err.to_string().into()))))
+                    .collect()
+            }
+        });
+
+        RefCell::new(join_all(fetches).await.into_iter().flatten().collect())
+    }
+}
+
+struct CloudwatchMetricSource {
+    index: usize,
+    batch: Rc<MetricBatch>,
+}
+
+impl DataSource for CloudwatchMetricSource {
+    fn name(&self) -> &str {
+        "Cloudwatch metric"
+    }
+
+    fn order_no(&self) -> u8 {
+        self.batch.configs[self.index].order_no
+    }
+
+    fn options(&self) -> &SourceOptions {
+        &self.batch.configs[self.index].options
+    }
+
+    fn resource_dimensions<'a>(&'a self, context: &'a AppContext) -> LocalBoxFuture<'a, Result<Vec<(String, String)>, DataSourceError>> {
+        Box::pin(async move {
+            let config = &self.batch.configs[self.index];
+            let ec2_client = context.aws.ec2(&config.options.scope()).await;
+            Ok(fetch_resource_dimensions(&ec2_client, config).await?)
+        })
+    }
+
+    fn fetch_data<'a>(&'a self, context: &'a AppContext) -> LocalBoxFuture<'a, Result<Vec<PromptData>, DataSourceError>> {
+        Box::pin(self.batch.take(self.index, context))
+    }
+}
 
 pub trait CloudwatchClient {
-    async fn get_metric_data(&self, start_time: DateTime, end_time: DateTime, query: MetricDataQuery) -> Result<GetMetricDataOutput, Box<dyn Error>>;
+    async fn get_metric_data(&self, start_time: DateTime, end_time: DateTime, queries: Vec<MetricDataQuery>, next_token: Option<String>) -> Result<GetMetricDataOutput, Box<dyn Error>>;
 }
 
 impl CloudwatchClient for Client {
-    async fn get_metric_data(&self, start_time: DateTime, end_time: DateTime, query: MetricDataQuery) -> Result<GetMetricDataOutput, Box<dyn Error>> {
+    async fn get_metric_data(&self, start_time: DateTime, end_time: DateTime, queries: Vec<MetricDataQuery>, next_token: Option<String>) -> Result<GetMetricDataOutput, Box<dyn Error>> {
         Ok(self.get_metric_data()
             .start_time(start_time)
             .end_time(end_time)
-            .metric_data_queries(query)
+            .set_metric_data_queries(Some(queries))
+            .set_next_token(next_token)
             .send()
             .await?)
     }
 }
 
-pub async fn fetch_data(client: impl CloudwatchClient, ec2_client: impl Ec2Client, config: &CloudwatchMetricConfig, range: &DateTimeRange) -> Result<Vec<PromptData>, Box<dyn Error>> {
-    let mut prompt_data_vec: Vec<PromptData> = Vec::new();
+/// Results and messages of every page of a GetMetricData request
+#[derive(Default)]
+struct MetricData {
+    results: Vec<MetricDataResult>,
+    messages: Vec<MessageData>,
+}
 
-    for dimension in build_dimension(ec2_client, config).await? {
-        let metric = Metric::builder()
-            .metric_name(&config.metric_name)
-            .namespace(&config.metric_namespace)
-            .dimensions(dimension.clone())
-            .build();
+impl MetricData {
+    /// Status of each query is taken from the last page it appeared on
+    fn status(&self) -> String {
+        let mut statuses: HashMap<Option<&str>, &StatusCode> = HashMap::new();
+        for result in &self.results {
+            if let Some(status) = result.status_code() {
+                statuses.insert(result.id(), status);
+            }
+        }
 
-        let metric_stat = MetricStat::builder()
-            .metric(metric)
-            .stat(&config.metric_stat)
-            .period(60)
-            .build();
+        let mut incomplete: Vec<&str> = statuses.values()
+            .filter(|status| **status != &StatusCode::Complete)
+            .map(|status| status.as_str())
+            .collect();
+        incomplete.sort();
+        incomplete.dedup();
 
-        let query = MetricDataQuery::builder()
-            .id(&config.metric_identifier)
-            .metric_stat(metric_stat)
-            .build();
+        match incomplete.is_empty() {
+            true => StatusCode::Complete.as_str().to_string(),
+            false => incomplete.join(", ")
+        }
+    }
+
+    /// Only keeps the results of the given columns, messages of the whole request are kept
+    fn select(&self, columns: &[Column]) -> MetricData {
+        MetricData {
+            results: self.results.iter()
+                .filter(|result| columns.iter().any(|column| Some(column.id.as_str()) == result.id()))
+                .cloned()
+                .collect(),
+            messages: self.messages.clone(),
+        }
+    }
 
-        let start_time = DateTime::from_millis(range.start_time);
-        let end_time = DateTime::from_millis(range.end_time);
+    fn message_lines(&self) -> Vec<String> {
+        let mut lines: Vec<String> = Vec::new();
+        let messages = self.messages.iter()
+            .chain(self.results.iter().flat_map(|result| result.messages()));
 
-        let response = client.get_metric_data(start_time, end_time, query).await?;
+        for message in messages {
+            let line = format!("Message: [{}] {}", message.code().unwrap_or_default(), message.value().unwrap_or_default());
+            if !lines.contains(&line) {
+                lines.push(line);
+            }
+        }
 
-        prompt_data_vec.push(PromptData {
-            description: build_description(config, dimension),
-            data: extract_to_csv(range, response)?
-        });
+        lines
+    }
+}
+
+async fn get_all_metric_data(client: &impl CloudwatchClient, start_time: DateTime, end_time: DateTime, queries: Vec<MetricDataQuery>) -> Result<MetricData, Box<dyn Error>> {
+    let pages = fetch_all_pages(|next_token| client.get_metric_data(start_time, end_time, queries.clone(), next_token), GetMetricDataOutput::next_token).await?;
+
+    let mut metric_data = MetricData::default();
+    for page in pages {
+        metric_data.results.extend(page.metric_data_results.unwrap_or_default());
+        metric_data.messages.extend(page.messages.unwrap_or_default());
+    }
+
+    Ok(metric_data)
+}
+
+/// Keeps the number of rows per metric reasonable, when no period is configured
+const DEFAULT_MAX_DATAPOINTS: i64 = 120;
+
+const PERIODS: [i32; 12] = [60, 120, 300, 600, 900, 1800, 3600, 7200, 10800, 21600, 43200, 86400];
+
+const DAY_MILLIS: i64 = 24 * 60 * 60 * 1000;
+
+/// GetMetricData accepts at most 500 queries per request
+const MAX_QUERIES_PER_REQUEST: usize = 500;
+
+/// Queries of one dimension of a configured metric, one per stat, or of an expression and its inputs
+struct MetricQueries {
+    description: Vec<String>,
+    columns: Vec<Column>,
+    queries: Vec<MetricDataQuery>,
+}
+
+/// A query returned as CSV column(s)
+struct Column {
+    /// Generated from the position of the query in the batch, so that it is unique and valid whatever the metric identifier
+    id: String,
+    /// Without a header, each series of the query gets a column named after its label, e.g. for `SEARCH(...)`
+    header: Option<String>,
+    /// The metric identifier, naming a series without a header nor a label
+    label: String,
+}
+
+/// Fetches every metric with as few requests as possible, returning the result of each metric in order
+/// With a `baseline`, the same queries are also sent for that window to be compared with
+pub async fn fetch_data(client: impl CloudwatchClient, ec2_client: impl Ec2Client, configs: &[&CloudwatchMetricConfig], range: &DateTimeRange, baseline: Option<&DateTimeRange>, now: i64) -> Result<Vec<Result<Vec<PromptData>, Box<dyn Error>>>, Box<dyn Error>> {
+    let mut planned: Vec<Result<Vec<MetricQueries>, Box<dyn Error>>> = Vec::new();
+    for (index, config) in configs.iter().enumerate() {
+        planned.push(build_queries(&ec2_client, config, &format!("m{index}"), range, baseline, now).await);
+    }
+
+    let queries = batch_queries(planned.iter()
+        .flatten()
+        .flatten()
+        .map(|metric_queries| metric_queries.queries.as_slice()));
+
+    let metric_data = fetch_metric_data(&client, &queries, range).await?;
+    let baseline_data = match baseline {
+        Some(baseline) => Some((baseline, fetch_metric_data(&client, &queries, baseline).await?)),
+        None => None
+    };
+
+    Ok(configs.iter()
+        .zip(planned)
+        .map(|(config, planned)| {
+            planned?.into_iter()
+                .map(|metric_queries| {
+                    let baseline = baseline_data.as_ref().map(|(baseline, data)| (*baseline, data));
+                    build_prompt_data(config, range, &metric_data, baseline, metric_queries)
+                })
+                .collect()
+        })
+        .collect())
+}
+
+/// Fetches the metrics attached by another data source, with the clients of its scope
+pub async fn fetch_attached(context: &AppContext, options: &SourceOptions, configs: &[CloudwatchMetricConfig]) -> Result<Vec<PromptData>, Box<dyn Error>> {
+    if configs.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let scope = options.scope();
+    let client = context.aws.cloudwatch(&scope).await;
+    let ec2_client = context.aws.ec2(&scope).await;
+    let configs: Vec<&CloudwatchMetricConfig> = configs.iter().collect();
+
+    let mut prompt_data_vec = Vec::new();
+    for result in fetch_data(client, ec2_client, &configs, &context.range, context.baseline.as_ref(), context.now).await? {
+        prompt_data_vec.extend(result?);
     }
 
     Ok(prompt_data_vec)
 }
 
-fn build_description(config: &CloudwatchMetricConfig, dimension: Dimension) -> Vec<String> {
+/// Fills requests up to the query limit, an expression staying in the same request as its inputs
+fn batch_queries<'a>(groups: impl Iterator<Item = &'a [MetricDataQuery]>) -> Vec<Vec<MetricDataQuery>> {
+    let mut batches: Vec<Vec<MetricDataQuery>> = Vec::new();
+    for group in groups {
+        match batches.last_mut() {
+            Some(batch) if batch.len() + group.len() <= MAX_QUERIES_PER_REQUEST => batch.extend_from_slice(group),
+            _ => batches.push(group.to_vec()),
+        }
+    }
+
+    batches
+}
+
+async fn fetch_metric_data(client: &impl CloudwatchClient, batches: &[Vec<MetricDataQuery>], range: &DateTimeRange) -> Result<MetricData, Box<dyn Error>> {
+    let start_time = DateTime::from_millis(range.start_time);
+    let end_time = DateTime::from_millis(range.end_time);
+
+    let mut metric_data = MetricData::default();
+    for batch in batches {
+        let chunk_data = get_all_metric_data(client, start_time, end_time, batch.clone()).await?;
+        metric_data.results.extend(chunk_data.results);
+        metric_data.messages.extend(chunk_data.messages);
+    }
+
+    Ok(metric_data)
+}
+
+async fn build_queries(ec2_client: &impl Ec2Client, config: &CloudwatchMetricConfig, id: &str, range: &DateTimeRange, baseline: Option<&DateTimeRange>, now: i64) -> Result<Vec<MetricQueries>, Box<dyn Error>> {
+    config.validate()?;
+
+    let period = resolve_period(config, range, baseline, now);
+
+    if let Some(expression) = &config.expression {
+        return Ok(vec![build_expression_queries(config, expression, id, period)?]);
+    }
+
+    let stats = config.stats()?;
+    let dimension_sets = build_dimensions(ec2_client, config).await?;
+
+    Ok(dimension_sets.into_iter()
+        .enumerate()
+        .map(|(dimension_index, dimensions)| {
+            // A column per stat, single stat keeps the generic `value` header
+            let columns: Vec<Column> = stats.iter()
+                .enumerate()
+                .map(|(stat_index, stat)| {
+                    let header = if stats.len() == 1 { "value".to_string() } else { stat.clone() };
+                    Column {
+                        id: format!("{id}_{dimension_index}_{stat_index}"),
+                        header: Some(header),
+                        label: config.metric_identifier.clone(),
+                    }
+                })
+                .collect();
+
+            let metric = Metric::builder()
+                .metric_name(&config.metric_name)
+                .namespace(&config.metric_namespace)
+                .set_dimensions(Some(dimensions.clone()))
+                .build();
+
+            let queries = stats.iter()
+                .zip(&columns)
+                .map(|(stat, column)| {
+                    let metric_stat = MetricStat::builder()
+                        .metric(metric.clone())
+                        .stat(stat)
+                        .period(period)
+                        .build();
+
+                    MetricDataQuery::builder()
+                        .id(&column.id)
+                        .metric_stat(metric_stat)
+                        .build()
+                })
+                .collect();
+
+            MetricQueries {
+                description: build_description(config, &dimensions, period),
+                columns,
+                queries,
+            }
+        })
+        .collect())
+}
+
+/// Input metrics are only used by the expression, their ids are prefixed with the expression's to be unique across the batch
+fn build_expression_queries(config: &CloudwatchMetricConfig, expression: &str, id: &str, period: i32) -> Result<MetricQueries, Box<dyn Error>> {
+    let mut queries: Vec<MetricDataQuery> = config.metrics.iter()
+        .map(|input| {
+            let metric = Metric::builder()
+                .metric_name(&input.metric_name)
+                .namespace(&input.metric_namespace)
+                .set_dimensions(Some(input.dimensions()?))
+                .build();
+
+            let metric_stat = MetricStat::builder()
+                .metric(metric)
+                .stat(&input.metric_stat)
+                .period(period)
+                .build();
+
+            let query = MetricDataQuery::builder()
+                .id(format!("{id}_{}", input.id))
+                .metric_stat(metric_stat)
+                .return_data(false)
+                .build();
+            Ok(query)
+        })
+        .collect::<Result<_, DataSourceError>>()?;
+
+    // SEARCH has its own period, only math on the input metrics needs one
+    let period = (!config.metrics.is_empty()).then_some(period);
+    let ids: Vec<&str> = config.metrics.iter().map(|input| input.id.as_str()).collect();
+
+    queries.push(MetricDataQuery::builder()
+        .id(id)
+        .expression(prefix_ids(expression, &ids, id))
+        .set_period(period)
+        .build());
+
+    Ok(MetricQueries {
+        description: build_expression_description(config, expression, period)?,
+        columns: vec![Column { id: id.to_string(), header: None, label: config.metric_identifier.clone() }],
+        queries,
+    })
+}
+
+/// Prefixes every reference to `ids` in the expression, leaving quoted text such as search terms untouched
+fn prefix_ids(expression: &str, ids: &[&str], prefix: &str) -> String {
+    let mut prefixed = String::new();
+    let mut token = String::new();
+    let mut quote: Option<char> = None;
+
+    let flush = |token: &mut String, prefixed: &mut String| {
+        if ids.contains(&token.as_str()) {
+            prefixed.push_str(&format!("{prefix}_"));
+        }
+        prefixed.push_str(token);
+        token.clear();
+    };
+
+    for char in expression.chars() {
+        match quote {
+            Some(open) => {
+                prefixed.push(char);
+                if char == open {
+                    quote = None;
+                }
+            },
+            None if char.is_ascii_alphanumeric() || char == '_' => token.push(char),
+            None => {
+                flush(&mut token, &mut prefixed);
+                if char == '\'' || char == '"' {
+                    quote = Some(char);
+                }
+                prefixed.push(char);
+            }
+        }
+    }
+    flush(&mut token, &mut prefixed);
+
+    prefixed
+}
+
+fn build_prompt_data(config: &CloudwatchMetricConfig, range: &DateTimeRange, metric_data: &MetricData, baseline: Option<(&DateTimeRange, &MetricData)>, metric_queries: MetricQueries) -> Result<PromptData, Box<dyn Error>> {
+    let metric_data = metric_data.select(&metric_queries.columns);
+
+    let mut description = metric_queries.description;
+    description.push(format!("Status: [{}]", metric_data.status()));
+    description.extend(metric_data.message_lines());
+
+    let table = MetricTable::new(&metric_queries.columns, &metric_data.results)?;
+    description.extend(build_anomaly_lines(config, range, &table));
+
+    let mut data = match config.summary {
+        true => extract_summary(config, range, &table)?,
+        false => extract_to_csv(range, &table)?,
+    };
+
+    if let Some((baseline_range, baseline_data)) = baseline {
+        let baseline_table = MetricTable::new(&metric_queries.columns, &baseline_data.select(&metric_queries.columns).results)?;
+        description.push(format!("Baseline: [{}]", baseline_range.describe()));
+
+        let comparison = compare_tables(&table, &baseline_table)?;
+        data = data.map(|data| format!("{data}\nBaseline comparison:\n{comparison}"));
+    }
+
+    Ok(PromptData { description, data })
+}
+
+/// The configured period, or one that suits both windows since they share the queries, e.g. an older baseline needs a coarser period
+fn resolve_period(config: &CloudwatchMetricConfig, range: &DateTimeRange, baseline: Option<&DateTimeRange>, now: i64) -> i32 {
+    let max_datapoints = config.max_datapoints.unwrap_or(DEFAULT_MAX_DATAPOINTS);
+
+    config.period.unwrap_or_else(|| {
+        std::iter::once(range)
+            .chain(baseline)
+            .map(|window| auto_period(window, max_datapoints, now))
+            .max()
+            .unwrap_or_default()
+    })
+}
+
+/// Picks the smallest period that keeps the range within `max_datapoints`
+fn auto_period(range: &DateTimeRange, max_datapoints: i64, now: i64) -> i32 {
+    let duration = (range.end_time - range.start_time) / 1000;
+    let max_datapoints = max_datapoints.max(1);
+    let minimum = minimum_period(now - range.start_time);
+
+    PERIODS.into_iter()
+        .find(|period| *period >= minimum && duration / *period as i64 <= max_datapoints)
+        .unwrap_or_else(|| {
+            let max_span = max_datapoints * 86400;
+            let days = (duration + max_span - 1) / max_span;
+            (days * 86400) as i32
+        })
+}
+
+/// Cloudwatch only retains 1 minute data for 15 days, and 5 minute data for 63 days
+fn minimum_period(age: i64) -> i32 {
+    if age > 63 * DAY_MILLIS {
+        3600
+    } else if age > 15 * DAY_MILLIS {
+        300
+    } else {
+        60
+    }
+}
+
+fn build_description(config: &CloudwatchMetricConfig, dimensions: &[Dimension], period: i32) -> Vec<String> {
     let mut description = vec![
         format!("Information: [Cloudwatch {}]", &config.metric_namespace),
         format!("Metric: [`{}`]", &config.metric_name),
-        format!("Dimension: [`{}:{}`]", &dimension.name.unwrap(), &dimension.value.unwrap())
     ];
 
+    match dimensions.len() {
+        0 => {},
+        1 => description.push(format!("Dimension: [{}]", format_dimensions(dimensions))),
+        _ => description.push(format!("Dimensions: [{}]", format_dimensions(dimensions))),
+    }
+    description.push(format!("Period: [{period} seconds]"));
+
     if let Some(unit) = &config.metric_unit {
         description.push(format!("Unit: {}", unit));
     }
@@ -73,62 +673,283 @@ fn build_description(config: &CloudwatchMetricConfig, dimension: Dimension) -> V
     description
 }
 
-fn extract_to_csv(range: &DateTimeRange, output: GetMetricDataOutput) -> Result<Option<String>, Box<dyn Error>> {
+fn build_expression_description(config: &CloudwatchMetricConfig, expression: &str, period: Option<i32>) -> Result<Vec<String>, DataSourceError> {
+    let mut description = vec![
+        "Information: [Cloudwatch Expression]".to_string(),
+        format!("Metric: [`{}`]", &config.metric_identifier),
+        format!("Expression: [`{expression}`]"),
+    ];
+
+    for input in &config.metrics {
+        let mut line = format!("Input: [`{}` = {} {} {}", input.id, input.metric_namespace, input.metric_name, input.metric_stat);
+        let dimensions = input.dimensions()?;
+        if !dimensions.is_empty() {
+            line.push_str(&format!(" {}", format_dimensions(&dimensions)));
+        }
+        line.push(']');
+        description.push(line);
+    }
+
+    if let Some(period) = period {
+        description.push(format!("Period: [{period} seconds]"));
+    }
+
+    if let Some(unit) = &config.metric_unit {
+        description.push(format!("Unit: {}", unit));
+    }
+
+    Ok(description)
+}
+
+fn format_dimensions(dimensions: &[Dimension]) -> String {
+    dimensions.iter()
+        .map(|dimension| format!("`{}:{}`", dimension.name().unwrap_or_default(), dimension.value().unwrap_or_default()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Default number of spikes listed per column in summary mode
+const DEFAULT_TOP_SPIKES: usize = 3;
+
+/// Datapoints of every column, merged by timestamp
+#[derive(Default)]
+struct MetricTable {
+    headers: Vec<String>,
+    rows: BTreeMap<i64, Vec<Option<f64>>>,
+}
+
+impl MetricTable {
+    fn new(columns: &[Column], results: &[MetricDataResult]) -> Result<MetricTable, Box<dyn Error>> {
+        let mut table = MetricTable {
+            headers: columns.iter().filter_map(|column| column.header.clone()).collect(),
+            ..MetricTable::default()
+        };
+
+        // Stats and pages don't always have data on the same timestamps, merge them by timestamp
+        for result in results {
+            let Some(column) = columns.iter().find(|column| Some(column.id.as_str()) == result.id()) else {
+                continue;
+            };
+
+            let header = match &column.header {
+                Some(header) => header.as_str(),
+                None => result.label().filter(|label| !label.is_empty()).unwrap_or(&column.label),
+            };
+            let position = match table.headers.iter().position(|existing| existing == header) {
+                Some(position) => position,
+                None => {
+                    table.headers.push(header.to_string());
+                    table.headers.len() - 1
+                }
+            };
+
+            for (timestamp, value) in result.timestamps().iter().zip(result.values()) {
+                let row = table.rows.entry(timestamp.to_millis()?).or_default();
+                if row.len() <= position {
+                    row.resize(position + 1, None);
+                }
+                row[position] = Some(*value);
+            }
+        }
+
+        for values in table.rows.values_mut() {
+            values.resize(table.headers.len(), None);
+        }
+
+        Ok(table)
+    }
+
+    fn series_of(&self, header: &str) -> Vec<(i64, f64)> {
+        match self.headers.iter().position(|existing| existing == header) {
+            Some(position) => self.series(position),
+            None => Vec::new()
+        }
+    }
+
+    fn series(&self, position: usize) -> Vec<(i64, f64)> {
+        self.rows.iter()
+            .filter_map(|(timestamp, values)| values[position].map(|value| (*timestamp, value)))
+            .collect()
+    }
+
+    /// Averages consecutive rows, so that at most `max_rows` are left
+    fn downsample(&self, max_rows: usize) -> MetricTable {
+        let rows: Vec<(&i64, &Vec<Option<f64>>)> = self.rows.iter().collect();
+        let bucket_size = rows.len().div_ceil(max_rows.max(1)).max(1);
+
+        let rows = rows.chunks(bucket_size)
+            .map(|bucket| {
+                let values = (0..self.headers.len())
+                    .map(|position| {
+                        let values: Vec<f64> = bucket.iter().filter_map(|(_, values)| values[position]).collect();
+                        (!values.is_empty()).then(|| round(values.iter().sum::<f64>() / values.len() as f64))
+                    })
+                    .collect();
+                (*bucket[0].0, values)
+            })
+            .collect();
+
+        MetricTable { headers: self.headers.clone(), rows }
+    }
+
+    fn to_csv(&self, range: &DateTimeRange) -> Result<String, Box<dyn Error>> {
+        let mut csv_writer = Writer::from_writer(Vec::new());
+
+        let mut header_record = vec!["timestamp".to_string()];
+        header_record.extend(self.headers.iter().cloned());
+        csv_writer.write_record(header_record)?;
+
+        for (timestamp, values) in &self.rows {
+            let mut record = vec![range.format_timestamp(*timestamp)];
+            record.extend(values.iter().map(|value| value.map(|v| v.to_string()).unwrap_or_default()));
+            csv_writer.write_record(record)?;
+        }
+
+        Ok(String::from_utf8(csv_writer.into_inner()?)?)
+    }
+}
+
+/// Statistics of every column of either window, with their deltas
+fn compare_tables(current: &MetricTable, baseline: &MetricTable) -> Result<String, Box<dyn Error>> {
     let mut csv_writer = Writer::from_writer(Vec::new());
-    csv_writer.write_record(["timestamp", "value"])?;
-    let mut rows = 0;
+    csv_writer.write_record(COMPARISON_HEADERS)?;
 
-    for result in output.metric_data_results() {
-        let timestamps = result.timestamps();
-        let values = result.values();
+    let mut headers = current.headers.clone();
+    headers.extend(baseline.headers.iter().filter(|header| !current.headers.contains(header)).cloned());
 
-        for (timestamp, value) in timestamps.iter().rev().zip(values.iter().rev()) {
-            let utc_time = chrono::DateTime::from_timestamp_millis(timestamp.to_millis()?).unwrap();
-            let local_time = utc_time.with_timezone(&range.time_zone);
+    for header in headers {
+        let current_summary = summarize(&current.series_of(&header));
+        let baseline_summary = summarize(&baseline.series_of(&header));
 
-            let t = format!("{local_time}");
-            let v = value.clone().to_string();
-            csv_writer.write_record(&[t, v])?;
-            rows += 1;
+        for record in comparison_records(&header, current_summary.as_ref(), baseline_summary.as_ref()) {
+            csv_writer.write_record(record)?;
         }
     }
 
-    if rows ==  0 {
+    Ok(String::from_utf8(csv_writer.into_inner()?)?)
+}
+
+/// Lists anomalies of every column, when detection or a threshold is configured
+fn build_anomaly_lines(config: &CloudwatchMetricConfig, range: &DateTimeRange, table: &MetricTable) -> Vec<String> {
+    let thresholds = Thresholds {
+        warn_above: config.warn_above,
+        warn_below: config.warn_below,
+    };
+    if !config.detect_anomalies && thresholds.warn_above.is_none() && thresholds.warn_below.is_none() {
+        return Vec::new();
+    }
+
+    let lines: Vec<String> = table.headers.iter()
+        .enumerate()
+        .flat_map(|(position, header)| {
+            detect(&table.series(position), &thresholds, config.detect_anomalies)
+                .into_iter()
+                .map(move |anomaly| format!("- `{header}` {}", anomaly.describe(|timestamp| range.format_timestamp(timestamp))))
+        })
+        .collect();
+
+    match lines.is_empty() {
+        true => vec!["Anomalies detected: [none]".to_string()],
+        false => [vec!["Anomalies detected:".to_string()], lines].concat()
+    }
+}
+
+fn extract_to_csv(range: &DateTimeRange, table: &MetricTable) -> Result<Option<String>, Box<dyn Error>> {
+    if table.rows.is_empty() {
         return Ok(Some("No applicable data found\n".to_string()))
     }
 
-    let csv = String::from_utf8(csv_writer.into_inner()?)?;
-    Ok(Some(csv))
+    Ok(Some(table.to_csv(range)?))
 }
 
-async fn build_dimension(ec2_client: impl Ec2Client, config: &CloudwatchMetricConfig) -> Result<Vec<Dimension>, Box<dyn Error>> {
-    // If EC2, fetch convert instance name to instance id first
-    if config.metric_namespace == "AWS/EC2" {
-        let instances = fetch_instances(ec2_client, &config.dimension_value).await?;
+/// Statistics and spikes of each column, optionally followed by a downsampled series
+fn extract_summary(config: &CloudwatchMetricConfig, range: &DateTimeRange, table: &MetricTable) -> Result<Option<String>, Box<dyn Error>> {
+    if table.rows.is_empty() {
+        return Ok(Some("No applicable data found\n".to_string()))
+    }
+
+    let mut summary_writer = Writer::from_writer(Vec::new());
+    let mut spikes_writer = Writer::from_writer(Vec::new());
 
-        return Ok(instances.into_iter()
-            .map(|instance| {
-                return Dimension::builder()
-                    .name(&config.dimension_name)
-                    .value(instance.instance_id().unwrap().to_string())
-                    .build()
-            })
-            .collect());
+    let mut header_record = vec!["column"];
+    header_record.extend(SUMMARY_HEADERS);
+    summary_writer.write_record(header_record)?;
+    spikes_writer.write_record(["timestamp", "column", "value"])?;
+
+    for (position, header) in table.headers.iter().enumerate() {
+        let series = table.series(position);
+
+        if let Some(summary) = summarize(&series) {
+            let mut record = vec![header.clone()];
+            record.extend(summary.to_record());
+            summary_writer.write_record(record)?;
+        }
+
+        for (timestamp, value) in top_spikes(&series, config.top_spikes.unwrap_or(DEFAULT_TOP_SPIKES)) {
+            spikes_writer.write_record([range.format_timestamp(timestamp), header.clone(), round(value).to_string()])?;
+        }
+    }
+
+    let mut data = String::from("Summary:\n");
+    data.push_str(&String::from_utf8(summary_writer.into_inner()?)?);
+    data.push_str("\nTop spikes:\n");
+    data.push_str(&String::from_utf8(spikes_writer.into_inner()?)?);
+
+    if let Some(max_rows) = config.downsample {
+        data.push_str("\nDownsampled series:\n");
+        data.push_str(&table.downsample(max_rows).to_csv(range)?);
     }
 
-    Ok(vec![Dimension::builder()
-        .name(&config.dimension_name)
-        .value(&config.dimension_value)
-        .build()])
+    Ok(Some(data))
+}
+
+/// A set of dimensions per metric to fetch
+/// The dimensions as queried, an EC2 instance name resolved to the ids of the instances
+pub async fn fetch_resource_dimensions(ec2_client: &impl Ec2Client, config: &CloudwatchMetricConfig) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    Ok(build_dimensions(ec2_client, config).await?
+        .iter()
+        .flatten()
+        .filter_map(|dimension| Some((dimension.name()?.to_string(), dimension.value()?.to_string())))
+        .collect())
+}
+
+async fn build_dimensions(ec2_client: &impl Ec2Client, config: &CloudwatchMetricConfig) -> Result<Vec<Vec<Dimension>>, Box<dyn Error>> {
+    let dimensions = config.dimensions()?;
+
+    // If EC2, fetch convert instance name to instance id first, with a set per matching instance
+    let instance_name = dimensions.iter()
+        .find(|dimension| config.metric_namespace == "AWS/EC2" && dimension.name() == Some("InstanceId"))
+        .and_then(|dimension| dimension.value());
+
+    let Some(instance_name) = instance_name else {
+        return Ok(vec![dimensions]);
+    };
+
+    let instances = fetch_instances(ec2_client, &instance_name.to_string()).await?;
+
+    Ok(instances.iter()
+        .filter_map(|instance| instance.instance_id())
+        .map(|instance_id| {
+            dimensions.iter()
+                .map(|dimension| match dimension.name() {
+                    Some("InstanceId") => Dimension::builder()
+                        .name("InstanceId")
+                        .value(instance_id)
+                        .build(),
+                    _ => dimension.clone()
+                })
+                .collect()
+        })
+        .collect())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::datasource::ec2::tests::MockEc2Client;
-    use aws_sdk_cloudwatch::types::MetricDataResult;
     use aws_smithy_types::date_time::Format;
     use chrono_tz::Tz;
+    use std::cell::RefCell;
 
     #[test]
     fn test_build_description() {
@@ -145,44 +966,109 @@ mod tests {
             .value("ec2-instance-name")
             .build();
 
-        let description = build_description(&config, dimension);
+        let description = build_description(&config, &[dimension], 60);
 
-        assert_eq!(description.len(), 3);
+        assert_eq!(description.len(), 4);
         assert_eq!(description[0], "Information: [Cloudwatch AWS/EC2]".to_string());
         assert_eq!(description[1], "Metric: [`CPUUtilization`]".to_string());
         assert_eq!(description[2], "Dimension: [`InstanceId:ec2-instance-name`]".to_string());
+        assert_eq!(description[3], "Period: [60 seconds]".to_string());
     }
 
-    #[test]
-    fn test_extract_to_csv_empty_row() {
-        let range = DateTimeRange::default();
-        let output = GetMetricDataOutput::builder().build();
+    #[tokio::test]
+    async fn test_build_dimensions() {
+        let ec2_client = MockEc2Client {
+            instance_id: "ec2-instance-id".to_string()
+        };
+        let config = CloudwatchMetricConfig {
+            metric_namespace: "AWS/EC2".to_string(),
+            metric_name: "CPUUtilization".to_string(),
+            dimension_name: "InstanceId".to_string(),
+            dimension_value: "ec2-instance-name".to_string(),
+            dimensions: BTreeMap::from([("AutoScalingGroupName".to_string(), "web-asg".to_string())]),
+            ..CloudwatchMetricConfig::default()
+        };
 
-        let result = extract_to_csv(&range, output).expect("Should extract to csv");
+        let dimension_sets = build_dimensions(&ec2_client, &config).await.expect("Should build dimensions");
+        let description = build_description(&config, &dimension_sets[0], 60);
 
-        assert_eq!(result, Some("No applicable data found\n".to_string()));
+        assert_eq!(dimension_sets.len(), 1);
+        assert_eq!(description[2], "Dimensions: [`InstanceId:ec2-instance-id`, `AutoScalingGroupName:web-asg`]".to_string());
     }
 
-    struct MockCloudwatchClient {}
-
-    impl CloudwatchClient for MockCloudwatchClient {
-        async fn get_metric_data(&self, _: DateTime, _: DateTime, _: MetricDataQuery) -> Result<GetMetricDataOutput, Box<dyn Error>> {
-            Ok(GetMetricDataOutput::builder()
-                .metric_data_results(MetricDataResult::builder()
-                    .timestamps(date_time("2023-10-12T09:30:00Z"))
-                    .values(1.0)
-
-                    .timestamps(date_time("2023-10-12T10:00:00Z"))
-                    .values(2.0)
-
-                    .timestamps(date_time("2023-10-12T10:30:00Z"))
-                    .values(3.0)
+    #[tokio::test]
+    async fn test_fetch_resource_dimensions() {
+        let ec2_client = MockEc2Client {
+            instance_id: "ec2-instance-id".to_string()
+        };
+        let config = CloudwatchMetricConfig {
+            metric_namespace: "AWS/EC2".to_string(),
+            metric_name: "CPUUtilization".to_string(),
+            dimension_name: "InstanceId".to_string(),
+            dimension_value: "ec2-instance-name".to_string(),
+            ..CloudwatchMetricConfig::default()
+        };
+
+        let dimensions = fetch_resource_dimensions(&ec2_client, &config).await.expect("Should resolve the instance");
+
+        assert_eq!(dimensions, vec![("InstanceId".to_string(), "ec2-instance-id".to_string())]);
+    }
+
+    #[test]
+    fn test_merge_dimensions() {
+        let dimensions = BTreeMap::from([
+            ("TargetGroup".to_string(), "targetgroup/web/456".to_string()),
+            ("LoadBalancer".to_string(), "app/web/123".to_string()),
+        ]);
+
+        let merged = merge_dimensions("", "", &dimensions).expect("Should merge dimensions");
+        let names: Vec<&str> = merged.iter().map(|dimension| dimension.name().unwrap()).collect();
 
+        assert_eq!(names, vec!["LoadBalancer", "TargetGroup"]);
+        assert!(matches!(merge_dimensions("LoadBalancer", "", &dimensions), Err(DataSourceError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_extract_to_csv_empty_row() {
+        let range = DateTimeRange::default();
+        let columns = vec![Column { id: "id_0".to_string(), header: Some("value".to_string()), label: "cpu".to_string() }];
+
+        let table = MetricTable::new(&columns, &[]).unwrap();
+
+        let result = extract_to_csv(&range, &table).expect("Should extract to csv");
+
+        assert_eq!(result, Some("No applicable data found\n".to_string()));
+    }
+
+    struct MockCloudwatchClient {}
+
+    impl CloudwatchClient for MockCloudwatchClient {
+        async fn get_metric_data(&self, _: DateTime, _: DateTime, queries: Vec<MetricDataQuery>, _: Option<String>) -> Result<GetMetricDataOutput, Box<dyn Error>> {
+            let mut output = GetMetricDataOutput::builder();
+
+            // Each stat gets its own multiple of the same series
+            for (index, query) in queries.iter().enumerate() {
+                let factor = (index + 1) as f64;
+
+                output = output.metric_data_results(MetricDataResult::builder()
+                    .id(query.id().unwrap())
+                    .status_code(StatusCode::Complete)
                     .timestamps(date_time("2023-10-12T11:00:00Z"))
-                    .values(4.0)
+                    .values(4.0 * factor)
+
+                    .timestamps(date_time("2023-10-12T10:30:00Z"))
+                    .values(3.0 * factor)
+
+                    .timestamps(date_time("2023-10-12T10:00:00Z"))
+                    .values(2.0 * factor)
+
+                    .timestamps(date_time("2023-10-12T09:30:00Z"))
+                    .values(1.0 * factor)
 
-                    .build())
-                .build())
+                    .build());
+            }
+
+            Ok(output.build())
         }
     }
 
@@ -198,6 +1084,7 @@ mod tests {
             metric_name: "CPUUtilization".to_string(),
             dimension_name: "InstanceId".to_string(),
             dimension_value: "ec2-instance-name".to_string(),
+            metric_stat: Some("Average".to_string()),
             ..CloudwatchMetricConfig::default()
         };
 
@@ -207,24 +1094,636 @@ mod tests {
             time_zone: Tz::Asia__Manila,
         };
 
-        let prompt_data_vec = fetch_data(client, ec2_client, &config, &range).await.expect("Should fetch data");
+        let prompt_data_vec = fetch_data(client, ec2_client, &[&config], &range, None, 0).await
+            .expect("Should fetch data")
+            .remove(0)
+            .expect("Should fetch metric");
 
         let expected = [
             "timestamp,value\n",
-            "2023-10-12 19:00:00 PST,4\n",
-            "2023-10-12 18:30:00 PST,3\n",
+            "2023-10-12 17:30:00 PST,1\n",
             "2023-10-12 18:00:00 PST,2\n",
-            "2023-10-12 17:30:00 PST,1\n"
+            "2023-10-12 18:30:00 PST,3\n",
+            "2023-10-12 19:00:00 PST,4\n"
         ].join("");
 
         assert_eq!(prompt_data_vec.len(), 1);
-        assert_eq!(prompt_data_vec.first().unwrap().description.len(), 3);
+        assert_eq!(prompt_data_vec.first().unwrap().description.len(), 5);
         assert_eq!(prompt_data_vec.first().unwrap().description[0], "Information: [Cloudwatch AWS/EC2]".to_string());
         assert_eq!(prompt_data_vec.first().unwrap().description[1], "Metric: [`CPUUtilization`]".to_string());
         assert_eq!(prompt_data_vec.first().unwrap().description[2], "Dimension: [`InstanceId:ec2-instance-id`]".to_string());
+        assert_eq!(prompt_data_vec.first().unwrap().description[4], "Status: [Complete]".to_string());
         assert_eq!(prompt_data_vec.first().unwrap().data, Some(expected));
     }
 
+    /// Returns the series over two pages, the first one being partial
+    struct PagingCloudwatchClient {
+        last_page_status: StatusCode,
+    }
+
+    impl CloudwatchClient for PagingCloudwatchClient {
+        async fn get_metric_data(&self, _: DateTime, _: DateTime, queries: Vec<MetricDataQuery>, next_token: Option<String>) -> Result<GetMetricDataOutput, Box<dyn Error>> {
+            let id = queries[0].id().unwrap();
+
+            let output = match next_token.as_deref() {
+                None => GetMetricDataOutput::builder()
+                    .metric_data_results(MetricDataResult::builder()
+                        .id(id)
+                        .status_code(StatusCode::PartialData)
+                        .timestamps(date_time("2023-10-12T11:00:00Z"))
+                        .values(4.0)
+                        .timestamps(date_time("2023-10-12T10:30:00Z"))
+                        .values(3.0)
+                        .build())
+                    .next_token("page-2"),
+                Some("page-2") => GetMetricDataOutput::builder()
+                    .metric_data_results(MetricDataResult::builder()
+                        .id(id)
+                        .status_code(self.last_page_status.clone())
+                        .timestamps(date_time("2023-10-12T10:00:00Z"))
+                        .values(2.0)
+                        .timestamps(date_time("2023-10-12T09:30:00Z"))
+                        .values(1.0)
+                        .messages(MessageData::builder()
+                            .code("Warning")
+                            .value("Some data points are missing")
+                            .build())
+                        .build()),
+                Some(token) => panic!("Unexpected token: {token}")
+            };
+
+            Ok(output.build())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_paginated() {
+        let config = CloudwatchMetricConfig {
+            metric_namespace: "AWS/RDS".to_string(),
+            metric_name: "CPUUtilization".to_string(),
+            dimension_name: "DBInstanceIdentifier".to_string(),
+            dimension_value: "rds-instance".to_string(),
+            metric_stat: Some("Average".to_string()),
+            ..CloudwatchMetricConfig::default()
+        };
+        let range = DateTimeRange::default();
+
+        for (last_page_status, expected_status) in [(StatusCode::Complete, "Complete"), (StatusCode::PartialData, "PartialData")] {
+            let client = PagingCloudwatchClient { last_page_status };
+            let ec2_client = MockEc2Client {
+                instance_id: "ec2-instance-id".to_string()
+            };
+
+            let prompt_data_vec = fetch_data(client, ec2_client, &[&config], &range, None, 0).await
+            .expect("Should fetch data")
+            .remove(0)
+            .expect("Should fetch metric");
+
+            let expected = [
+                "timestamp,value\n",
+                "2023-10-12 09:30:00 UTC,1\n",
+                "2023-10-12 10:00:00 UTC,2\n",
+                "2023-10-12 10:30:00 UTC,3\n",
+                "2023-10-12 11:00:00 UTC,4\n"
+            ].join("");
+
+            assert_eq!(prompt_data_vec[0].description[4], format!("Status: [{expected_status}]"));
+            assert_eq!(prompt_data_vec[0].description[5], "Message: [Warning] Some data points are missing".to_string());
+            assert_eq!(prompt_data_vec[0].data, Some(expected));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_multiple_stats() {
+        let client = MockCloudwatchClient {};
+        let ec2_client = MockEc2Client {
+            instance_id: "ec2-instance-id".to_string()
+        };
+
+        let config = CloudwatchMetricConfig {
+            metric_identifier: "rds_cpu".to_string(),
+            metric_namespace: "AWS/RDS".to_string(),
+            metric_name: "CPUUtilization".to_string(),
+            dimension_name: "DBInstanceIdentifier".to_string(),
+            dimension_value: "rds-instance".to_string(),
+            metric_stats: Some(vec!["Average".to_string(), "Maximum".to_string(), "p99".to_string()]),
+            period: Some(1800),
+            ..CloudwatchMetricConfig::default()
+        };
+
+        let range = DateTimeRange {
+            start_time: date_time("2023-10-12T09:30:00Z").to_millis().unwrap(),
+            end_time: date_time("2023-10-12T11:00:00Z").to_millis().unwrap(),
+            time_zone: Tz::UTC,
+        };
+
+        let prompt_data_vec = fetch_data(client, ec2_client, &[&config], &range, None, 0).await
+            .expect("Should fetch data")
+            .remove(0)
+            .expect("Should fetch metric");
+
+        let expected = [
+            "timestamp,Average,Maximum,p99\n",
+            "2023-10-12 09:30:00 UTC,1,2,3\n",
+            "2023-10-12 10:00:00 UTC,2,4,6\n",
+            "2023-10-12 10:30:00 UTC,3,6,9\n",
+            "2023-10-12 11:00:00 UTC,4,8,12\n"
+        ].join("");
+
+        assert_eq!(prompt_data_vec.len(), 1);
+        assert_eq!(prompt_data_vec[0].description[3], "Period: [1800 seconds]".to_string());
+        assert_eq!(prompt_data_vec[0].data, Some(expected));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_without_stat() {
+        let client = MockCloudwatchClient {};
+        let ec2_client = MockEc2Client {
+            instance_id: "ec2-instance-id".to_string()
+        };
+        let config = CloudwatchMetricConfig::default();
+        let range = DateTimeRange::default();
+
+        let err = fetch_data(client, ec2_client, &[&config], &range, None, 0).await
+            .expect("Should fetch data")
+            .remove(0)
+            .expect_err("Should require a stat");
+
+        assert!(matches!(DataSourceError::from(err), DataSourceError::InvalidConfig(_)));
+    }
+
+    /// Records the number of queries of each request
+    #[derive(Default)]
+    struct CountingCloudwatchClient {
+        requests: RefCell<Vec<usize>>,
+    }
+
+    impl CloudwatchClient for &CountingCloudwatchClient {
+        async fn get_metric_data(&self, start_time: DateTime, end_time: DateTime, queries: Vec<MetricDataQuery>, next_token: Option<String>) -> Result<GetMetricDataOutput, Box<dyn Error>> {
+            self.requests.borrow_mut().push(queries.len());
+            MockCloudwatchClient {}.get_metric_data(start_time, end_time, queries, next_token).await
+        }
+    }
+
+    fn build_config(metric_identifier: &str, stats: usize) -> CloudwatchMetricConfig {
+        CloudwatchMetricConfig {
+            metric_identifier: metric_identifier.to_string(),
+            metric_namespace: "AWS/RDS".to_string(),
+            metric_name: "CPUUtilization".to_string(),
+            dimension_name: "DBInstanceIdentifier".to_string(),
+            dimension_value: "rds-instance".to_string(),
+            metric_stats: Some((0..stats).map(|stat| format!("p{stat}")).collect()),
+            ..CloudwatchMetricConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_batches_metrics() {
+        let client = CountingCloudwatchClient::default();
+        let ec2_client = MockEc2Client {
+            instance_id: "ec2-instance-id".to_string()
+        };
+        let cpu = build_config("cpu", 1);
+        let memory = build_config("memory", 2);
+        let range = DateTimeRange::default();
+
+        let results = fetch_data(&client, ec2_client, &[&cpu, &memory], &range, None, 0).await.expect("Should fetch data");
+
+        assert_eq!(*client.requests.borrow(), vec![3]);
+        assert_eq!(results.len(), 2);
+
+        let cpu_data = results[0].as_ref().expect("Should fetch cpu")[0].data.clone().unwrap();
+        let memory_data = results[1].as_ref().expect("Should fetch memory")[0].data.clone().unwrap();
+        assert!(cpu_data.starts_with("timestamp,value\n"));
+        assert!(cpu_data.ends_with(",4\n"));
+        // The mock multiplies by the position in the request, memory queries are 2nd and 3rd
+        assert!(memory_data.starts_with("timestamp,p0,p1\n"));
+        assert!(memory_data.ends_with(",8,12\n"));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_splits_large_batches() {
+        let client = CountingCloudwatchClient::default();
+        let ec2_client = MockEc2Client {
+            instance_id: "ec2-instance-id".to_string()
+        };
+        let first = build_config("first", 300);
+        let second = build_config("second", 300);
+        let range = DateTimeRange::default();
+
+        let results = fetch_data(&client, ec2_client, &[&first, &second], &range, None, 0).await.expect("Should fetch data");
+
+        assert_eq!(*client.requests.borrow(), vec![300, 300]);
+        assert!(results.iter().all(|result| result.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_keeps_expression_with_inputs() {
+        let client = CountingCloudwatchClient::default();
+        let ec2_client = MockEc2Client {
+            instance_id: "ec2-instance-id".to_string()
+        };
+        let input = |id: &str| ExpressionMetricConfig {
+            id: id.to_string(),
+            metric_namespace: "AWS/ApplicationELB".to_string(),
+            metric_name: "RequestCount".to_string(),
+            metric_stat: "Sum".to_string(),
+            ..ExpressionMetricConfig::default()
+        };
+        let first = build_config("first", 498);
+        // The inputs and the expression would straddle the 500th query
+        let math = CloudwatchMetricConfig {
+            metric_identifier: "total".to_string(),
+            expression: Some("m1+m2+m3".to_string()),
+            metrics: vec![input("m1"), input("m2"), input("m3")],
+            ..CloudwatchMetricConfig::default()
+        };
+        let range = DateTimeRange::default();
+
+        let results = fetch_data(&client, ec2_client, &[&first, &math], &range, None, 0).await.expect("Should fetch data");
+
+        assert_eq!(*client.requests.borrow(), vec![498, 4]);
+        assert!(results.iter().all(|result| result.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_isolates_failing_metric() {
+        let client = CountingCloudwatchClient::default();
+        let ec2_client = MockEc2Client {
+            instance_id: "ec2-instance-id".to_string()
+        };
+        let valid = build_config("valid", 1);
+        let invalid = CloudwatchMetricConfig {
+            metric_stats: None,
+            ..build_config("invalid", 0)
+        };
+        let range = DateTimeRange::default();
+
+        let results = fetch_data(&client, ec2_client, &[&valid, &invalid], &range, None, 0).await.expect("Should fetch data");
+
+        assert_eq!(*client.requests.borrow(), vec![1]);
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+    }
+
+    #[test]
+    fn test_build_data_sources_duplicate_identifier() {
+        let toml = "[[m]]
+metric_identifier = 'cpu'
+[[m]]
+metric_identifier = 'cpu'";
+        let table: toml::Table = toml::from_str(toml).unwrap();
+        let values = table["m"].as_array().unwrap().iter()
+            .map(|value| {
+                let mut value = value.clone();
+                for (key, field) in [("dimension_name", "d"), ("dimension_value", "v"), ("metric_namespace", "n"), ("metric_name", "m")] {
+                    value.as_table_mut().unwrap().insert(key.to_string(), toml::Value::String(field.to_string()));
+                }
+                value.as_table_mut().unwrap().insert("order_no".to_string(), toml::Value::Integer(1));
+                value
+            })
+            .collect();
+
+        let result = build_data_sources(values);
+
+        assert_eq!(result.err().unwrap().to_string(), "Duplicate metric_identifier: cpu");
+    }
+
+    #[test]
+    fn test_extract_to_csv_missing_values() {
+        let range = DateTimeRange::default();
+        let columns = vec![
+            Column { id: "id_0".to_string(), header: Some("Average".to_string()), label: "cpu".to_string() },
+            Column { id: "id_1".to_string(), header: Some("Maximum".to_string()), label: "cpu".to_string() },
+        ];
+        let results = [
+            MetricDataResult::builder()
+                .id("id_0")
+                .timestamps(date_time("2023-10-12T09:30:00Z"))
+                .values(1.0)
+                .build(),
+            MetricDataResult::builder()
+                .id("id_1")
+                .timestamps(date_time("2023-10-12T10:00:00Z"))
+                .values(2.0)
+                .build(),
+        ];
+
+        let table = MetricTable::new(&columns, &results).unwrap();
+
+        let result = extract_to_csv(&range, &table).expect("Should extract to csv");
+
+        let expected = [
+            "timestamp,Average,Maximum\n",
+            "2023-10-12 09:30:00 UTC,1,\n",
+            "2023-10-12 10:00:00 UTC,,2\n",
+        ].join("");
+
+        assert_eq!(result, Some(expected));
+    }
+
+    /// Records the queries, and returns two labelled series for each expression
+    #[derive(Default)]
+    struct ExpressionCloudwatchClient {
+        queries: RefCell<Vec<MetricDataQuery>>,
+    }
+
+    impl CloudwatchClient for &ExpressionCloudwatchClient {
+        async fn get_metric_data(&self, _: DateTime, _: DateTime, queries: Vec<MetricDataQuery>, _: Option<String>) -> Result<GetMetricDataOutput, Box<dyn Error>> {
+            let mut output = GetMetricDataOutput::builder();
+
+            for query in queries.iter().filter(|query| query.expression().is_some()) {
+                for (label, value) in [("i-1", 1.0), ("i-2", 2.0)] {
+                    output = output.metric_data_results(MetricDataResult::builder()
+                        .id(query.id().unwrap())
+                        .label(label)
+                        .status_code(StatusCode::Complete)
+                        .timestamps(date_time("2023-10-12T09:30:00Z"))
+                        .values(value)
+                        .build());
+                }
+            }
+
+            self.queries.borrow_mut().extend(queries);
+            Ok(output.build())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_search_expression() {
+        let client = ExpressionCloudwatchClient::default();
+        let ec2_client = MockEc2Client {
+            instance_id: "ec2-instance-id".to_string()
+        };
+        let config = CloudwatchMetricConfig {
+            metric_identifier: "asg_cpu".to_string(),
+            expression: Some("SEARCH('{AWS/EC2,InstanceId} MetricName=\"CPUUtilization\"', 'Average', 300)".to_string()),
+            ..CloudwatchMetricConfig::default()
+        };
+        let range = DateTimeRange::default();
+
+        let prompt_data_vec = fetch_data(&client, ec2_client, &[&config], &range, None, 0).await
+            .expect("Should fetch data")
+            .remove(0)
+            .expect("Should fetch metric");
+
+        let queries = client.queries.borrow();
+        assert_eq!(queries.len(), 1);
+        assert_eq!(queries[0].id(), Some("m0"));
+        assert_eq!(queries[0].period(), None);
+
+        assert_eq!(prompt_data_vec[0].description, vec![
+            "Information: [Cloudwatch Expression]".to_string(),
+            "Metric: [`asg_cpu`]".to_string(),
+            "Expression: [`SEARCH('{AWS/EC2,InstanceId} MetricName=\"CPUUtilization\"', 'Average', 300)`]".to_string(),
+            "Status: [Complete]".to_string(),
+        ]);
+        assert_eq!(prompt_data_vec[0].data, Some("timestamp,i-1,i-2\n2023-10-12 09:30:00 UTC,1,2\n".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_metric_math() {
+        let client = ExpressionCloudwatchClient::default();
+        let ec2_client = MockEc2Client {
+            instance_id: "ec2-instance-id".to_string()
+        };
+        let input = |id: &str, metric_name: &str| ExpressionMetricConfig {
+            id: id.to_string(),
+            metric_namespace: "AWS/ApplicationELB".to_string(),
+            metric_name: metric_name.to_string(),
+            metric_stat: "Sum".to_string(),
+            dimension_name: "LoadBalancer".to_string(),
+            dimension_value: "app/web/123".to_string(),
+            ..ExpressionMetricConfig::default()
+        };
+        let config = CloudwatchMetricConfig {
+            metric_identifier: "error_rate".to_string(),
+            expression: Some("m1/m2*100".to_string()),
+            metrics: vec![input("m1", "HTTPCode_Target_5XX_Count"), input("m2", "RequestCount")],
+            period: Some(300),
+            ..CloudwatchMetricConfig::default()
+        };
+        let range = DateTimeRange::default();
+
+        let prompt_data_vec = fetch_data(&client, ec2_client, &[&config], &range, None, 0).await
+            .expect("Should fetch data")
+            .remove(0)
+            .expect("Should fetch metric");
+
+        let queries = client.queries.borrow();
+        let ids: Vec<&str> = queries.iter().map(|query| query.id().unwrap()).collect();
+        assert_eq!(ids, vec!["m0_m1", "m0_m2", "m0"]);
+        assert_eq!(queries[0].return_data(), Some(false));
+        assert_eq!(queries[2].expression(), Some("m0_m1/m0_m2*100"));
+        assert_eq!(queries[2].period(), Some(300));
+
+        assert_eq!(prompt_data_vec[0].description[3], "Input: [`m1` = AWS/ApplicationELB HTTPCode_Target_5XX_Count Sum `LoadBalancer:app/web/123`]".to_string());
+        assert_eq!(prompt_data_vec[0].description[5], "Period: [300 seconds]".to_string());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_unique_query_ids() {
+        let client = ExpressionCloudwatchClient::default();
+        let ec2_client = MockEc2Client {
+            instance_id: "ec2-instance-id".to_string()
+        };
+        // `x` with its input `y` and `x_y` used to both send a query with the id `x_y`
+        let input = ExpressionMetricConfig {
+            id: "y".to_string(),
+            metric_namespace: "AWS/EC2".to_string(),
+            metric_name: "CPUUtilization".to_string(),
+            metric_stat: "Average".to_string(),
+            ..ExpressionMetricConfig::default()
+        };
+        let math = CloudwatchMetricConfig {
+            metric_identifier: "x".to_string(),
+            expression: Some("y*2".to_string()),
+            metrics: vec![input],
+            ..CloudwatchMetricConfig::default()
+        };
+        let search = CloudwatchMetricConfig {
+            metric_identifier: "x_y".to_string(),
+            expression: Some("SEARCH('{AWS/EC2,InstanceId}', 'Average', 300)".to_string()),
+            ..CloudwatchMetricConfig::default()
+        };
+        let range = DateTimeRange::default();
+
+        let results = fetch_data(&client, ec2_client, &[&math, &search], &range, None, 0).await.expect("Should fetch data");
+
+        let queries = client.queries.borrow();
+        let ids: Vec<&str> = queries.iter().map(|query| query.id().unwrap()).collect();
+        assert_eq!(ids, vec!["m0_y", "m0", "m1"]);
+        assert!(results.into_iter().all(|result| result.is_ok()));
+    }
+
+    #[test]
+    fn test_validate_input_ids() {
+        let config = |ids: &[&str]| CloudwatchMetricConfig {
+            expression: Some("e".to_string()),
+            metrics: ids.iter().map(|id| ExpressionMetricConfig { id: id.to_string(), ..ExpressionMetricConfig::default() }).collect(),
+            ..CloudwatchMetricConfig::default()
+        };
+
+        assert!(config(&["m1", "errors_5xx", "rateOk"]).validate_input_ids().is_ok());
+        assert_eq!(config(&["M1"]).validate_input_ids().unwrap_err().to_string(), "Invalid configuration: metrics id `M1` must start with a lowercase letter, followed by letters, digits or underscores");
+        assert!(config(&["1m"]).validate_input_ids().is_err());
+        assert!(config(&["m-1"]).validate_input_ids().is_err());
+        assert!(config(&[""]).validate_input_ids().is_err());
+        assert_eq!(config(&["m1", "m1"]).validate_input_ids().unwrap_err().to_string(), "Invalid configuration: Duplicate metrics id: m1");
+    }
+
+    #[test]
+    fn test_prefix_ids() {
+        assert_eq!(prefix_ids("m1/m2*100", &["m1", "m2"], "err"), "err_m1/err_m2*100");
+        assert_eq!(prefix_ids("m10 + m1", &["m1"], "x"), "m10 + x_m1");
+        assert_eq!(prefix_ids("SUM([m1, 'm1']) + \"m1\"", &["m1"], "x"), "SUM([x_m1, 'm1']) + \"m1\"");
+    }
+
+    #[test]
+    fn test_validate_requires_metric_without_expression() {
+        let config = CloudwatchMetricConfig {
+            metric_stat: Some("Average".to_string()),
+            ..CloudwatchMetricConfig::default()
+        };
+
+        assert!(matches!(config.validate(), Err(DataSourceError::InvalidConfig(_))));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_summary() {
+        let client = MockCloudwatchClient {};
+        let ec2_client = MockEc2Client {
+            instance_id: "ec2-instance-id".to_string()
+        };
+        let config = CloudwatchMetricConfig {
+            summary: true,
+            top_spikes: Some(2),
+            downsample: Some(2),
+            ..build_config("cpu", 1)
+        };
+        let range = DateTimeRange::default();
+
+        let prompt_data_vec = fetch_data(client, ec2_client, &[&config], &range, None, 0).await
+            .expect("Should fetch data")
+            .remove(0)
+            .expect("Should fetch metric");
+
+        let expected = [
+            "Summary:\n",
+            "column,count,min,max,mean,stddev,p50,p95,p99,trend_per_hour\n",
+            "value,4,1,4,2.5,1.118,2.5,3.85,3.97,2\n",
+            "\nTop spikes:\n",
+            "timestamp,column,value\n",
+            "2023-10-12 11:00:00 UTC,value,4\n",
+            "2023-10-12 10:30:00 UTC,value,3\n",
+            "\nDownsampled series:\n",
+            "timestamp,value\n",
+            "2023-10-12 09:30:00 UTC,1.5\n",
+            "2023-10-12 10:30:00 UTC,3.5\n",
+        ].join("");
+
+        assert_eq!(prompt_data_vec[0].data, Some(expected));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_anomalies() {
+        let client = MockCloudwatchClient {};
+        let ec2_client = MockEc2Client {
+            instance_id: "ec2-instance-id".to_string()
+        };
+        let range = DateTimeRange::default();
+        let with_thresholds = CloudwatchMetricConfig {
+            detect_anomalies: true,
+            warn_above: Some(3.5),
+            ..build_config("cpu", 1)
+        };
+        let without_anomalies = CloudwatchMetricConfig {
+            detect_anomalies: true,
+            ..build_config("memory", 1)
+        };
+
+        let mut results = fetch_data(client, ec2_client, &[&with_thresholds, &without_anomalies], &range, None, 0).await.expect("Should fetch data");
+        let memory = results.remove(1).expect("Should fetch memory");
+        let cpu = results.remove(0).expect("Should fetch cpu");
+
+        assert_eq!(cpu[0].description[cpu[0].description.len() - 2..], [
+            "Anomalies detected:".to_string(),
+            "- `value` above 3.5 at 2023-10-12 11:00:00 UTC, peak 4".to_string(),
+        ]);
+        assert_eq!(memory[0].description.last().unwrap(), "Anomalies detected: [none]");
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_with_baseline() {
+        let client = CountingCloudwatchClient::default();
+        let ec2_client = MockEc2Client {
+            instance_id: "ec2-instance-id".to_string()
+        };
+        let config = build_config("cpu", 1);
+        let range = DateTimeRange::default();
+        let baseline = DateTimeRange {
+            start_time: date_time("2023-10-11T09:30:00Z").to_millis().unwrap(),
+            end_time: date_time("2023-10-11T11:00:00Z").to_millis().unwrap(),
+            time_zone: Tz::UTC,
+        };
+        let now = date_time("2023-10-12T12:00:00Z").to_millis().unwrap();
+
+        let prompt_data_vec = fetch_data(&client, ec2_client, &[&config], &range, Some(&baseline), now).await
+            .expect("Should fetch data")
+            .remove(0)
+            .expect("Should fetch metric");
+
+        let comparison = [
+            "\nBaseline comparison:\n",
+            "column,statistic,current,baseline,delta,delta_percent\n",
+            "value,count,4,4,0,0\n",
+            "value,mean,2.5,2.5,0,0\n",
+            "value,min,1,1,0,0\n",
+            "value,max,4,4,0,0\n",
+            "value,p95,3.85,3.85,0,0\n",
+        ].join("");
+
+        assert_eq!(*client.requests.borrow(), vec![1, 1]);
+        assert_eq!(prompt_data_vec[0].description.last().unwrap(), "Baseline: [2023-10-11 09:30:00 UTC to 2023-10-11 11:00:00 UTC]");
+        assert!(prompt_data_vec[0].data.as_ref().unwrap().ends_with(&comparison));
+    }
+
+    #[test]
+    fn test_auto_period() {
+        let now = date_time("2023-10-12T12:00:00Z").to_millis().unwrap();
+        let range = |start: &str, end: &str| DateTimeRange {
+            start_time: date_time(start).to_millis().unwrap(),
+            end_time: date_time(end).to_millis().unwrap(),
+            time_zone: Tz::UTC,
+        };
+
+        // 1 hour, 24 hours and 7 days windows
+        assert_eq!(auto_period(&range("2023-10-12T11:00:00Z", "2023-10-12T12:00:00Z"), 120, now), 60);
+        assert_eq!(auto_period(&range("2023-10-11T12:00:00Z", "2023-10-12T12:00:00Z"), 120, now), 900);
+        assert_eq!(auto_period(&range("2023-10-05T12:00:00Z", "2023-10-12T12:00:00Z"), 120, now), 7200);
+        // Older than 15 days only has 5 minute data
+        assert_eq!(auto_period(&range("2023-09-01T11:00:00Z", "2023-09-01T12:00:00Z"), 120, now), 300);
+        // Beyond the largest period, whole days are used
+        assert_eq!(auto_period(&range("2023-01-01T00:00:00Z", "2023-10-12T00:00:00Z"), 10, now), 2505600);
+    }
+
+    #[test]
+    fn test_resolve_period_with_baseline() {
+        let now = date_time("2023-10-12T12:00:00Z").to_millis().unwrap();
+        let range = |start: &str, end: &str| DateTimeRange {
+            start_time: date_time(start).to_millis().unwrap(),
+            end_time: date_time(end).to_millis().unwrap(),
+            time_zone: Tz::UTC,
+        };
+        let current = range("2023-10-12T11:00:00Z", "2023-10-12T12:00:00Z");
+        let last_month = range("2023-09-12T11:00:00Z", "2023-09-12T12:00:00Z");
+        let configured = CloudwatchMetricConfig { period: Some(60), ..CloudwatchMetricConfig::default() };
+
+        assert_eq!(resolve_period(&CloudwatchMetricConfig::default(), &current, None, now), 60);
+        assert_eq!(resolve_period(&CloudwatchMetricConfig::default(), &current, Some(&last_month), now), 300);
+        assert_eq!(resolve_period(&configured, &current, Some(&last_month), now), 60);
+    }
+
     fn date_time(s: &str) -> DateTime {
         DateTime::from_str(s, Format::DateTime).unwrap()
     }
@@ -1,114 +1,242 @@
-use crate::datasource::ds::DataSource::{CloudwatchLogInsight, CloudwatchMetric, Ec2, Rds};
-use crate::datasource::{app_description, cloudwatch_log_insight, cloudwatch_metric, ec2, rds};
-use crate::lib::config::{AppDescConfig, CloudwatchLogInsightConfig, CloudwatchMetricConfig, Ec2Config, RdsConfig};
+use crate::datasource::app_description::AppDescConfig;
+use crate::datasource::autoscaling_group::AutoScalingGroupConfig;
+use crate::datasource::cloudwatch_alarm::CloudwatchAlarmConfig;
+use crate::datasource::cloudwatch_log_events::CloudwatchLogEventsConfig;
+use crate::datasource::cloudwatch_log_insight::CloudwatchLogInsightConfig;
+use crate::datasource::cloudwatch_metric;
+use crate::datasource::ec2::Ec2Config;
+use crate::datasource::ecs::EcsConfig;
+use crate::datasource::elb::ElbConfig;
+use crate::datasource::lambda::LambdaConfig;
+use crate::datasource::rds::RdsConfig;
+use crate::lib::aws::AwsScope;
 use crate::lib::context::AppContext;
 use crate::lib::prompt::PromptData;
-use std::cmp::Ordering;
+use futures::future::LocalBoxFuture;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::fmt;
-use aws_config::BehaviorVersion;
-use aws_config::meta::region::RegionProviderChain;
-use DataSource::AppDescription;
 
-#[derive(Debug)]
-pub enum DataSource {
-    AppDescription { config: AppDescConfig },
-    Ec2 { config: Ec2Config },
-    Rds { config: RdsConfig },
-    CloudwatchMetric { config: CloudwatchMetricConfig },
-    CloudwatchLogInsight { config: CloudwatchLogInsightConfig }
+pub trait DataSource {
+    /// Human readable name, displayed while fetching
+    fn name(&self) -> &str;
+
+    /// The order this data will appear on the text prompt
+    fn order_no(&self) -> u8;
+
+    fn options(&self) -> &SourceOptions;
+
+    /// Cloudwatch dimensions of the resources this data source looks at, used to find their alarms
+    fn resource_dimensions<'a>(&'a self, _context: &'a AppContext) -> LocalBoxFuture<'a, Result<Vec<(String, String)>, DataSourceError>> {
+        Box::pin(async { Ok(Vec::new()) })
+    }
+
+    fn fetch_data<'a>(&'a self, context: &'a AppContext) -> LocalBoxFuture<'a, Result<Vec<PromptData>, DataSourceError>>;
 }
 
-impl DataSource {
-    fn order_no(&self) -> u8 {
-        match self {
-            AppDescription { config, ..} => config.order_no,
-            Ec2 { config, .. } => config.order_no,
-            Rds { config, .. } => config.order_no,
-            CloudwatchMetric { config, .. } => config.order_no,
-            CloudwatchLogInsight { config, .. } => config.order_no,
+/// Settings shared by every data source, flattened into its `toml` table
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct SourceOptions {
+    /// Overrides `[general].on_error` for this data source
+    pub on_error: Option<ErrorPolicy>,
+    /// Overrides `[general].profile` for this data source
+    pub profile: Option<String>,
+    /// Defaults to the region of the profile
+    pub region: Option<String>,
+    /// Role assumed with the profile's credentials, e.g. for another account
+    pub role_arn: Option<String>,
+}
+
+impl SourceOptions {
+    pub fn scope(&self) -> AwsScope {
+        AwsScope {
+            profile: self.profile.clone(),
+            region: self.region.clone(),
+            role_arn: self.role_arn.clone(),
         }
     }
+}
 
-    pub async fn fetch_data(&self, context: &AppContext) -> Result<Vec<PromptData>, Box<dyn Error>> {
-        let region_provider = RegionProviderChain::default_provider();
-        let sdk_config = aws_config::defaults(BehaviorVersion::latest())
-            .region(region_provider)
-            .profile_name(&context.profile)
-            .load()
-            .await;
-
-        let prompt_data = match self {
-            AppDescription { config} => {
-                vec![app_description::fetch_data(config)]
-            },
-            Ec2 { config } => {
-                let client = aws_sdk_ec2::Client::new(&sdk_config);
-                ec2::fetch_data(client, config).await?
-            },
-            Rds { config } => {
-                let client = aws_sdk_rds::Client::new(&sdk_config);
-                vec![rds::fetch_data(client, config).await?]
-            },
-            CloudwatchMetric { config } => {
-                let client = aws_sdk_cloudwatch::Client::new(&sdk_config);
-                let ec2_client = aws_sdk_ec2::Client::new(&sdk_config);
-                cloudwatch_metric::fetch_data(client, ec2_client, config, &context.range).await?
-            },
-            CloudwatchLogInsight { config } => {
-                let client = aws_sdk_cloudwatchlogs::Client::new(&sdk_config);
-                vec![cloudwatch_log_insight::fetch_data(client, config, &context.range).await?]
-            }
-        };
-
-        Ok(prompt_data)
-    }
+/// What to do with the rest of the diagnosis when a data source fails
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorPolicy {
+    /// Report the data source as unavailable in the prompt, and carry on
+    #[default]
+    Skip,
+    /// Abort the whole run
+    Fail,
+}
+
+#[derive(Debug)]
+pub enum DataSourceError {
+    NotFound { resource: &'static str, name: String },
+    QueryStatus(String),
+    QueryTimeout(u64),
+    /// Ctrl-C while fetching, aborts the run whatever the error policy
+    Interrupted,
+    MissingField(&'static str),
+    InvalidConfig(String),
+    Other(Box<dyn Error>),
 }
 
-impl fmt::Display for DataSource {
+impl fmt::Display for DataSourceError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let display_string = match self {
-            AppDescription { .. } => "App description".to_string(),
-            Ec2 { .. } => "EC2 instance".to_string(),
-            Rds { .. } => "RDS instance".to_string(),
-            CloudwatchMetric { .. } => "Cloudwatch metric".to_string(),
-            CloudwatchLogInsight { .. } => "Cloudwatch log insight".to_string(),
-        };
-        write!(f, "{display_string}")
+        match self {
+            DataSourceError::NotFound { resource, name } => write!(f, "Unable to find {resource} with name: {name}"),
+            DataSourceError::QueryStatus(status) => write!(f, "Unexpected status: {status}"),
+            DataSourceError::QueryTimeout(seconds) => write!(f, "Query did not complete within {seconds}s, it was stopped"),
+            DataSourceError::Interrupted => write!(f, "Interrupted"),
+            DataSourceError::MissingField(field) => write!(f, "{field} is missing from response"),
+            DataSourceError::InvalidConfig(message) => write!(f, "Invalid configuration: {message}"),
+            DataSourceError::Other(err) => write!(f, "{err}"),
+        }
     }
 }
 
-impl Ord for DataSource {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.order_no().cmp(&other.order_no())
+impl Error for DataSourceError {}
+
+impl From<Box<dyn Error>> for DataSourceError {
+    fn from(err: Box<dyn Error>) -> Self {
+        match err.downcast::<DataSourceError>() {
+            Ok(err) => *err,
+            Err(err) => DataSourceError::Other(err),
+        }
     }
 }
 
-impl PartialOrd for DataSource {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
+/// Builds every data source of a `toml` table at once, allowing them to share state
+pub type Factory = fn(Vec<toml::Value>) -> Result<Vec<Box<dyn DataSource>>, Box<dyn Error>>;
+
+/// Maps a `toml` table name (e.g. `[[ec2]]`) to the data source built from it
+pub struct Registry {
+    factories: BTreeMap<&'static str, Factory>
+}
+
+impl Registry {
+    pub fn register<T: DataSource + DeserializeOwned + 'static>(&mut self, table_name: &'static str) {
+        self.factories.insert(table_name, build_data_sources::<T>);
+    }
+
+    pub fn register_factory(&mut self, table_name: &'static str, factory: Factory) {
+        self.factories.insert(table_name, factory);
+    }
+
+    pub fn build(&self, table_name: &str, value: toml::Value) -> Result<Vec<Box<dyn DataSource>>, Box<dyn Error>> {
+        let factory = self.factories.get(table_name)
+            .ok_or(format!("Unknown data source: [[{table_name}]]"))?;
+
+        match value {
+            toml::Value::Array(values) => factory(values),
+            _ => Err(format!("Data source [[{table_name}]] must be an array of tables").into())
+        }
     }
 }
 
-impl PartialEq for DataSource {
-    fn eq(&self, other: &Self) -> bool {
-        self.order_no() == other.order_no()
+impl Default for Registry {
+    fn default() -> Self {
+        let mut registry = Registry { factories: BTreeMap::new() };
+        registry.register::<AppDescConfig>("app_description");
+        registry.register::<Ec2Config>("ec2");
+        registry.register::<RdsConfig>("rds");
+        registry.register_factory("cloudwatch_metric", cloudwatch_metric::build_data_sources);
+        registry.register::<CloudwatchLogInsightConfig>("cloudwatch_log_insight");
+        registry.register::<CloudwatchLogEventsConfig>("cloudwatch_log_events");
+        registry.register::<ElbConfig>("elb");
+        registry.register::<EcsConfig>("ecs");
+        registry.register::<LambdaConfig>("lambda");
+        registry.register::<AutoScalingGroupConfig>("autoscaling_group");
+        registry.register::<CloudwatchAlarmConfig>("cloudwatch_alarm");
+        registry
     }
 }
 
-impl Eq for DataSource {}
+fn build_data_sources<T: DataSource + DeserializeOwned + 'static>(values: Vec<toml::Value>) -> Result<Vec<Box<dyn DataSource>>, Box<dyn Error>> {
+    values.into_iter()
+        .map(|value| Ok(Box::new(value.try_into::<T>()?) as Box<dyn DataSource>))
+        .collect()
+}
 
 #[cfg(test)]
 mod tests {
-    use crate::datasource::ds::DataSource::{AppDescription, CloudwatchLogInsight, CloudwatchMetric, Ec2, Rds};
-    use crate::lib::config::{AppDescConfig, CloudwatchLogInsightConfig, CloudwatchMetricConfig, Ec2Config, RdsConfig};
+    use super::*;
+
+    fn build(table_name: &str, toml: &str) -> Result<Vec<Box<dyn DataSource>>, Box<dyn Error>> {
+        let value: toml::Table = toml::from_str(toml).unwrap();
+        Registry::default().build(table_name, value[table_name].clone())
+    }
+
+    #[test]
+    fn test_name() {
+        let cases = [
+            ("app_description", "[[app_description]]\norder_no = 1\ndescription = ''", "App description"),
+            ("ec2", "[[ec2]]\norder_no = 1\ninstance_name = ''", "EC2 instance"),
+            ("rds", "[[rds]]\norder_no = 1\ndb_identifier = ''", "RDS instance"),
+            ("cloudwatch_metric", "[[cloudwatch_metric]]\norder_no = 1\ndimension_name = ''\ndimension_value = ''\nmetric_identifier = ''\nmetric_namespace = ''\nmetric_name = ''\nmetric_stat = ''", "Cloudwatch metric"),
+            ("cloudwatch_log_insight", "[[cloudwatch_log_insight]]\norder_no = 1\ndescription = ''\nlog_group_name = ''\nquery = ''\nresult_columns = []", "Cloudwatch log insight"),
+            ("elb", "[[elb]]\norder_no = 1\nload_balancer_name = ''", "Load balancer"),
+            ("ecs", "[[ecs]]\norder_no = 1\ncluster_name = ''\nservice_name = ''", "ECS service"),
+            ("lambda", "[[lambda]]\norder_no = 1\nfunction_name = ''", "Lambda function"),
+            ("autoscaling_group", "[[autoscaling_group]]\norder_no = 1\nauto_scaling_group_name = ''", "Auto Scaling group"),
+            ("cloudwatch_alarm", "[[cloudwatch_alarm]]\norder_no = 1\nalarm_name_prefix = 'web-'", "Cloudwatch alarms"),
+            ("cloudwatch_log_events", "[[cloudwatch_log_events]]\norder_no = 1\ndescription = ''\nlog_group_name = ''", "Cloudwatch log events"),
+        ];
+
+        for (table_name, toml, expected) in cases {
+            let data_sources = build(table_name, toml).expect("Should build data source");
+            assert_eq!(data_sources.len(), 1);
+            assert_eq!(data_sources[0].name(), expected);
+        }
+    }
 
     #[test]
-    fn test_fmt() {
-        assert_eq!(format!("{}", AppDescription { config: AppDescConfig::default() }), "App description");
-        assert_eq!(format!("{}", Ec2 { config: Ec2Config::default() }), "EC2 instance");
-        assert_eq!(format!("{}", Rds { config: RdsConfig::default() }), "RDS instance");
-        assert_eq!(format!("{}", CloudwatchMetric { config: CloudwatchMetricConfig::default() }), "Cloudwatch metric");
-        assert_eq!(format!("{}", CloudwatchLogInsight { config: CloudwatchLogInsightConfig::default() }), "Cloudwatch log insight");
+    fn test_build_multiple() {
+        let toml = "[[rds]]\norder_no = 2\ndb_identifier = 'a'\n[[rds]]\norder_no = 1\ndb_identifier = 'b'";
+
+        let data_sources = build("rds", toml).expect("Should build data sources");
+
+        assert_eq!(data_sources.len(), 2);
+        assert_eq!(data_sources[0].order_no(), 2);
+        assert_eq!(data_sources[1].order_no(), 1);
+    }
+
+    #[test]
+    fn test_build_unknown_table() {
+        let result = build("unknown", "[[unknown]]\norder_no = 1");
+
+        assert_eq!(result.err().unwrap().to_string(), "Unknown data source: [[unknown]]");
+    }
+
+    #[test]
+    fn test_build_source_options() {
+        let toml = "[[rds]]\norder_no = 1\ndb_identifier = 'a'\non_error = 'fail'\nprofile = 'db'\nregion = 'us-east-1'\nrole_arn = 'arn'";
+
+        let data_sources = build("rds", toml).unwrap();
+        let options = data_sources[0].options();
+
+        assert_eq!(options.on_error, Some(ErrorPolicy::Fail));
+        assert_eq!(options.scope(), AwsScope {
+            profile: Some("db".to_string()),
+            region: Some("us-east-1".to_string()),
+            role_arn: Some("arn".to_string()),
+        });
+    }
+
+    #[test]
+    fn test_error_from_boxed_error() {
+        let not_found: Box<dyn Error> = DataSourceError::NotFound { resource: "EC2 instance", name: "name".to_string() }.into();
+        let other: Box<dyn Error> = "some error".into();
+
+        assert!(matches!(DataSourceError::from(not_found), DataSourceError::NotFound { .. }));
+        assert!(matches!(DataSourceError::from(other), DataSourceError::Other(_)));
+    }
+
+    #[test]
+    fn test_build_missing_field() {
+        let result = build("ec2", "[[ec2]]\norder_no = 1");
+
+        assert!(result.is_err());
     }
 }
@@ -1,10 +1,49 @@
-use crate::lib::config::Ec2Config;
+use crate::datasource::ds::{DataSource, DataSourceError, SourceOptions};
+use crate::lib::context::AppContext;
 use crate::lib::prompt::PromptData;
 use aws_sdk_ec2::operation::describe_instances::DescribeInstancesOutput;
 use aws_sdk_ec2::types::{Filter, Instance};
 use aws_sdk_ec2::Client;
+use futures::future::LocalBoxFuture;
+use serde::Deserialize;
 use std::error::Error;
 
+#[derive(Deserialize, Debug, Default)]
+pub struct Ec2Config {
+    pub order_no: u8,
+    pub instance_name: String,
+    #[serde(flatten)]
+    pub options: SourceOptions,
+}
+
+impl DataSource for Ec2Config {
+    fn name(&self) -> &str {
+        "EC2 instance"
+    }
+
+    fn order_no(&self) -> u8 {
+        self.order_no
+    }
+
+    fn options(&self) -> &SourceOptions {
+        &self.options
+    }
+
+    fn resource_dimensions<'a>(&'a self, context: &'a AppContext) -> LocalBoxFuture<'a, Result<Vec<(String, String)>, DataSourceError>> {
+        Box::pin(async move {
+            let client = context.aws.ec2(&self.options.scope()).await;
+            Ok(fetch_resource_dimensions(&client, self).await?)
+        })
+    }
+
+    fn fetch_data<'a>(&'a self, context: &'a AppContext) -> LocalBoxFuture<'a, Result<Vec<PromptData>, DataSourceError>> {
+        Box::pin(async move {
+            let client = context.aws.ec2(&self.options.scope()).await;
+            Ok(fetch_data(client, self).await?)
+        })
+    }
+}
+
 pub trait Ec2Client {
     async fn describe_instances(&self, filter: Filter) -> Result<DescribeInstancesOutput, Box<dyn Error>>;
 }
@@ -18,7 +57,7 @@ impl Ec2Client for Client {
     }
 }
 
-pub async fn fetch_instances(client: impl Ec2Client, ec2_instance_name: &String) -> Result<Vec<Instance>, Box<dyn Error>> {
+pub async fn fetch_instances(client: &impl Ec2Client, ec2_instance_name: &String) -> Result<Vec<Instance>, Box<dyn Error>> {
     let filter = Filter::builder()
         .name("tag:Name")
         .values(ec2_instance_name)
@@ -33,12 +72,27 @@ pub async fn fetch_instances(client: impl Ec2Client, ec2_instance_name: &String)
         }
     }
 
-    assert!(!instances.is_empty(), "Unable to find EC2 instance with name: {ec2_instance_name}");
+    if instances.is_empty() {
+        return Err(DataSourceError::NotFound {
+            resource: "EC2 instance",
+            name: ec2_instance_name.to_string()
+        }.into());
+    }
+
     Ok(instances)
 }
 
+/// Every instance carrying the name, as the data of each of them is sent
+pub async fn fetch_resource_dimensions(client: &impl Ec2Client, config: &Ec2Config) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    Ok(fetch_instances(client, &config.instance_name).await?
+        .iter()
+        .filter_map(|instance| instance.instance_id())
+        .map(|instance_id| ("InstanceId".to_string(), instance_id.to_string()))
+        .collect())
+}
+
 pub async fn fetch_data(client: impl Ec2Client, config: &Ec2Config) -> Result<Vec<PromptData>, Box<dyn Error>> {
-    let instances = fetch_instances(client, &config.instance_name).await?;
+    let instances = fetch_instances(&client, &config.instance_name).await?;
 
     let prompt_data_vec = instances.into_iter().map(|instance| PromptData {
         description: build_description(config, instance),
@@ -49,22 +103,24 @@ pub async fn fetch_data(client: impl Ec2Client, config: &Ec2Config) -> Result<Ve
 }
 
 fn build_description(config: &Ec2Config, instance: Instance) -> Vec<String> {
-    let instance_id = instance.instance_id().unwrap();
-    let instance_type = instance.instance_type().unwrap().as_str();
-    let cpu = instance.cpu_options().unwrap();
+    // Fields missing from a sparse response are shown as N/A rather than failing the data source
+    let instance_id = instance.instance_id().unwrap_or("N/A");
+    let instance_type = instance.instance_type().map(|instance_type| instance_type.as_str()).unwrap_or("N/A");
+    let cpu = instance.cpu_options();
+    let core_count = cpu.and_then(|cpu| cpu.core_count()).map_or("N/A".to_string(), |count| count.to_string());
+    let threads_per_core = cpu.and_then(|cpu| cpu.threads_per_core()).map_or("N/A".to_string(), |count| count.to_string());
     let instance_state = instance.state()
-        .unwrap()
-        .name()
-        .unwrap()
-        .to_string();
+        .and_then(|state| state.name())
+        .map(|name| name.as_str())
+        .unwrap_or("N/A");
 
     vec![
         "Information: [EC2 Instance]".to_string(),
         format!("Instance name: [`{}`]", &config.instance_name),
         format!("Instance id: [`{}`]", instance_id),
         format!("Instance type: [`{}`]", instance_type),
-        format!("Cpu core count: [{}]", cpu.core_count().unwrap()),
-        format!("Cpu threads per core: [{}]", cpu.threads_per_core().unwrap()),
+        format!("Cpu core count: [{core_count}]"),
+        format!("Cpu threads per core: [{threads_per_core}]"),
         format!("State: [{instance_state}]"),
     ]
 }
@@ -105,7 +161,8 @@ pub mod tests {
         };
         let config = Ec2Config {
             order_no: 1,
-            instance_name: "ec2-instance-name".to_string()
+            instance_name: "ec2-instance-name".to_string(),
+            ..Ec2Config::default()
         };
 
         for prompt_data in fetch_data(client, &config).await.expect("Should be able to fetch data") {
@@ -121,6 +178,21 @@ pub mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_fetch_resource_dimensions() {
+        let client = MockEc2Client {
+            instance_id: "ec2-instance-id".to_string()
+        };
+        let config = Ec2Config {
+            instance_name: "ec2-instance-name".to_string(),
+            ..Ec2Config::default()
+        };
+
+        let dimensions = fetch_resource_dimensions(&client, &config).await.expect("Should resolve the instance");
+
+        assert_eq!(dimensions, vec![("InstanceId".to_string(), "ec2-instance-id".to_string())]);
+    }
+
     struct NoInstanceEc2Client { }
 
     impl Ec2Client for NoInstanceEc2Client {
@@ -132,11 +204,27 @@ pub mod tests {
     }
 
     #[tokio::test]
-    #[should_panic(expected = "Unable to find EC2 instance with name: not-found-instance-name")]
     async fn test_fetch_instances_not_found() {
         let client = NoInstanceEc2Client {};
         let instance_name = String::from("not-found-instance-name");
 
-        fetch_instances(client, &instance_name).await.unwrap();
+        let err = fetch_instances(&client, &instance_name).await.expect_err("Should not find instance");
+
+        assert_eq!(err.to_string(), "Unable to find EC2 instance with name: not-found-instance-name");
+        assert!(matches!(DataSourceError::from(err), DataSourceError::NotFound { .. }));
+    }
+
+    #[test]
+    fn test_build_description_sparse_instance() {
+        let config = Ec2Config {
+            instance_name: "instance-name".to_string(),
+            ..Ec2Config::default()
+        };
+
+        let description = build_description(&config, Instance::builder().build());
+
+        assert_eq!(description[2], "Instance id: [`N/A`]");
+        assert_eq!(description[4], "Cpu core count: [N/A]");
+        assert_eq!(description[6], "State: [N/A]");
     }
 }
\ No newline at end of file
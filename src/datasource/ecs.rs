@@ -0,0 +1,323 @@
+use crate::datasource::ds::{DataSource, DataSourceError, SourceOptions};
+use crate::lib::aws::millis;
+use crate::lib::context::{AppContext, DateTimeRange};
+use crate::lib::prompt::PromptData;
+use aws_sdk_ecs::operation::describe_services::DescribeServicesOutput;
+use aws_sdk_ecs::operation::describe_tasks::DescribeTasksOutput;
+use aws_sdk_ecs::operation::list_tasks::ListTasksOutput;
+use aws_sdk_ecs::types::{DesiredStatus, Service, Task};
+use aws_sdk_ecs::Client;
+use aws_smithy_types::DateTime;
+use csv::Writer;
+use futures::future::LocalBoxFuture;
+use serde::Deserialize;
+use std::error::Error;
+
+const DEFAULT_MAX_EVENTS: usize = 20;
+const DEFAULT_MAX_STOPPED_TASKS: usize = 10;
+
+#[derive(Deserialize, Debug, Default)]
+pub struct EcsConfig {
+    pub order_no: u8,
+    pub cluster_name: String,
+    pub service_name: String,
+    /// Most recent service events within the time range
+    pub max_events: Option<usize>,
+    /// Most recently stopped tasks within the time range, ECS only keeps them for about an hour
+    pub max_stopped_tasks: Option<usize>,
+    #[serde(flatten)]
+    pub options: SourceOptions,
+}
+
+impl DataSource for EcsConfig {
+    fn name(&self) -> &str {
+        "ECS service"
+    }
+
+    fn order_no(&self) -> u8 {
+        self.order_no
+    }
+
+    fn options(&self) -> &SourceOptions {
+        &self.options
+    }
+
+    fn resource_dimensions<'a>(&'a self, _: &'a AppContext) -> LocalBoxFuture<'a, Result<Vec<(String, String)>, DataSourceError>> {
+        Box::pin(async move { Ok(vec![("ClusterName".to_string(), self.cluster_name.clone()), ("ServiceName".to_string(), self.service_name.clone())]) })
+    }
+
+    fn fetch_data<'a>(&'a self, context: &'a AppContext) -> LocalBoxFuture<'a, Result<Vec<PromptData>, DataSourceError>> {
+        Box::pin(async move {
+            let client = context.aws.ecs(&self.options.scope()).await;
+            Ok(vec![fetch_data(&client, self, &context.range).await?])
+        })
+    }
+}
+
+pub trait EcsClient {
+    async fn describe_services(&self, cluster_name: &str, service_name: &str) -> Result<DescribeServicesOutput, Box<dyn Error>>;
+
+    async fn list_stopped_tasks(&self, cluster_name: &str, service_name: &str) -> Result<ListTasksOutput, Box<dyn Error>>;
+
+    async fn describe_tasks(&self, cluster_name: &str, task_arns: Vec<String>) -> Result<DescribeTasksOutput, Box<dyn Error>>;
+}
+
+impl EcsClient for Client {
+    async fn describe_services(&self, cluster_name: &str, service_name: &str) -> Result<DescribeServicesOutput, Box<dyn Error>> {
+        Ok(self.describe_services()
+            .cluster(cluster_name)
+            .services(service_name)
+            .send()
+            .await?)
+    }
+
+    async fn list_stopped_tasks(&self, cluster_name: &str, service_name: &str) -> Result<ListTasksOutput, Box<dyn Error>> {
+        Ok(self.list_tasks()
+            .cluster(cluster_name)
+            .service_name(service_name)
+            .desired_status(DesiredStatus::Stopped)
+            .send()
+            .await?)
+    }
+
+    async fn describe_tasks(&self, cluster_name: &str, task_arns: Vec<String>) -> Result<DescribeTasksOutput, Box<dyn Error>> {
+        Ok(self.describe_tasks()
+            .cluster(cluster_name)
+            .set_tasks(Some(task_arns))
+            .send()
+            .await?)
+    }
+}
+
+pub async fn fetch_data(client: &impl EcsClient, config: &EcsConfig, range: &DateTimeRange) -> Result<PromptData, Box<dyn Error>> {
+    let service = client.describe_services(&config.cluster_name, &config.service_name).await?
+        .services
+        .unwrap_or_default()
+        .into_iter()
+        .next()
+        .ok_or(DataSourceError::NotFound { resource: "ECS service", name: format!("{}/{}", config.cluster_name, config.service_name) })?;
+
+    // ListTasks returns at most 100 tasks, DescribeTasks accepts as many
+    let task_arns = client.list_stopped_tasks(&config.cluster_name, &config.service_name).await?
+        .task_arns
+        .unwrap_or_default();
+
+    let mut stopped_tasks = match task_arns.is_empty() {
+        true => Vec::new(),
+        false => client.describe_tasks(&config.cluster_name, task_arns).await?.tasks.unwrap_or_default(),
+    };
+    stopped_tasks.retain(|task| within(range, task.stopped_at()));
+    stopped_tasks.sort_by_key(|task| std::cmp::Reverse(millis(task.stopped_at())));
+    stopped_tasks.truncate(config.max_stopped_tasks.unwrap_or(DEFAULT_MAX_STOPPED_TASKS));
+
+    Ok(PromptData {
+        description: build_description(config, &service),
+        data: Some(format!("Events:\n{}\nStopped tasks:\n{}", format_events(config, &service, range), extract_stopped_tasks(&stopped_tasks, range)?)),
+    })
+}
+
+fn within(range: &DateTimeRange, date_time: Option<&DateTime>) -> bool {
+    millis(date_time).is_some_and(|millis| range.start_time <= millis && millis <= range.end_time)
+}
+
+/// `family:revision` of a task definition ARN
+fn task_definition_name(task_definition_arn: &str) -> &str {
+    task_definition_arn.rsplit('/').next().unwrap_or(task_definition_arn)
+}
+
+fn build_description(config: &EcsConfig, service: &Service) -> Vec<String> {
+    let mut description = vec![
+        "Information: [ECS Service]".to_string(),
+        format!("Cluster: [`{}`]", &config.cluster_name),
+        format!("Service: [`{}`]", &config.service_name),
+        format!("Status: [{}]", service.status().unwrap_or_default()),
+        format!("Launch type: [{}]", service.launch_type().map(|launch_type| launch_type.as_str()).unwrap_or_default()),
+        format!("Task definition: [`{}`]", task_definition_name(service.task_definition().unwrap_or_default())),
+        format!("Tasks: [desired {}, running {}, pending {}]", service.desired_count(), service.running_count(), service.pending_count()),
+    ];
+
+    for deployment in service.deployments() {
+        let mut line = format!(
+            "Deployment: [{} `{}`, rollout {}",
+            deployment.status().unwrap_or_default(),
+            task_definition_name(deployment.task_definition().unwrap_or_default()),
+            deployment.rollout_state().map(|rollout_state| rollout_state.as_str()).unwrap_or_default(),
+        );
+        if let Some(reason) = deployment.rollout_state_reason() {
+            line.push_str(&format!(" ({reason})"));
+        }
+        line.push_str(&format!(
+            ", desired {}, running {}, pending {}, failed tasks {}]",
+            deployment.desired_count(),
+            deployment.running_count(),
+            deployment.pending_count(),
+            deployment.failed_tasks(),
+        ));
+        description.push(line);
+    }
+
+    description
+}
+
+/// Events within the range, oldest first
+fn format_events(config: &EcsConfig, service: &Service, range: &DateTimeRange) -> String {
+    // ECS returns the most recent events first
+    let mut events: Vec<_> = service.events()
+        .iter()
+        .filter(|event| within(range, event.created_at()))
+        .take(config.max_events.unwrap_or(DEFAULT_MAX_EVENTS))
+        .collect();
+    events.reverse();
+
+    if events.is_empty() {
+        return "No service events found\n".to_string();
+    }
+
+    events.iter()
+        .map(|event| format!("{} {}\n", range.format_timestamp(millis(event.created_at()).unwrap_or_default()), event.message().unwrap_or_default()))
+        .collect()
+}
+
+/// One row per container of each stopped task, the exit code and reason tell an OOM kill from a failed health check
+fn extract_stopped_tasks(tasks: &[Task], range: &DateTimeRange) -> Result<String, Box<dyn Error>> {
+    if tasks.is_empty() {
+        return Ok("No stopped tasks found\n".to_string());
+    }
+
+    let mut csv_writer = Writer::from_writer(Vec::new());
+    csv_writer.write_record(["task", "stopped_at", "stop_code", "stopped_reason", "container", "exit_code", "container_reason"])?;
+
+    for task in tasks {
+        let task_id = task.task_arn().unwrap_or_default().rsplit('/').next().unwrap_or_default();
+        let stopped_at = millis(task.stopped_at()).map(|millis| range.format_timestamp(millis)).unwrap_or_default();
+        let stop_code = task.stop_code().map(|stop_code| stop_code.as_str()).unwrap_or_default();
+
+        for container in task.containers() {
+            csv_writer.write_record([
+                task_id,
+                &stopped_at,
+                stop_code,
+                task.stopped_reason().unwrap_or_default(),
+                container.name().unwrap_or_default(),
+                &container.exit_code().map(|exit_code| exit_code.to_string()).unwrap_or_default(),
+                container.reason().unwrap_or_default(),
+            ])?;
+        }
+    }
+
+    Ok(String::from_utf8(csv_writer.into_inner()?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_ecs::types::{Container, Deployment, DeploymentRolloutState, LaunchType, ServiceEvent, TaskStopCode};
+
+    const MINUTE: i64 = 60 * 1000;
+
+    struct MockEcsClient {
+        services: Vec<Service>,
+    }
+
+    impl EcsClient for MockEcsClient {
+        async fn describe_services(&self, _: &str, _: &str) -> Result<DescribeServicesOutput, Box<dyn Error>> {
+            Ok(DescribeServicesOutput::builder()
+                .set_services(Some(self.services.clone()))
+                .build())
+        }
+
+        async fn list_stopped_tasks(&self, _: &str, _: &str) -> Result<ListTasksOutput, Box<dyn Error>> {
+            Ok(ListTasksOutput::builder()
+                .task_arns("arn:aws:ecs:us-east-1:123456789012:task/prod/old")
+                .task_arns("arn:aws:ecs:us-east-1:123456789012:task/prod/oom")
+                .build())
+        }
+
+        async fn describe_tasks(&self, _: &str, _: Vec<String>) -> Result<DescribeTasksOutput, Box<dyn Error>> {
+            let task = |id: &str, stopped_at: i64, container: Container| Task::builder()
+                .task_arn(format!("arn:aws:ecs:us-east-1:123456789012:task/prod/{id}"))
+                .stopped_at(DateTime::from_millis(stopped_at))
+                .stop_code(TaskStopCode::EssentialContainerExited)
+                .stopped_reason("Essential container in task exited")
+                .containers(container)
+                .build();
+
+            Ok(DescribeTasksOutput::builder()
+                .tasks(task("old", -MINUTE, Container::builder().name("web").exit_code(0).build()))
+                .tasks(task("oom", 5 * MINUTE, Container::builder().name("web").exit_code(137).reason("OutOfMemoryError: Container killed due to memory usage").build()))
+                .build())
+        }
+    }
+
+    fn service() -> Service {
+        Service::builder()
+            .status("ACTIVE")
+            .launch_type(LaunchType::Fargate)
+            .task_definition("arn:aws:ecs:us-east-1:123456789012:task-definition/web:42")
+            .desired_count(3)
+            .running_count(2)
+            .pending_count(1)
+            .deployments(Deployment::builder()
+                .status("PRIMARY")
+                .task_definition("arn:aws:ecs:us-east-1:123456789012:task-definition/web:42")
+                .rollout_state(DeploymentRolloutState::InProgress)
+                .rollout_state_reason("ECS deployment in progress.")
+                .desired_count(3)
+                .running_count(2)
+                .pending_count(1)
+                .failed_tasks(1)
+                .build())
+            .events(ServiceEvent::builder().created_at(DateTime::from_millis(6 * MINUTE)).message("(service web) has started 1 tasks").build())
+            .events(ServiceEvent::builder().created_at(DateTime::from_millis(2 * MINUTE)).message("(service web) has reached a steady state.").build())
+            .events(ServiceEvent::builder().created_at(DateTime::from_millis(-MINUTE)).message("(service web) is outside the range").build())
+            .build()
+    }
+
+    fn config() -> EcsConfig {
+        EcsConfig {
+            order_no: 1,
+            cluster_name: "prod".to_string(),
+            service_name: "web".to_string(),
+            ..EcsConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data() {
+        let client = MockEcsClient { services: vec![service()] };
+        let range = DateTimeRange { start_time: 0, end_time: 10 * MINUTE, ..DateTimeRange::default() };
+
+        let prompt_data = fetch_data(&client, &config(), &range).await.expect("Should fetch data");
+
+        let expected = [
+            "Events:\n",
+            "1970-01-01 00:02:00 UTC (service web) has reached a steady state.\n",
+            "1970-01-01 00:06:00 UTC (service web) has started 1 tasks\n",
+            "\nStopped tasks:\n",
+            "task,stopped_at,stop_code,stopped_reason,container,exit_code,container_reason\n",
+            "oom,1970-01-01 00:05:00 UTC,EssentialContainerExited,Essential container in task exited,web,137,OutOfMemoryError: Container killed due to memory usage\n",
+        ].join("");
+
+        assert_eq!(prompt_data.description, vec![
+            "Information: [ECS Service]",
+            "Cluster: [`prod`]",
+            "Service: [`web`]",
+            "Status: [ACTIVE]",
+            "Launch type: [FARGATE]",
+            "Task definition: [`web:42`]",
+            "Tasks: [desired 3, running 2, pending 1]",
+            "Deployment: [PRIMARY `web:42`, rollout IN_PROGRESS (ECS deployment in progress.), desired 3, running 2, pending 1, failed tasks 1]",
+        ]);
+        assert_eq!(prompt_data.data, Some(expected));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_not_found() {
+        let client = MockEcsClient { services: vec![] };
+        let range = DateTimeRange::default();
+
+        let err = fetch_data(&client, &config(), &range).await.expect_err("Should not find service");
+
+        assert_eq!(err.to_string(), "Unable to find ECS service with name: prod/web");
+    }
+}
@@ -0,0 +1,502 @@
+use crate::datasource::cloudwatch_metric::{self, CloudwatchMetricConfig};
+use crate::datasource::ds::{DataSource, DataSourceError, SourceOptions};
+use crate::lib::aws::{empty_when_not_found, fetch_all_pages};
+use crate::lib::context::AppContext;
+use crate::lib::prompt::{or_unavailable, PromptData};
+use aws_sdk_elasticloadbalancingv2::operation::describe_listeners::DescribeListenersOutput;
+use aws_sdk_elasticloadbalancingv2::operation::describe_load_balancers::DescribeLoadBalancersOutput;
+use aws_sdk_elasticloadbalancingv2::operation::describe_target_groups::DescribeTargetGroupsOutput;
+use aws_sdk_elasticloadbalancingv2::operation::describe_target_health::DescribeTargetHealthOutput;
+use aws_sdk_elasticloadbalancingv2::types::{Listener, LoadBalancer, LoadBalancerTypeEnum, TargetGroup, TargetHealthDescription};
+use aws_sdk_elasticloadbalancingv2::Client;
+use csv::Writer;
+use futures::future::LocalBoxFuture;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ElbConfig {
+    pub order_no: u8,
+    pub load_balancer_name: String,
+    /// Also sends the standard metrics of an application load balancer
+    #[serde(default)]
+    pub metrics: bool,
+    #[serde(flatten)]
+    pub options: SourceOptions,
+}
+
+impl DataSource for ElbConfig {
+    fn name(&self) -> &str {
+        "Load balancer"
+    }
+
+    fn order_no(&self) -> u8 {
+        self.order_no
+    }
+
+    fn options(&self) -> &SourceOptions {
+        &self.options
+    }
+
+    fn resource_dimensions<'a>(&'a self, context: &'a AppContext) -> LocalBoxFuture<'a, Result<Vec<(String, String)>, DataSourceError>> {
+        Box::pin(async move {
+            let client = context.aws.elb(&self.options.scope()).await;
+            Ok(fetch_resource_dimensions(&client, self).await?)
+        })
+    }
+
+    fn fetch_data<'a>(&'a self, context: &'a AppContext) -> LocalBoxFuture<'a, Result<Vec<PromptData>, DataSourceError>> {
+        Box::pin(async move {
+            let client = context.aws.elb(&self.options.scope()).await;
+            let details = fetch_details(&client, self).await?;
+
+            let mut prompt_data_vec = vec![build_prompt_data(self, &details)?];
+            let metrics = cloudwatch_metric::fetch_attached(context, &self.options, &build_metric_configs(self, &details)).await;
+            prompt_data_vec.extend(or_unavailable("Load balancer metrics", metrics));
+
+            Ok(prompt_data_vec)
+        })
+    }
+}
+
+pub trait ElbClient {
+    async fn describe_load_balancers(&self, name: &str) -> Result<DescribeLoadBalancersOutput, Box<dyn Error>>;
+
+    async fn describe_listeners(&self, load_balancer_arn: &str, marker: Option<String>) -> Result<DescribeListenersOutput, Box<dyn Error>>;
+
+    async fn describe_target_groups(&self, load_balancer_arn: &str, marker: Option<String>) -> Result<DescribeTargetGroupsOutput, Box<dyn Error>>;
+
+    async fn describe_target_health(&self, target_group_arn: &str) -> Result<DescribeTargetHealthOutput, Box<dyn Error>>;
+}
+
+impl ElbClient for Client {
+    async fn describe_load_balancers(&self, name: &str) -> Result<DescribeLoadBalancersOutput, Box<dyn Error>> {
+        let result = self.describe_load_balancers()
+            .names(name)
+            .send()
+            .await;
+
+        empty_when_not_found(result, |err| err.is_load_balancer_not_found_exception(), || DescribeLoadBalancersOutput::builder().build())
+    }
+
+    async fn describe_listeners(&self, load_balancer_arn: &str, marker: Option<String>) -> Result<DescribeListenersOutput, Box<dyn Error>> {
+        Ok(self.describe_listeners()
+            .load_balancer_arn(load_balancer_arn)
+            .set_marker(marker)
+            .send()
+            .await?)
+    }
+
+    async fn describe_target_groups(&self, load_balancer_arn: &str, marker: Option<String>) -> Result<DescribeTargetGroupsOutput, Box<dyn Error>> {
+        Ok(self.describe_target_groups()
+            .load_balancer_arn(load_balancer_arn)
+            .set_marker(marker)
+            .send()
+            .await?)
+    }
+
+    async fn describe_target_health(&self, target_group_arn: &str) -> Result<DescribeTargetHealthOutput, Box<dyn Error>> {
+        Ok(self.describe_target_health()
+            .target_group_arn(target_group_arn)
+            .send()
+            .await?)
+    }
+}
+
+/// A load balancer with its listeners, and the health of the targets of each target group
+pub struct LoadBalancerDetails {
+    load_balancer: LoadBalancer,
+    listeners: Vec<Listener>,
+    target_groups: Vec<(TargetGroup, Vec<TargetHealthDescription>)>,
+}
+
+async fn fetch_load_balancer(client: &impl ElbClient, config: &ElbConfig) -> Result<LoadBalancer, Box<dyn Error>> {
+    Ok(client.describe_load_balancers(&config.load_balancer_name).await?
+        .load_balancers
+        .unwrap_or_default()
+        .into_iter()
+        .next()
+        .ok_or(DataSourceError::NotFound { resource: "load balancer", name: config.load_balancer_name.clone() })?)
+}
+
+async fn fetch_listeners(client: &impl ElbClient, load_balancer_arn: &str) -> Result<Vec<Listener>, Box<dyn Error>> {
+    Ok(fetch_all_pages(|marker| client.describe_listeners(load_balancer_arn, marker), DescribeListenersOutput::next_marker).await?
+        .into_iter()
+        .flat_map(|output| output.listeners.unwrap_or_default())
+        .collect())
+}
+
+async fn fetch_target_groups(client: &impl ElbClient, load_balancer_arn: &str) -> Result<Vec<TargetGroup>, Box<dyn Error>> {
+    Ok(fetch_all_pages(|marker| client.describe_target_groups(load_balancer_arn, marker), DescribeTargetGroupsOutput::next_marker).await?
+        .into_iter()
+        .flat_map(|output| output.target_groups.unwrap_or_default())
+        .collect())
+}
+
+/// The `LoadBalancer` dimension, and the `TargetGroup` dimension of each target group, as found in the alarms
+pub async fn fetch_resource_dimensions(client: &impl ElbClient, config: &ElbConfig) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let load_balancer = fetch_load_balancer(client, config).await?;
+    let load_balancer_arn = load_balancer.load_balancer_arn().ok_or(DataSourceError::MissingField("Load balancer ARN"))?;
+
+    let mut dimensions = vec![("LoadBalancer".to_string(), load_balancer_dimension(load_balancer_arn).to_string())];
+    for target_group in fetch_target_groups(client, load_balancer_arn).await? {
+        let target_group_arn = target_group.target_group_arn().ok_or(DataSourceError::MissingField("Target group ARN"))?;
+        dimensions.push(("TargetGroup".to_string(), target_group_dimension(target_group_arn).to_string()));
+    }
+
+    Ok(dimensions)
+}
+
+pub async fn fetch_details(client: &impl ElbClient, config: &ElbConfig) -> Result<LoadBalancerDetails, Box<dyn Error>> {
+    let load_balancer = fetch_load_balancer(client, config).await?;
+
+    let load_balancer_arn = load_balancer.load_balancer_arn().ok_or(DataSourceError::MissingField("Load balancer ARN"))?;
+    let listeners = fetch_listeners(client, load_balancer_arn).await?;
+
+    let mut target_groups = Vec::new();
+    for target_group in fetch_target_groups(client, load_balancer_arn).await? {
+        let target_group_arn = target_group.target_group_arn().ok_or(DataSourceError::MissingField("Target group ARN"))?;
+        let health = client.describe_target_health(target_group_arn).await?.target_health_descriptions.unwrap_or_default();
+        target_groups.push((target_group, health));
+    }
+
+    Ok(LoadBalancerDetails { load_balancer, listeners, target_groups })
+}
+
+fn build_prompt_data(config: &ElbConfig, details: &LoadBalancerDetails) -> Result<PromptData, Box<dyn Error>> {
+    Ok(PromptData {
+        description: build_description(config, details),
+        data: Some(extract_target_health(details)?),
+    })
+}
+
+fn build_description(config: &ElbConfig, details: &LoadBalancerDetails) -> Vec<String> {
+    let load_balancer = &details.load_balancer;
+    let load_balancer_type = load_balancer.r#type().map(|load_balancer_type| load_balancer_type.as_str()).unwrap_or_default();
+
+    let mut description = vec![
+        "Information: [Load Balancer]".to_string(),
+        format!("Name: [`{}`]", &config.load_balancer_name),
+        format!("Type: [{load_balancer_type}]"),
+        format!("Scheme: [{}]", load_balancer.scheme().map(|scheme| scheme.as_str()).unwrap_or_default()),
+        format!("State: [{}]", load_balancer.state().and_then(|state| state.code()).map(|code| code.as_str()).unwrap_or_default()),
+    ];
+
+    for listener in &details.listeners {
+        let actions = listener.default_actions()
+            .iter()
+            .map(|action| {
+                let action_type = action.r#type().map(|action_type| action_type.as_str()).unwrap_or_default();
+
+                // Weighted target groups, e.g. of a canary or blue/green deployment
+                let weighted = action.forward_config().map(|forward| forward.target_groups()).unwrap_or_default();
+                if weighted.len() > 1 {
+                    let target_groups = weighted.iter()
+                        .map(|target_group| format!(
+                            "`{}` weight {}",
+                            target_group.target_group_arn().map(target_group_name).unwrap_or_default(),
+                            target_group.weight().unwrap_or(1),
+                        ))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    return format!("{action_type} {target_groups}");
+                }
+
+                let target_group = action.target_group_arn()
+                    .or(weighted.first().and_then(|target_group| target_group.target_group_arn()))
+                    .map(target_group_name)
+                    .unwrap_or_default();
+                match target_group.is_empty() {
+                    true => action_type.to_string(),
+                    false => format!("{action_type} `{target_group}`"),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        description.push(format!(
+            "Listener: [{}:{} -> {actions}]",
+            listener.protocol().map(|protocol| protocol.as_str()).unwrap_or_default(),
+            listener.port().unwrap_or_default(),
+        ));
+    }
+
+    for (target_group, health) in &details.target_groups {
+        let healthy = health.iter()
+            .filter(|description| description.target_health().and_then(|health| health.state()).map(|state| state.as_str()) == Some("healthy"))
+            .count();
+
+        let mut line = format!(
+            "Target group: [`{}` {}:{}, {healthy}/{} healthy",
+            target_group.target_group_name().unwrap_or_default(),
+            target_group.protocol().map(|protocol| protocol.as_str()).unwrap_or_default(),
+            target_group.port().unwrap_or_default(),
+            health.len(),
+        );
+        if let Some(path) = target_group.health_check_path() {
+            line.push_str(&format!(", health check `{path}`"));
+        }
+        line.push(']');
+        description.push(line);
+    }
+
+    if config.metrics && load_balancer.r#type() != Some(&LoadBalancerTypeEnum::Application) {
+        description.push("Note: [Metrics are only attached to application load balancers]".to_string());
+    }
+
+    description
+}
+
+/// One row per target, with the reason code of targets that aren't healthy
+fn extract_target_health(details: &LoadBalancerDetails) -> Result<String, Box<dyn Error>> {
+    if details.target_groups.iter().all(|(_, health)| health.is_empty()) {
+        return Ok("No registered targets\n".to_string());
+    }
+
+    let mut csv_writer = Writer::from_writer(Vec::new());
+    csv_writer.write_record(["target_group", "target", "port", "state", "reason", "description"])?;
+
+    for (target_group, health) in &details.target_groups {
+        for description in health {
+            let target = description.target();
+            let target_health = description.target_health();
+
+            csv_writer.write_record([
+                target_group.target_group_name().unwrap_or_default(),
+                target.and_then(|target| target.id()).unwrap_or_default(),
+                &target.and_then(|target| target.port()).map(|port| port.to_string()).unwrap_or_default(),
+                target_health.and_then(|health| health.state()).map(|state| state.as_str()).unwrap_or_default(),
+                target_health.and_then(|health| health.reason()).map(|reason| reason.as_str()).unwrap_or_default(),
+                target_health.and_then(|health| health.description()).unwrap_or_default(),
+            ])?;
+        }
+    }
+
+    Ok(String::from_utf8(csv_writer.into_inner()?)?)
+}
+
+/// `app/name/id` of a load balancer ARN, as expected by the `LoadBalancer` dimension
+fn load_balancer_dimension(load_balancer_arn: &str) -> &str {
+    load_balancer_arn.split_once("loadbalancer/").map(|(_, name)| name).unwrap_or(load_balancer_arn)
+}
+
+/// `targetgroup/name/id` of a target group ARN, as expected by the `TargetGroup` dimension
+fn target_group_dimension(target_group_arn: &str) -> &str {
+    target_group_arn.find("targetgroup/").map(|index| &target_group_arn[index..]).unwrap_or(target_group_arn)
+}
+
+fn target_group_name(target_group_arn: &str) -> &str {
+    target_group_dimension(target_group_arn).split('/').nth(1).unwrap_or(target_group_arn)
+}
+
+/// 5XX counts, response time and request count of the load balancer, and unhealthy hosts of each target group
+fn build_metric_configs(config: &ElbConfig, details: &LoadBalancerDetails) -> Vec<CloudwatchMetricConfig> {
+    if !config.metrics || details.load_balancer.r#type() != Some(&LoadBalancerTypeEnum::Application) {
+        return Vec::new();
+    }
+
+    let load_balancer = load_balancer_dimension(details.load_balancer.load_balancer_arn().unwrap_or_default());
+
+    let metric = |identifier: &str, metric_name: &str, stats: &[&str], dimensions: BTreeMap<String, String>| {
+        CloudwatchMetricConfig::attached(config.order_no, identifier, "AWS/ApplicationELB", metric_name, stats, dimensions, &config.options)
+    };
+    let dimensions = BTreeMap::from([("LoadBalancer".to_string(), load_balancer.to_string())]);
+
+    let mut configs = vec![
+        metric("elb_5xx_count", "HTTPCode_ELB_5XX_Count", &["Sum"], dimensions.clone()),
+        metric("target_5xx_count", "HTTPCode_Target_5XX_Count", &["Sum"], dimensions.clone()),
+        metric("target_response_time", "TargetResponseTime", &["Average", "p99"], dimensions.clone()),
+        metric("request_count", "RequestCount", &["Sum"], dimensions.clone()),
+    ];
+
+    for (index, (target_group, _)) in details.target_groups.iter().enumerate() {
+        let mut dimensions = dimensions.clone();
+        dimensions.insert("TargetGroup".to_string(), target_group_dimension(target_group.target_group_arn().unwrap_or_default()).to_string());
+        configs.push(metric(&format!("unhealthy_host_count_{index}"), "UnHealthyHostCount", &["Maximum"], dimensions));
+    }
+
+    configs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_elasticloadbalancingv2::types::{Action, ActionTypeEnum, ForwardActionConfig, TargetGroupTuple, LoadBalancerSchemeEnum, LoadBalancerState, LoadBalancerStateEnum, ProtocolEnum, TargetDescription, TargetHealth, TargetHealthReasonEnum, TargetHealthStateEnum};
+
+    const LOAD_BALANCER_ARN: &str = "arn:aws:elasticloadbalancing:us-east-1:123456789012:loadbalancer/app/my-alb/50dc6c495c0c9188";
+    const TARGET_GROUP_ARN: &str = "arn:aws:elasticloadbalancing:us-east-1:123456789012:targetgroup/web/73e2d6bc24d8a067";
+    const CANARY_TARGET_GROUP_ARN: &str = "arn:aws:elasticloadbalancing:us-east-1:123456789012:targetgroup/web-canary/0f1e2d3c4b5a6978";
+
+    struct MockElbClient {
+        load_balancer_type: LoadBalancerTypeEnum,
+    }
+
+    impl ElbClient for MockElbClient {
+        async fn describe_load_balancers(&self, name: &str) -> Result<DescribeLoadBalancersOutput, Box<dyn Error>> {
+            if name != "my-alb" {
+                return Ok(DescribeLoadBalancersOutput::builder().build());
+            }
+
+            Ok(DescribeLoadBalancersOutput::builder()
+                .load_balancers(LoadBalancer::builder()
+                    .load_balancer_arn(LOAD_BALANCER_ARN)
+                    .load_balancer_name(name)
+                    .r#type(self.load_balancer_type.clone())
+                    .scheme(LoadBalancerSchemeEnum::InternetFacing)
+                    .state(LoadBalancerState::builder().code(LoadBalancerStateEnum::Active).build())
+                    .build())
+                .build())
+        }
+
+        /// A listener per page
+        async fn describe_listeners(&self, _: &str, marker: Option<String>) -> Result<DescribeListenersOutput, Box<dyn Error>> {
+            if marker.is_none() {
+                return Ok(DescribeListenersOutput::builder()
+                    .listeners(Listener::builder()
+                        .protocol(ProtocolEnum::Https)
+                        .port(443)
+                        .default_actions(Action::builder()
+                            .r#type(ActionTypeEnum::Forward)
+                            .target_group_arn(TARGET_GROUP_ARN)
+                            .build())
+                        .build())
+                    .next_marker("2")
+                    .build());
+            }
+
+            Ok(DescribeListenersOutput::builder()
+                .listeners(Listener::builder()
+                    .protocol(ProtocolEnum::Http)
+                    .port(80)
+                    .default_actions(Action::builder()
+                        .r#type(ActionTypeEnum::Forward)
+                        .forward_config(ForwardActionConfig::builder()
+                            .target_groups(TargetGroupTuple::builder().target_group_arn(TARGET_GROUP_ARN).weight(90).build())
+                            .target_groups(TargetGroupTuple::builder().target_group_arn(CANARY_TARGET_GROUP_ARN).weight(10).build())
+                            .build())
+                        .build())
+                    .build())
+                .build())
+        }
+
+        async fn describe_target_groups(&self, _: &str, _: Option<String>) -> Result<DescribeTargetGroupsOutput, Box<dyn Error>> {
+            Ok(DescribeTargetGroupsOutput::builder()
+                .target_groups(TargetGroup::builder()
+                    .target_group_arn(TARGET_GROUP_ARN)
+                    .target_group_name("web")
+                    .protocol(ProtocolEnum::Http)
+                    .port(8080)
+                    .health_check_path("/health")
+                    .build())
+                .build())
+        }
+
+        async fn describe_target_health(&self, _: &str) -> Result<DescribeTargetHealthOutput, Box<dyn Error>> {
+            let target = |id: &str, state: TargetHealthStateEnum, reason: Option<TargetHealthReasonEnum>, description: Option<&str>| {
+                TargetHealthDescription::builder()
+                    .target(TargetDescription::builder().id(id).port(8080).build())
+                    .target_health(TargetHealth::builder()
+                        .state(state)
+                        .set_reason(reason)
+                        .set_description(description.map(String::from))
+                        .build())
+                    .build()
+            };
+
+            Ok(DescribeTargetHealthOutput::builder()
+                .target_health_descriptions(target("i-0a1", TargetHealthStateEnum::Healthy, None, None))
+                .target_health_descriptions(target(
+                    "i-0b2",
+                    TargetHealthStateEnum::Unhealthy,
+                    Some(TargetHealthReasonEnum::FailedHealthChecks),
+                    Some("Health checks failed with these codes: [502]"),
+                ))
+                .build())
+        }
+    }
+
+    fn config() -> ElbConfig {
+        ElbConfig {
+            order_no: 1,
+            load_balancer_name: "my-alb".to_string(),
+            metrics: true,
+            ..ElbConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_build_prompt_data() {
+        let client = MockElbClient { load_balancer_type: LoadBalancerTypeEnum::Application };
+
+        let details = fetch_details(&client, &config()).await.expect("Should fetch details");
+        let prompt_data = build_prompt_data(&config(), &details).expect("Should build prompt data");
+
+        let expected = [
+            "target_group,target,port,state,reason,description\n",
+            "web,i-0a1,8080,healthy,,\n",
+            "web,i-0b2,8080,unhealthy,Target.FailedHealthChecks,Health checks failed with these codes: [502]\n",
+        ].join("");
+
+        assert_eq!(prompt_data.description, vec![
+            "Information: [Load Balancer]",
+            "Name: [`my-alb`]",
+            "Type: [application]",
+            "Scheme: [internet-facing]",
+            "State: [active]",
+            "Listener: [HTTPS:443 -> forward `web`]",
+            "Listener: [HTTP:80 -> forward `web` weight 90, `web-canary` weight 10]",
+            "Target group: [`web` HTTP:8080, 1/2 healthy, health check `/health`]",
+        ]);
+        assert_eq!(prompt_data.data, Some(expected));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_resource_dimensions() {
+        let client = MockElbClient { load_balancer_type: LoadBalancerTypeEnum::Application };
+
+        let dimensions = fetch_resource_dimensions(&client, &config()).await.expect("Should fetch dimensions");
+
+        assert_eq!(dimensions, vec![
+            ("LoadBalancer".to_string(), "app/my-alb/50dc6c495c0c9188".to_string()),
+            ("TargetGroup".to_string(), "targetgroup/web/73e2d6bc24d8a067".to_string()),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_details_not_found() {
+        let client = MockElbClient { load_balancer_type: LoadBalancerTypeEnum::Application };
+        let config = ElbConfig {
+            load_balancer_name: "unknown".to_string(),
+            ..ElbConfig::default()
+        };
+
+        let err = fetch_details(&client, &config).await.err().expect("Should not find load balancer");
+
+        assert_eq!(err.to_string(), "Unable to find load balancer with name: unknown");
+    }
+
+    #[tokio::test]
+    async fn test_build_metric_configs() {
+        let client = MockElbClient { load_balancer_type: LoadBalancerTypeEnum::Application };
+        let details = fetch_details(&client, &config()).await.expect("Should fetch details");
+
+        let configs = build_metric_configs(&config(), &details);
+
+        let identifiers: Vec<&str> = configs.iter().map(|config| config.metric_identifier.as_str()).collect();
+        assert_eq!(identifiers, vec!["elb_5xx_count", "target_5xx_count", "target_response_time", "request_count", "unhealthy_host_count_0"]);
+        assert_eq!(configs[0].dimensions["LoadBalancer"], "app/my-alb/50dc6c495c0c9188");
+        assert_eq!(configs[4].dimensions["TargetGroup"], "targetgroup/web/73e2d6bc24d8a067");
+    }
+
+    #[tokio::test]
+    async fn test_build_metric_configs_network_load_balancer() {
+        let client = MockElbClient { load_balancer_type: LoadBalancerTypeEnum::Network };
+        let details = fetch_details(&client, &config()).await.expect("Should fetch details");
+
+        let description = build_description(&config(), &details);
+
+        assert!(build_metric_configs(&config(), &details).is_empty());
+        assert_eq!(description.last().unwrap(), "Note: [Metrics are only attached to application load balancers]");
+    }
+}
@@ -0,0 +1,372 @@
+use crate::datasource::cloudwatch_log_events::{self, CloudwatchLogEventsConfig};
+use crate::datasource::cloudwatch_metric::{self, CloudwatchMetricConfig};
+use crate::datasource::ds::{DataSource, DataSourceError, SourceOptions};
+use crate::lib::aws::{empty_when_not_found, fetch_all_pages};
+use crate::lib::context::AppContext;
+use crate::lib::prompt::{or_unavailable, PromptData};
+use aws_sdk_lambda::operation::get_function::GetFunctionOutput;
+use aws_sdk_lambda::operation::list_aliases::ListAliasesOutput;
+use aws_sdk_lambda::operation::list_provisioned_concurrency_configs::ListProvisionedConcurrencyConfigsOutput;
+use aws_sdk_lambda::operation::list_versions_by_function::ListVersionsByFunctionOutput;
+use aws_sdk_lambda::types::{AliasConfiguration, FunctionConfiguration, ProvisionedConcurrencyConfigListItem};
+use aws_sdk_lambda::Client;
+use futures::future::LocalBoxFuture;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+const DEFAULT_MAX_REPORT_LINES: usize = 20;
+
+/// Only the most recent published versions are listed
+const MAX_VERSIONS: usize = 5;
+
+#[derive(Deserialize, Debug, Default)]
+pub struct LambdaConfig {
+    pub order_no: u8,
+    pub function_name: String,
+    /// The latest `REPORT` lines of the function's log group within the time range
+    pub max_report_lines: Option<usize>,
+    #[serde(flatten)]
+    pub options: SourceOptions,
+}
+
+impl DataSource for LambdaConfig {
+    fn name(&self) -> &str {
+        "Lambda function"
+    }
+
+    fn order_no(&self) -> u8 {
+        self.order_no
+    }
+
+    fn options(&self) -> &SourceOptions {
+        &self.options
+    }
+
+    fn resource_dimensions<'a>(&'a self, _: &'a AppContext) -> LocalBoxFuture<'a, Result<Vec<(String, String)>, DataSourceError>> {
+        Box::pin(async move { Ok(vec![("FunctionName".to_string(), self.function_name.clone())]) })
+    }
+
+    fn fetch_data<'a>(&'a self, context: &'a AppContext) -> LocalBoxFuture<'a, Result<Vec<PromptData>, DataSourceError>> {
+        Box::pin(async move {
+            let client = context.aws.lambda(&self.options.scope()).await;
+            let details = fetch_details(&client, self).await?;
+
+            let mut prompt_data_vec = vec![PromptData {
+                description: build_description(self, &details),
+                data: None,
+            }];
+            // A function never invoked has no log group yet, neither failure hides the configuration
+            let metrics = cloudwatch_metric::fetch_attached(context, &self.options, &build_metric_configs(self)).await;
+            prompt_data_vec.extend(or_unavailable("Lambda metrics", metrics));
+
+            let logs_client = context.aws.cloudwatch_logs(&self.options.scope()).await;
+            let report_lines = cloudwatch_log_events::fetch_data(&logs_client, &build_report_config(self, &details), &context.range).await;
+            prompt_data_vec.extend(or_unavailable("Lambda REPORT lines", report_lines.map(|prompt_data| vec![prompt_data])));
+
+            Ok(prompt_data_vec)
+        })
+    }
+}
+
+pub trait LambdaClient {
+    async fn get_function(&self, function_name: &str) -> Result<GetFunctionOutput, Box<dyn Error>>;
+
+    async fn list_aliases(&self, function_name: &str, marker: Option<String>) -> Result<ListAliasesOutput, Box<dyn Error>>;
+
+    async fn list_versions(&self, function_name: &str, marker: Option<String>) -> Result<ListVersionsByFunctionOutput, Box<dyn Error>>;
+
+    async fn list_provisioned_concurrency_configs(&self, function_name: &str, marker: Option<String>) -> Result<ListProvisionedConcurrencyConfigsOutput, Box<dyn Error>>;
+}
+
+impl LambdaClient for Client {
+    async fn get_function(&self, function_name: &str) -> Result<GetFunctionOutput, Box<dyn Error>> {
+        let result = self.get_function()
+            .function_name(function_name)
+            .send()
+            .await;
+
+        empty_when_not_found(result, |err| err.is_resource_not_found_exception(), || GetFunctionOutput::builder().build())
+    }
+
+    async fn list_aliases(&self, function_name: &str, marker: Option<String>) -> Result<ListAliasesOutput, Box<dyn Error>> {
+        Ok(self.list_aliases()
+            .function_name(function_name)
+            .set_marker(marker)
+            .send()
+            .await?)
+    }
+
+    async fn list_versions(&self, function_name: &str, marker: Option<String>) -> Result<ListVersionsByFunctionOutput, Box<dyn Error>> {
+        Ok(self.list_versions_by_function()
+            .function_name(function_name)
+            .set_marker(marker)
+            .send()
+            .await?)
+    }
+
+    async fn list_provisioned_concurrency_configs(&self, function_name: &str, marker: Option<String>) -> Result<ListProvisionedConcurrencyConfigsOutput, Box<dyn Error>> {
+        Ok(self.list_provisioned_concurrency_configs()
+            .function_name(function_name)
+            .set_marker(marker)
+            .send()
+            .await?)
+    }
+}
+
+/// Configuration of `$LATEST`, with the concurrency settings, aliases and versions of the function
+pub struct FunctionDetails {
+    configuration: FunctionConfiguration,
+    reserved_concurrency: Option<i32>,
+    provisioned_concurrency: Vec<ProvisionedConcurrencyConfigListItem>,
+    aliases: Vec<AliasConfiguration>,
+    versions: Vec<String>,
+}
+
+pub async fn fetch_details(client: &impl LambdaClient, config: &LambdaConfig) -> Result<FunctionDetails, Box<dyn Error>> {
+    let function = client.get_function(&config.function_name).await?;
+    let configuration = function.configuration
+        .ok_or(DataSourceError::NotFound { resource: "Lambda function", name: config.function_name.clone() })?;
+    let reserved_concurrency = function.concurrency.and_then(|concurrency| concurrency.reserved_concurrent_executions);
+
+    let aliases = fetch_all_pages(|marker| client.list_aliases(&config.function_name, marker), ListAliasesOutput::next_marker).await?
+        .into_iter()
+        .flat_map(|output| output.aliases.unwrap_or_default())
+        .collect();
+
+    let versions = fetch_all_pages(|marker| client.list_versions(&config.function_name, marker), ListVersionsByFunctionOutput::next_marker).await?;
+
+    // Published versions are numbers, `$LATEST` isn't
+    let mut versions: Vec<u64> = versions.iter()
+        .flat_map(|output| output.versions())
+        .filter_map(|version| version.version()?.parse().ok())
+        .collect();
+    versions.sort();
+    let versions = versions.iter().rev().take(MAX_VERSIONS).rev().map(|version| version.to_string()).collect();
+
+    let provisioned_concurrency = fetch_all_pages(|marker| client.list_provisioned_concurrency_configs(&config.function_name, marker), ListProvisionedConcurrencyConfigsOutput::next_marker).await?
+        .into_iter()
+        .flat_map(|output| output.provisioned_concurrency_configs.unwrap_or_default())
+        .collect();
+
+    Ok(FunctionDetails { configuration, reserved_concurrency, provisioned_concurrency, aliases, versions })
+}
+
+fn build_description(config: &LambdaConfig, details: &FunctionDetails) -> Vec<String> {
+    let configuration = &details.configuration;
+    let architectures = configuration.architectures()
+        .iter()
+        .map(|architecture| architecture.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let mut description = vec![
+        "Information: [Lambda Function]".to_string(),
+        format!("Function name: [`{}`]", &config.function_name),
+        format!("Runtime: [{}]", configuration.runtime().map(|runtime| runtime.as_str()).unwrap_or("container image")),
+        format!("Architecture: [{architectures}]"),
+        format!("Memory: [{} MB]", configuration.memory_size().unwrap_or_default()),
+        format!("Timeout: [{} seconds]", configuration.timeout().unwrap_or_default()),
+        format!("State: [{}]", configuration.state().map(|state| state.as_str()).unwrap_or_default()),
+        format!("Last update status: [{}]", configuration.last_update_status().map(|status| status.as_str()).unwrap_or_default()),
+        format!("Last modified: [{}]", configuration.last_modified().unwrap_or_default()),
+    ];
+
+    match details.reserved_concurrency {
+        Some(reserved) => description.push(format!("Reserved concurrency: [{reserved}]")),
+        None => description.push("Reserved concurrency: [none, uses the unreserved account concurrency]".to_string()),
+    }
+
+    for provisioned in &details.provisioned_concurrency {
+        // The qualifier is the alias or version at the end of the ARN
+        let qualifier = provisioned.function_arn().and_then(|arn| arn.rsplit(':').next()).unwrap_or_default();
+        description.push(format!(
+            "Provisioned concurrency: [`{qualifier}` requested {}, allocated {}, available {}, {}]",
+            provisioned.requested_provisioned_concurrent_executions().unwrap_or_default(),
+            provisioned.allocated_provisioned_concurrent_executions().unwrap_or_default(),
+            provisioned.available_provisioned_concurrent_executions().unwrap_or_default(),
+            provisioned.status().map(|status| status.as_str()).unwrap_or_default(),
+        ));
+    }
+
+    for alias in &details.aliases {
+        description.push(format!("Alias: [`{}` -> version {}]", alias.name().unwrap_or_default(), alias.function_version().unwrap_or_default()));
+    }
+
+    if !details.versions.is_empty() {
+        description.push(format!("Latest versions: [{}]", details.versions.join(", ")));
+    }
+
+    description
+}
+
+/// Errors, throttles, p99 duration and concurrent executions of the function
+fn build_metric_configs(config: &LambdaConfig) -> Vec<CloudwatchMetricConfig> {
+    let dimensions = BTreeMap::from([("FunctionName".to_string(), config.function_name.clone())]);
+    let metric = |identifier: &str, metric_name: &str, stats: &[&str]| {
+        CloudwatchMetricConfig::attached(config.order_no, identifier, "AWS/Lambda", metric_name, stats, dimensions.clone(), &config.options)
+    };
+
+    vec![
+        metric("errors", "Errors", &["Sum"]),
+        metric("throttles", "Throttles", &["Sum"]),
+        metric("duration", "Duration", &["p99"]),
+        metric("concurrent_executions", "ConcurrentExecutions", &["Maximum"]),
+    ]
+}
+
+/// `REPORT` lines tell the duration, max memory used and init duration (cold starts) of each invocation
+fn build_report_config(config: &LambdaConfig, details: &FunctionDetails) -> CloudwatchLogEventsConfig {
+    let log_group_name = details.configuration.logging_config()
+        .and_then(|logging_config| logging_config.log_group())
+        .map(String::from)
+        .unwrap_or_else(|| format!("/aws/lambda/{}", config.function_name));
+
+    CloudwatchLogEventsConfig {
+        order_no: config.order_no,
+        description: format!("REPORT lines of the Lambda function `{}`", config.function_name),
+        log_group_name,
+        filter_pattern: Some("\"REPORT RequestId\"".to_string()),
+        max_events: Some(config.max_report_lines.unwrap_or(DEFAULT_MAX_REPORT_LINES)),
+        options: config.options.clone(),
+        ..CloudwatchLogEventsConfig::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_lambda::types::{Architecture, Concurrency, LastUpdateStatus, LoggingConfig, ProvisionedConcurrencyStatusEnum, Runtime, State};
+
+    struct MockLambdaClient {
+        function_name: String,
+    }
+
+    impl LambdaClient for MockLambdaClient {
+        async fn get_function(&self, function_name: &str) -> Result<GetFunctionOutput, Box<dyn Error>> {
+            if function_name != self.function_name {
+                return Ok(GetFunctionOutput::builder().build());
+            }
+
+            Ok(GetFunctionOutput::builder()
+                .configuration(FunctionConfiguration::builder()
+                    .function_name(function_name)
+                    .runtime(Runtime::Nodejs20x)
+                    .architectures(Architecture::Arm64)
+                    .memory_size(512)
+                    .timeout(30)
+                    .state(State::Active)
+                    .last_update_status(LastUpdateStatus::Successful)
+                    .last_modified("2025-03-01T12:00:00.000+0000")
+                    .build())
+                .concurrency(Concurrency::builder().reserved_concurrent_executions(50).build())
+                .build())
+        }
+
+        async fn list_aliases(&self, _: &str, _: Option<String>) -> Result<ListAliasesOutput, Box<dyn Error>> {
+            Ok(ListAliasesOutput::builder()
+                .aliases(AliasConfiguration::builder().name("live").function_version("12").build())
+                .build())
+        }
+
+        /// Versions are split over two pages
+        async fn list_versions(&self, _: &str, marker: Option<String>) -> Result<ListVersionsByFunctionOutput, Box<dyn Error>> {
+            let version = |version: &str| FunctionConfiguration::builder().version(version).build();
+
+            Ok(match marker {
+                None => ListVersionsByFunctionOutput::builder()
+                    .versions(version("$LATEST"))
+                    .versions(version("2"))
+                    .versions(version("7"))
+                    .versions(version("8"))
+                    .next_marker("page-2")
+                    .build(),
+                Some(_) => ListVersionsByFunctionOutput::builder()
+                    .versions(version("9"))
+                    .versions(version("10"))
+                    .versions(version("12"))
+                    .build(),
+            })
+        }
+
+        async fn list_provisioned_concurrency_configs(&self, _: &str, _: Option<String>) -> Result<ListProvisionedConcurrencyConfigsOutput, Box<dyn Error>> {
+            Ok(ListProvisionedConcurrencyConfigsOutput::builder()
+                .provisioned_concurrency_configs(ProvisionedConcurrencyConfigListItem::builder()
+                    .function_arn("arn:aws:lambda:us-east-1:123456789012:function:checkout:live")
+                    .requested_provisioned_concurrent_executions(10)
+                    .allocated_provisioned_concurrent_executions(10)
+                    .available_provisioned_concurrent_executions(10)
+                    .status(ProvisionedConcurrencyStatusEnum::Ready)
+                    .build())
+                .build())
+        }
+    }
+
+    fn config() -> LambdaConfig {
+        LambdaConfig {
+            order_no: 1,
+            function_name: "checkout".to_string(),
+            ..LambdaConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_build_description() {
+        let client = MockLambdaClient { function_name: "checkout".to_string() };
+
+        let details = fetch_details(&client, &config()).await.expect("Should fetch details");
+        let description = build_description(&config(), &details);
+
+        assert_eq!(description, vec![
+            "Information: [Lambda Function]",
+            "Function name: [`checkout`]",
+            "Runtime: [nodejs20.x]",
+            "Architecture: [arm64]",
+            "Memory: [512 MB]",
+            "Timeout: [30 seconds]",
+            "State: [Active]",
+            "Last update status: [Successful]",
+            "Last modified: [2025-03-01T12:00:00.000+0000]",
+            "Reserved concurrency: [50]",
+            "Provisioned concurrency: [`live` requested 10, allocated 10, available 10, READY]",
+            "Alias: [`live` -> version 12]",
+            "Latest versions: [7, 8, 9, 10, 12]",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_details_not_found() {
+        let client = MockLambdaClient { function_name: "other".to_string() };
+
+        let err = fetch_details(&client, &config()).await.err().expect("Should not find function");
+
+        assert_eq!(err.to_string(), "Unable to find Lambda function with name: checkout");
+    }
+
+    #[tokio::test]
+    async fn test_build_report_config() {
+        let client = MockLambdaClient { function_name: "checkout".to_string() };
+        let mut details = fetch_details(&client, &config()).await.expect("Should fetch details");
+
+        let default_log_group = build_report_config(&config(), &details);
+        details.configuration = FunctionConfiguration::builder()
+            .logging_config(LoggingConfig::builder().log_group("/custom/checkout").build())
+            .build();
+        let custom_log_group = build_report_config(&config(), &details);
+
+        assert_eq!(default_log_group.log_group_name, "/aws/lambda/checkout");
+        assert_eq!(default_log_group.filter_pattern, Some("\"REPORT RequestId\"".to_string()));
+        assert_eq!(default_log_group.max_events, Some(20));
+        assert_eq!(custom_log_group.log_group_name, "/custom/checkout");
+    }
+
+    #[test]
+    fn test_build_metric_configs() {
+        let configs = build_metric_configs(&config());
+
+        let metrics: Vec<(&str, &str)> = configs.iter().map(|config| (config.metric_name.as_str(), config.metric_namespace.as_str())).collect();
+        assert_eq!(metrics, vec![("Errors", "AWS/Lambda"), ("Throttles", "AWS/Lambda"), ("Duration", "AWS/Lambda"), ("ConcurrentExecutions", "AWS/Lambda")]);
+        assert_eq!(configs[2].metric_stats, Some(vec!["p99".to_string()]));
+        assert_eq!(configs[0].dimensions["FunctionName"], "checkout");
+    }
+}
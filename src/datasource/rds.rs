@@ -1,10 +1,46 @@
-use crate::lib::config::RdsConfig;
+use crate::datasource::ds::{DataSource, DataSourceError, SourceOptions};
+use crate::lib::context::AppContext;
 use crate::lib::prompt::PromptData;
 use aws_sdk_rds::operation::describe_db_instances::DescribeDbInstancesOutput;
 use aws_sdk_rds::types::DbInstance;
 use aws_sdk_rds::Client;
+use futures::future::LocalBoxFuture;
+use serde::Deserialize;
 use std::error::Error;
 
+#[derive(Deserialize, Debug, Default)]
+pub struct RdsConfig {
+    pub order_no: u8,
+    pub db_identifier: String,
+    #[serde(flatten)]
+    pub options: SourceOptions,
+}
+
+impl DataSource for RdsConfig {
+    fn name(&self) -> &str {
+        "RDS instance"
+    }
+
+    fn order_no(&self) -> u8 {
+        self.order_no
+    }
+
+    fn options(&self) -> &SourceOptions {
+        &self.options
+    }
+
+    fn resource_dimensions<'a>(&'a self, _: &'a AppContext) -> LocalBoxFuture<'a, Result<Vec<(String, String)>, DataSourceError>> {
+        Box::pin(async move { Ok(vec![("DBInstanceIdentifier".to_string(), self.db_identifier.clone())]) })
+    }
+
+    fn fetch_data<'a>(&'a self, context: &'a AppContext) -> LocalBoxFuture<'a, Result<Vec<PromptData>, DataSourceError>> {
+        Box::pin(async move {
+            let client = context.aws.rds(&self.options.scope()).await;
+            Ok(vec![fetch_data(client, self).await?])
+        })
+    }
+}
+
 pub trait RdsClient {
     async fn describe_db_instances(&self) -> Result<DescribeDbInstancesOutput, Box<dyn Error>>;
 }
@@ -30,18 +66,22 @@ pub async fn fetch_data(client: impl RdsClient, config: &RdsConfig) -> Result<Pr
         }
     }
 
-    panic!("Unable to find DB instance with name: {}", config.db_identifier);
+    Err(DataSourceError::NotFound {
+        resource: "DB instance",
+        name: config.db_identifier.clone()
+    }.into())
 }
 
+/// Fields missing from a sparse response are shown as N/A rather than failing the data source
 fn build_description(config: &RdsConfig, instance: &DbInstance) -> Vec<String> {
     vec![
         "Information: [RDS Instance]".to_string(),
         format!("DB identifier: [`{}`]", &config.db_identifier),
-        format!("Class: [`{}`]", instance.db_instance_class().unwrap()),
-        format!("Engine: [{} {}]", instance.engine().unwrap(), instance.engine_version().unwrap()),
-        format!("Storage type: [{}]", instance.storage_type().unwrap()),
-        format!("Status: [{}]", instance.db_instance_status().unwrap()),
-        format!("Multi AZ: [{}]", instance.multi_az().unwrap()),
+        format!("Class: [`{}`]", instance.db_instance_class().unwrap_or("N/A")),
+        format!("Engine: [{} {}]", instance.engine().unwrap_or("N/A"), instance.engine_version().unwrap_or("N/A")),
+        format!("Storage type: [{}]", instance.storage_type().unwrap_or("N/A")),
+        format!("Status: [{}]", instance.db_instance_status().unwrap_or("N/A")),
+        format!("Multi AZ: [{}]", instance.multi_az().map_or("N/A".to_string(), |multi_az| multi_az.to_string())),
     ]
 }
 
@@ -77,12 +117,13 @@ mod tests {
         let config = RdsConfig {
             order_no: 1,
             db_identifier: "db-identifier-name".to_string(),
+            ..RdsConfig::default()
         };
 
         let prompt_data = fetch_data(client, &config).await.expect("Should be able to fetch data");
 
         assert_eq!(prompt_data.description.len(), 7);
-        assert_eq!(prompt_data.description.get(0).unwrap(), "Information: [RDS Instance]");
+        assert_eq!(prompt_data.description.first().unwrap(), "Information: [RDS Instance]");
         assert_eq!(prompt_data.description.get(1).unwrap(), "DB identifier: [`db-identifier-name`]");
         assert_eq!(prompt_data.description.get(2).unwrap(), "Class: [`db.t4g.medium`]");
         assert_eq!(prompt_data.description.get(3).unwrap(), "Engine: [postgresql 16.1]");
@@ -93,7 +134,6 @@ mod tests {
     }
 
     #[tokio::test]
-    #[should_panic(expected = "Unable to find DB instance with name: db-identifier-name-1")]
     async fn test_fetch_data_not_found() {
         let client = MockRdsClient {
             db_instance_identifier: "db-identifier-name-2".to_string()
@@ -101,8 +141,25 @@ mod tests {
         let config = RdsConfig {
             order_no: 1,
             db_identifier: "db-identifier-name-1".to_string(),
+            ..RdsConfig::default()
+        };
+
+        let err = fetch_data(client, &config).await.expect_err("Should not find DB instance");
+
+        assert_eq!(err.to_string(), "Unable to find DB instance with name: db-identifier-name-1");
+        assert!(matches!(DataSourceError::from(err), DataSourceError::NotFound { .. }));
+    }
+
+    #[test]
+    fn test_build_description_sparse_instance() {
+        let config = RdsConfig {
+            db_identifier: "db-identifier-name".to_string(),
+            ..RdsConfig::default()
         };
 
-        fetch_data(client, &config).await.expect("Should be able to fetch data");
+        let description = build_description(&config, &DbInstance::builder().build());
+
+        assert_eq!(description[3], "Engine: [N/A N/A]");
+        assert_eq!(description[6], "Multi AZ: [N/A]");
     }
 }
@@ -0,0 +1,240 @@
+use crate::lib::summary::{percentile, round};
+
+/// Robust z-score above which a datapoint is an outlier
+const OUTLIER_SCORE: f64 = 3.5;
+
+/// Outliers are meaningless on a handful of datapoints
+const MIN_OUTLIER_POINTS: usize = 8;
+
+/// Datapoints compared before and after a candidate level shift
+const SHIFT_WINDOW: usize = 5;
+
+/// The means must differ by this many standard deviations of the noisiest window
+const SHIFT_SCORE: f64 = 3.0;
+
+/// And by at least this ratio, so that flat series don't report tiny steps
+const MIN_SHIFT_RATIO: f64 = 0.1;
+
+#[derive(Debug, Default)]
+pub struct Thresholds {
+    pub warn_above: Option<f64>,
+    pub warn_below: Option<f64>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AnomalyKind {
+    Outlier,
+    LevelShift { before: f64, after: f64 },
+    Above(f64),
+    Below(f64),
+}
+
+/// An interval of consecutive anomalous datapoints, `peak` being the most anomalous value
+#[derive(Debug, PartialEq)]
+pub struct Anomaly {
+    pub kind: AnomalyKind,
+    pub start: i64,
+    pub end: i64,
+    pub peak: f64,
+}
+
+impl Anomaly {
+    pub fn describe(&self, format_timestamp: impl Fn(i64) -> String) -> String {
+        let interval = match self.start == self.end {
+            true => format!("at {}", format_timestamp(self.start)),
+            false => format!("from {} to {}", format_timestamp(self.start), format_timestamp(self.end)),
+        };
+
+        match &self.kind {
+            AnomalyKind::Outlier => format!("outlier {interval}, peak {}", round(self.peak)),
+            AnomalyKind::LevelShift { before, after } => format!("level shift {interval}, mean {} -> {}", round(*before), round(*after)),
+            AnomalyKind::Above(limit) => format!("above {limit} {interval}, peak {}", round(self.peak)),
+            AnomalyKind::Below(limit) => format!("below {limit} {interval}, lowest {}", round(self.peak)),
+        }
+    }
+}
+
+/// `points` are `(timestamp millis, value)` in chronological order
+pub fn detect(points: &[(i64, f64)], thresholds: &Thresholds, statistical: bool) -> Vec<Anomaly> {
+    let mut anomalies = Vec::new();
+
+    if statistical {
+        anomalies.extend(outliers(points));
+        anomalies.extend(level_shifts(points));
+    }
+
+    if let Some(limit) = thresholds.warn_above {
+        anomalies.extend(intervals(points, |value| value > limit, f64::max, || AnomalyKind::Above(limit)));
+    }
+
+    if let Some(limit) = thresholds.warn_below {
+        anomalies.extend(intervals(points, |value| value < limit, f64::min, || AnomalyKind::Below(limit)));
+    }
+
+    anomalies.sort_by_key(|anomaly| anomaly.start);
+    anomalies
+}
+
+/// Modified z-score based on the median absolute deviation, which a few outliers can't skew
+fn outliers(points: &[(i64, f64)]) -> Vec<Anomaly> {
+    if points.len() < MIN_OUTLIER_POINTS {
+        return Vec::new();
+    }
+
+    let mut sorted: Vec<f64> = points.iter().map(|(_, value)| *value).collect();
+    sorted.sort_by(f64::total_cmp);
+    let median = percentile(&sorted, 50.0);
+
+    let mut deviations: Vec<f64> = sorted.iter().map(|value| (value - median).abs()).collect();
+    deviations.sort_by(f64::total_cmp);
+    let mad = percentile(&deviations, 50.0);
+
+    // Mostly flat series have no MAD, fall back to the mean absolute deviation
+    let scale = match mad > 0.0 {
+        true => mad / 0.6745,
+        false => 1.253314 * deviations.iter().sum::<f64>() / deviations.len() as f64,
+    };
+    if scale == 0.0 {
+        return Vec::new();
+    }
+
+    let farthest = |a: f64, b: f64| if (a - median).abs() >= (b - median).abs() { a } else { b };
+    intervals(points, |value| (value - median).abs() / scale > OUTLIER_SCORE, farthest, || AnomalyKind::Outlier)
+}
+
+/// Compares the mean of the windows before and after each datapoint, keeping the largest step of each run
+fn level_shifts(points: &[(i64, f64)]) -> Vec<Anomaly> {
+    if points.len() < 2 * SHIFT_WINDOW {
+        return Vec::new();
+    }
+
+    let mut anomalies: Vec<Anomaly> = Vec::new();
+    let mut best: Option<(usize, f64, f64, f64)> = None;
+
+    for index in SHIFT_WINDOW..=points.len() - SHIFT_WINDOW {
+        let (before, before_stddev) = mean_stddev(&points[index - SHIFT_WINDOW..index]);
+        let (after, after_stddev) = mean_stddev(&points[index..index + SHIFT_WINDOW]);
+
+        let step = (after - before).abs();
+        let shifted = step > SHIFT_SCORE * before_stddev.max(after_stddev)
+            && step > MIN_SHIFT_RATIO * before.abs().max(after.abs());
+
+        match (shifted, best) {
+            (true, Some((_, best_step, _, _))) if best_step >= step => {},
+            (true, _) => best = Some((index, step, before, after)),
+            (false, Some((best_index, _, before, after))) => {
+                anomalies.push(level_shift(points, best_index, before, after));
+                best = None;
+            },
+            (false, None) => {},
+        }
+    }
+
+    if let Some((best_index, _, before, after)) = best {
+        anomalies.push(level_shift(points, best_index, before, after));
+    }
+
+    anomalies
+}
+
+fn level_shift(points: &[(i64, f64)], index: usize, before: f64, after: f64) -> Anomaly {
+    Anomaly {
+        kind: AnomalyKind::LevelShift { before, after },
+        start: points[index].0,
+        end: points[index].0,
+        peak: points[index].1,
+    }
+}
+
+fn mean_stddev(points: &[(i64, f64)]) -> (f64, f64) {
+    let count = points.len() as f64;
+    let mean = points.iter().map(|(_, value)| value).sum::<f64>() / count;
+    let variance = points.iter().map(|(_, value)| (value - mean).powi(2)).sum::<f64>() / count;
+    (mean, variance.sqrt())
+}
+
+/// Groups consecutive datapoints matching `predicate`, `peak` picks the value reported for each group
+fn intervals(points: &[(i64, f64)], predicate: impl Fn(f64) -> bool, peak: impl Fn(f64, f64) -> f64, kind: impl Fn() -> AnomalyKind) -> Vec<Anomaly> {
+    let mut anomalies: Vec<Anomaly> = Vec::new();
+    let mut previous_matched = false;
+
+    for (timestamp, value) in points {
+        let matched = predicate(*value);
+
+        match (matched, previous_matched, anomalies.last_mut()) {
+            (true, true, Some(anomaly)) => {
+                anomaly.end = *timestamp;
+                anomaly.peak = peak(anomaly.peak, *value);
+            },
+            (true, _, _) => anomalies.push(Anomaly { kind: kind(), start: *timestamp, end: *timestamp, peak: *value }),
+            _ => {},
+        }
+
+        previous_matched = matched;
+    }
+
+    anomalies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn series(values: &[f64]) -> Vec<(i64, f64)> {
+        values.iter().enumerate().map(|(index, value)| (index as i64, *value)).collect()
+    }
+
+    #[test]
+    fn test_detect_outlier() {
+        let points = series(&[10.0, 11.0, 10.0, 9.0, 10.0, 55.0, 60.0, 10.0, 11.0, 10.0]);
+
+        let anomalies = detect(&points, &Thresholds::default(), true);
+
+        assert_eq!(anomalies, vec![Anomaly { kind: AnomalyKind::Outlier, start: 5, end: 6, peak: 60.0 }]);
+    }
+
+    #[test]
+    fn test_detect_outlier_on_flat_series() {
+        let points = series(&[1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 50.0]);
+
+        let anomalies = detect(&points, &Thresholds::default(), true);
+
+        assert_eq!(anomalies, vec![Anomaly { kind: AnomalyKind::Outlier, start: 7, end: 7, peak: 50.0 }]);
+        assert!(detect(&series(&[1.0; 10]), &Thresholds::default(), true).is_empty());
+    }
+
+    #[test]
+    fn test_detect_level_shift() {
+        let points = series(&[10.0, 11.0, 10.0, 9.0, 10.0, 11.0, 30.0, 31.0, 30.0, 29.0, 30.0, 31.0]);
+
+        let anomalies = detect(&points, &Thresholds::default(), true);
+
+        assert_eq!(anomalies, vec![Anomaly {
+            kind: AnomalyKind::LevelShift { before: 10.2, after: 30.0 },
+            start: 6,
+            end: 6,
+            peak: 30.0,
+        }]);
+    }
+
+    #[test]
+    fn test_detect_thresholds() {
+        let points = series(&[50.0, 85.0, 90.0, 70.0, 5.0, 95.0]);
+        let thresholds = Thresholds { warn_above: Some(80.0), warn_below: Some(10.0) };
+
+        let anomalies = detect(&points, &thresholds, false);
+
+        assert_eq!(anomalies, vec![
+            Anomaly { kind: AnomalyKind::Above(80.0), start: 1, end: 2, peak: 90.0 },
+            Anomaly { kind: AnomalyKind::Below(10.0), start: 4, end: 4, peak: 5.0 },
+            Anomaly { kind: AnomalyKind::Above(80.0), start: 5, end: 5, peak: 95.0 },
+        ]);
+    }
+
+    #[test]
+    fn test_describe() {
+        let anomaly = Anomaly { kind: AnomalyKind::Above(80.0), start: 1, end: 2, peak: 90.123456 };
+
+        assert_eq!(anomaly.describe(|timestamp| format!("t{timestamp}")), "above 80 from t1 to t2, peak 90.123");
+    }
+}
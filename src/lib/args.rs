@@ -1,8 +1,11 @@
-use chrono::{NaiveDateTime, TimeZone};
+use chrono::{Days, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
 use chrono_tz::Tz;
 use clap::Parser;
 use std::error::Error;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::Duration;
+
+/// Shorter numbers are ambiguous, they are rejected rather than read as epoch millis
+const MIN_EPOCH_MILLIS_DIGITS: usize = 12;
 
 // Automatically performs diagnosis on your AWS environment with AI
 #[derive(Parser, Debug)]
@@ -11,94 +14,313 @@ pub struct Args {
     /// Configuration file to use
     pub file: String,
 
-    /// Duration in seconds, since the current date time
-    #[arg(long, default_value_t = 3600)]
-    pub duration: u64,
+    /// Duration since the current date time, in seconds or with a unit [e.g. 90m, 2h, 1d]
+    #[arg(long, default_value = "3600")]
+    pub duration: String,
 
-    /// Start time [format: %Y-%m-%d %H:%M:%S].
+    /// Start time until the end time, or now [e.g. 2h, yesterday 14:00].
+    /// If provided, ignores duration argument
+    #[arg(long)]
+    pub since: Option<String>,
+
+    /// Start time [e.g. %Y-%m-%d %H:%M:%S, RFC3339, epoch millis, yesterday 14:00, now-2h, 2h].
     /// If provided, ignores duration argument
     #[arg(long)]
     pub start: Option<String>,
 
-    /// End time [format: %Y-%m-%d %H:%M:%S].
+    /// End time [e.g. %Y-%m-%d %H:%M:%S, RFC3339, epoch millis, today 09:00, now-15m].
     /// If provided, ignores duration argument
     #[arg(long)]
     pub end: Option<String>,
 
+    /// Time of the incident, the window is centered on it [same formats as start].
+    /// If provided, ignores duration argument
+    #[arg(long)]
+    pub around: Option<String>,
+
+    /// Duration before and after the around time [e.g. 30m, 1h]
+    #[arg(long, default_value = "30m")]
+    pub padding: String,
+
     /// Print the raw prompt data
     #[arg(long, default_value_t = false)]
     pub print_prompt_data: bool,
 
     /// Dry run mode, don't generate diagnosis
     #[arg(long, default_value_t = false)]
-    pub dry_run: bool
+    pub dry_run: bool,
+
+    /// Maximum number of data sources fetched at the same time
+    #[arg(long, default_value_t = 4)]
+    pub concurrency: usize,
+
+    /// Compares with the same window shifted into the past [e.g. 1d, 7d, 12h]
+    #[arg(long)]
+    pub baseline: Option<String>,
+
+    /// Baseline start time, same formats as start.
+    /// Used with baseline-end instead of a baseline offset
+    #[arg(long)]
+    pub baseline_start: Option<String>,
+
+    /// Baseline end time, same formats as end.
+    /// Used with baseline-start instead of a baseline offset
+    #[arg(long)]
+    pub baseline_end: Option<String>
 }
 
-pub fn build_start_and_end(args: &Args, time_zone: Tz) -> Result<(Duration, Duration), Box<dyn Error>> {
-    let start_time: Duration;
-    let end_time: Duration;
+/// `now` is sampled once by the caller, so that the range, baseline and event time agree
+pub fn build_start_and_end(args: &Args, time_zone: Tz, now: Duration) -> Result<(Duration, Duration), Box<dyn Error>> {
+    if let Some(event_time) = build_event_time(args, time_zone, now)? {
+        if args.since.is_some() || args.start.is_some() || args.end.is_some() {
+            return Err("around can't be used with since, start or end arguments".into());
+        }
 
-    match (&args.duration, &args.start, &args.end) {
-        // start & end are both present
-        (_, Some(s), Some(e)) => {
-            start_time = parse_date_time(s, time_zone)?;
-            end_time = parse_date_time(e, time_zone)?;
+        let padding = parse_duration(&args.padding)?;
+        let start_time = event_time.checked_sub(padding).ok_or("Padding is too large")?;
+        // Recent incidents can't be padded into the future
+        let end_time = (event_time + padding).min(now);
+
+        validate_range(start_time, end_time, now)?;
+        return Ok((start_time, end_time));
+    }
+
+    let (start_time, end_time) = match (&args.since, &args.start, &args.end) {
+        (Some(_), Some(_), _) => return Err("Only one of since and start arguments can be provided".into()),
+        // since until end, or now
+        (Some(since), None, end) => {
+            let end_time = match end {
+                Some(e) => parse_time(e, time_zone, now)?,
+                None => now
+            };
+            (parse_time(since, time_zone, now)?, end_time)
         },
+        // start & end are both present
+        (None, Some(s), Some(e)) => (parse_time(s, time_zone, now)?, parse_time(e, time_zone, now)?),
         // start & end are both missing, use duration argument
-        (_, None, None) => {
-            end_time = SystemTime::now().duration_since(UNIX_EPOCH)?;
-            start_time = end_time.checked_sub(Duration::from_secs(args.duration)).unwrap();
-        }
-        _ => {
-            panic!("Both start and end arguments must be provided");
+        (None, None, None) => {
+            let duration = parse_duration(&args.duration)?;
+            (now.checked_sub(duration).ok_or("Duration is too large")?, now)
+        },
+        _ => return Err("Both start and end arguments must be provided".into())
+    };
+
+    validate_range(start_time, end_time, now)?;
+    Ok((start_time, end_time))
+}
+
+/// The incident time given with `around`, if any
+pub fn build_event_time(args: &Args, time_zone: Tz, now: Duration) -> Result<Option<Duration>, Box<dyn Error>> {
+    let Some(around) = &args.around else {
+        return Ok(None);
+    };
+
+    let event_time = parse_time(around, time_zone, now)?;
+    if event_time > now {
+        return Err("Around time must not be in the future".into());
+    }
+
+    Ok(Some(event_time))
+}
+
+fn validate_range(start_time: Duration, end_time: Duration, now: Duration) -> Result<(), Box<dyn Error>> {
+    if start_time >= end_time {
+        return Err("Start time must be before end time".into());
+    }
+
+    if end_time > now {
+        return Err("End time must not be in the future".into());
+    }
+
+    Ok(())
+}
+
+/// The window compared with `start` and `end`, if any
+pub fn build_baseline(args: &Args, time_zone: Tz, start_time: Duration, end_time: Duration, now: Duration) -> Result<Option<(Duration, Duration)>, Box<dyn Error>> {
+    let baseline = match (&args.baseline, &args.baseline_start, &args.baseline_end) {
+        (None, None, None) => return Ok(None),
+        (Some(offset), None, None) => {
+            let offset = parse_duration(offset)?;
+            let start_time = start_time.checked_sub(offset).ok_or("Baseline offset is too large")?;
+            (start_time, end_time - offset)
+        },
+        (None, Some(s), Some(e)) => (parse_time(s, time_zone, now)?, parse_time(e, time_zone, now)?),
+        _ => return Err("Either baseline, or both baseline-start and baseline-end arguments must be provided".into())
+    };
+
+    validate_range(baseline.0, baseline.1, now)?;
+    Ok(Some(baseline))
+}
+
+/// Durations in seconds, or with units such as `90s`, `15m`, `12h`, `7d`, `2w` or `1h30m`
+pub fn parse_duration(s: &str) -> Result<Duration, Box<dyn Error>> {
+    let s = s.trim();
+    let invalid = || format!("Invalid duration: {s}");
+
+    if !s.is_empty() && s.chars().all(|c| c.is_ascii_digit()) {
+        return Ok(Duration::from_secs(s.parse()?));
+    }
+
+    let mut seconds: u64 = 0;
+    let mut rest = s;
+
+    while !rest.is_empty() {
+        let (amount, remaining) = rest.split_at(rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len()));
+        let amount: u64 = amount.parse().map_err(|_| invalid())?;
+
+        let unit_length = remaining.find(|c: char| c.is_ascii_digit()).unwrap_or(remaining.len());
+        let (unit, remaining) = remaining.split_at(unit_length);
+
+        let unit_seconds = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            "w" => 7 * 24 * 60 * 60,
+            _ => return Err(invalid().into())
+        };
+
+        seconds = amount.checked_mul(unit_seconds)
+            .and_then(|amount_seconds| seconds.checked_add(amount_seconds))
+            .ok_or_else(invalid)?;
+        rest = remaining;
+    }
+
+    match s.is_empty() {
+        true => Err(invalid().into()),
+        false => Ok(Duration::from_secs(seconds))
+    }
+}
+
+/// Absolute or relative to `now`: `now`, `now-15m`, `2h` (ago), `today 14:00`, `yesterday 14:00`,
+/// `%Y-%m-%d %H:%M:%S` in the time zone, RFC3339 or epoch millis
+pub fn parse_time(s: &str, time_zone: Tz, now: Duration) -> Result<Duration, Box<dyn Error>> {
+    let s = s.trim();
+
+    if s == "now" {
+        return Ok(now);
+    }
+
+    if let Some(offset) = s.strip_prefix("now-") {
+        return Ok(now.checked_sub(parse_duration(offset)?).ok_or(format!("Invalid time: {s}"))?);
+    }
+
+    // Epoch millis have had 12 digits since 1973, a shorter number could be seconds ago or epoch seconds
+    if !s.is_empty() && s.chars().all(|c| c.is_ascii_digit()) {
+        if s.len() < MIN_EPOCH_MILLIS_DIGITS {
+            return Err(format!("Invalid time: {s}, a number needs a unit (e.g. 1h ago) or use a timestamp").into());
         }
+
+        return Ok(Duration::from_millis(s.parse()?));
     }
 
-    Ok((start_time, end_time))
+    if let Ok(date_time) = chrono::DateTime::parse_from_rfc3339(s) {
+        return from_millis(date_time.timestamp_millis());
+    }
+
+    if let Some(date_time) = parse_relative_day(s, time_zone, now)? {
+        return Ok(date_time);
+    }
+
+    if let Ok(ago) = parse_duration(s) {
+        return Ok(now.checked_sub(ago).ok_or(format!("Invalid time: {s}"))?);
+    }
+
+    parse_date_time(s, time_zone)
+}
+
+/// `today` or `yesterday`, optionally followed by `%H:%M` or `%H:%M:%S`
+fn parse_relative_day(s: &str, time_zone: Tz, now: Duration) -> Result<Option<Duration>, Box<dyn Error>> {
+    let (day, time) = s.split_once(' ').unwrap_or((s, "00:00"));
+
+    let today = time_zone.timestamp_millis_opt(now.as_millis() as i64)
+        .single()
+        .ok_or("Invalid current time")?
+        .date_naive();
+    let date = match day {
+        "today" => today,
+        "yesterday" => today.checked_sub_days(Days::new(1)).ok_or("Invalid current time")?,
+        _ => return Ok(None)
+    };
+
+    let time = NaiveTime::parse_from_str(time.trim(), "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(time.trim(), "%H:%M"))
+        .map_err(|_| format!("Invalid time: {s}"))?;
+
+    Ok(Some(from_local(date.and_time(time), time_zone)?))
 }
 
 fn parse_date_time(s: &str, time_zone: Tz) -> Result<Duration, Box<dyn Error>> {
-    let format = "%Y-%m-%d %H:%M:%S";
+    let formats = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S"];
 
-    Ok(NaiveDateTime::parse_from_str(s, format)
-        .map(|ndt| time_zone.from_local_datetime(&ndt).unwrap())
-        .map(|dt| Duration::from_millis(dt.timestamp_millis() as u64))?)
+    let date_time = formats.iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+        .or_else(|| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok().map(|date| date.and_time(NaiveTime::MIN)))
+        .ok_or(format!("Invalid time: {s}"))?;
+
+    from_local(date_time, time_zone)
+}
+
+fn from_local(date_time: NaiveDateTime, time_zone: Tz) -> Result<Duration, Box<dyn Error>> {
+    // Times skipped by daylight saving don't exist, repeated ones use the earliest
+    let date_time = time_zone.from_local_datetime(&date_time)
+        .earliest()
+        .ok_or(format!("Invalid time in {time_zone}: {date_time}"))?;
+
+    from_millis(date_time.timestamp_millis())
+}
+
+fn from_millis(millis: i64) -> Result<Duration, Box<dyn Error>> {
+    Ok(Duration::from_millis(u64::try_from(millis).map_err(|_| "Time must be after 1970-01-01")?))
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
     use std::ops::Sub;
-    use std::panic;
 
     #[test]
     fn test_build_start_and_end_using_duration() {
         let args = Args {
             file: String::new(),
-            duration: 100,
+            duration: String::from("100"),
+            since: None,
+            around: None,
+            padding: String::from("30m"),
             start: None,
             end: None,
             print_prompt_data: false,
             dry_run: false,
+            concurrency: 1,
+            baseline: None,
+            baseline_start: None,
+            baseline_end: None,
         };
-        let (start, end) = build_start_and_end(&args, Tz::UTC)
+        let (start, end) = build_start_and_end(&args, Tz::UTC, NOW)
             .expect("Should not return an error");
         let diff = end.sub(start).as_secs();
 
-        assert_eq!(args.duration, diff);
+        assert_eq!(100, diff);
     }
 
     #[test]
     fn test_build_start_and_end_using_range() {
         let args = Args {
             file: String::new(),
-            duration: 0,
+            duration: String::from("3600"),
+            since: None,
+            around: None,
+            padding: String::from("30m"),
             start: Some(String::from("2024-01-01 12:00:00")),
             end: Some(String::from("2024-01-02 12:00:00")),
             print_prompt_data: false,
             dry_run: false,
+            concurrency: 1,
+            baseline: None,
+            baseline_start: None,
+            baseline_end: None,
         };
-        let (start, end) = build_start_and_end(&args, Tz::UTC)
+        let (start, end) = build_start_and_end(&args, Tz::UTC, NOW)
             .expect("Should not return an error");
         let diff = end.sub(start).as_secs();
 
@@ -109,17 +331,182 @@ mod test {
     fn test_build_start_and_end_using_range_should_have_both_duration() {
         let args = Args {
             file: String::new(),
-            duration: 0,
+            duration: String::from("3600"),
+            since: None,
+            around: None,
+            padding: String::from("30m"),
             start: Some(String::from("2024-01-01 12:00:00")),
             end: None,
             print_prompt_data: false,
             dry_run: false,
+            concurrency: 1,
+            baseline: None,
+            baseline_start: None,
+            baseline_end: None,
         };
 
-        let result = panic::catch_unwind(|| {
-            build_start_and_end(&args, Tz::UTC)
-        });
+        let err = build_start_and_end(&args, Tz::UTC, NOW).expect_err("Should return an error");
+
+        assert_eq!(err.to_string(), "Both start and end arguments must be provided");
+    }
+
+    #[test]
+    fn test_build_baseline_using_offset() {
+        let args = Args {
+            file: String::new(),
+            duration: String::from("3600"),
+            since: None,
+            around: None,
+            padding: String::from("30m"),
+            start: None,
+            end: None,
+            print_prompt_data: false,
+            dry_run: false,
+            concurrency: 1,
+            baseline: Some(String::from("7d")),
+            baseline_start: None,
+            baseline_end: None,
+        };
+        let week = Duration::from_secs(7 * 86400);
+
+        let baseline = build_baseline(&args, Tz::UTC, week * 2, week * 3, NOW)
+            .expect("Should not return an error");
 
-        assert!(result.is_err());
+        assert_eq!(baseline, Some((week, week * 2)));
+    }
+
+    #[test]
+    fn test_build_baseline_using_range_should_have_both() {
+        let args = Args {
+            file: String::new(),
+            duration: String::from("3600"),
+            since: None,
+            around: None,
+            padding: String::from("30m"),
+            start: None,
+            end: None,
+            print_prompt_data: false,
+            dry_run: false,
+            concurrency: 1,
+            baseline: None,
+            baseline_start: Some(String::from("2024-01-01 12:00:00")),
+            baseline_end: None,
+        };
+
+        assert!(build_baseline(&args, Tz::UTC, Duration::ZERO, Duration::ZERO, NOW).is_err());
+    }
+
+    fn build_args(since: Option<&str>, start: Option<&str>, end: Option<&str>) -> Args {
+        Args {
+            file: String::new(),
+            duration: String::from("90m"),
+            since: since.map(String::from),
+            around: None,
+            padding: String::from("30m"),
+            start: start.map(String::from),
+            end: end.map(String::from),
+            print_prompt_data: false,
+            dry_run: false,
+            concurrency: 1,
+            baseline: None,
+            baseline_start: None,
+            baseline_end: None,
+        }
+    }
+
+    /// 2024-01-02 12:00:00 UTC
+    const NOW: Duration = Duration::from_secs(1704196800);
+
+    #[test]
+    fn test_build_range_using_since() {
+        let (start, end) = build_start_and_end(&build_args(Some("2h"), None, Some("now-15m")), Tz::UTC, NOW)
+            .expect("Should not return an error");
+
+        assert_eq!(NOW - start, Duration::from_secs(2 * 3600));
+        assert_eq!(NOW - end, Duration::from_secs(15 * 60));
+    }
+
+    #[test]
+    fn test_build_range_using_duration_with_unit() {
+        let (start, end) = build_start_and_end(&build_args(None, None, None), Tz::UTC, NOW)
+            .expect("Should not return an error");
+
+        assert_eq!(end - start, Duration::from_secs(90 * 60));
+    }
+
+    #[test]
+    fn test_build_range_around() {
+        let args = Args {
+            around: Some(String::from("now-2h")),
+            padding: String::from("15m"),
+            ..build_args(None, None, None)
+        };
+
+        let (start, end) = build_start_and_end(&args, Tz::UTC, NOW).expect("Should not return an error");
+
+        assert_eq!(NOW - start, Duration::from_secs(2 * 3600 + 15 * 60));
+        assert_eq!(NOW - end, Duration::from_secs(2 * 3600 - 15 * 60));
+        assert_eq!(build_event_time(&args, Tz::UTC, NOW).unwrap(), Some(NOW - Duration::from_secs(2 * 3600)));
+    }
+
+    #[test]
+    fn test_build_range_around_recent_incident() {
+        let args = Args {
+            around: Some(String::from("now-5m")),
+            ..build_args(None, None, None)
+        };
+
+        let (start, end) = build_start_and_end(&args, Tz::UTC, NOW).expect("Should not return an error");
+
+        assert_eq!(NOW - start, Duration::from_secs(35 * 60));
+        assert_eq!(end, NOW);
+    }
+
+    #[test]
+    fn test_build_range_validation() {
+        let cases = [
+            (build_args(None, Some("now-1h"), Some("now-2h")), "Start time must be before end time"),
+            (build_args(None, Some("now-1h"), Some("2024-01-03 00:00:00")), "End time must not be in the future"),
+            (build_args(Some("1h"), Some("now-1h"), None), "Only one of since and start arguments can be provided"),
+            (build_args(None, None, Some("now")), "Both start and end arguments must be provided"),
+            (build_args(None, Some("someday"), Some("now")), "Invalid time: someday"),
+            (Args { around: Some(String::from("now-1h")), ..build_args(Some("2h"), None, None) }, "around can't be used with since, start or end arguments"),
+        ];
+
+        for (args, expected) in cases {
+            let err = build_start_and_end(&args, Tz::UTC, NOW).expect_err("Should return an error");
+            assert_eq!(err.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_parse_time() {
+        let manila = Tz::Asia__Manila;
+        let expected = |rfc3339: &str| from_millis(chrono::DateTime::parse_from_rfc3339(rfc3339).unwrap().timestamp_millis()).unwrap();
+
+        assert_eq!(parse_time("now", manila, NOW).unwrap(), NOW);
+        assert_eq!(parse_time("now-15m", manila, NOW).unwrap(), NOW - Duration::from_secs(900));
+        assert_eq!(parse_time("1704196800000", manila, NOW).unwrap(), NOW);
+        assert_eq!(parse_time("3600", manila, NOW).unwrap_err().to_string(), "Invalid time: 3600, a number needs a unit (e.g. 1h ago) or use a timestamp");
+        assert_eq!(parse_time("1704196800", manila, NOW).unwrap_err().to_string(), "Invalid time: 1704196800, a number needs a unit (e.g. 1h ago) or use a timestamp");
+        assert_eq!(parse_time("2024-01-02T20:00:00+08:00", manila, NOW).unwrap(), NOW);
+        assert_eq!(parse_time("2024-01-02 20:00:00", manila, NOW).unwrap(), NOW);
+        assert_eq!(parse_time("2024-01-02 20:00", manila, NOW).unwrap(), NOW);
+        assert_eq!(parse_time("yesterday 14:00", manila, NOW).unwrap(), expected("2024-01-01T14:00:00+08:00"));
+        assert_eq!(parse_time("today", manila, NOW).unwrap(), expected("2024-01-02T00:00:00+08:00"));
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("3600").unwrap(), Duration::from_secs(3600));
+        assert_eq!(parse_duration("1h30m").unwrap(), Duration::from_secs(5400));
+        assert_eq!(parse_duration("12h").unwrap(), Duration::from_secs(12 * 3600));
+        assert_eq!(parse_duration("1d").unwrap(), Duration::from_secs(86400));
+        assert!(parse_duration("1y").is_err());
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("").is_err());
+        assert_eq!(parse_duration("30000000000000000w").unwrap_err().to_string(), "Invalid duration: 30000000000000000w");
+        assert!(parse_duration("18446744073709551615s1s").is_err());
     }
 }
@@ -0,0 +1,275 @@
+use aws_config::meta::region::RegionProviderChain;
+use aws_config::sts::AssumeRoleProvider;
+use aws_config::{BehaviorVersion, ConfigLoader, Region, SdkConfig};
+use aws_smithy_runtime_api::client::result::SdkError;
+use aws_smithy_types::DateTime;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::error::Error;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+
+/// Per data source overrides of where AWS requests are sent to
+#[derive(Clone, Debug, Default, Hash, PartialEq, Eq)]
+pub struct AwsScope {
+    pub profile: Option<String>,
+    pub region: Option<String>,
+    pub role_arn: Option<String>,
+}
+
+type ClientKey = (TypeId, AwsScope);
+
+const SESSION_NAME: &str = "auto-diagnostic";
+
+/// Loads the `SdkConfig` once per scope, and caches one client per service/scope
+#[derive(Default)]
+pub struct ClientFactory {
+    pub profile: String,
+    sdk_configs: Mutex<HashMap<AwsScope, Arc<OnceCell<SdkConfig>>>>,
+    clients: Mutex<HashMap<ClientKey, Box<dyn Any + Send + Sync>>>,
+}
+
+impl ClientFactory {
+    pub fn new(profile: String) -> Self {
+        ClientFactory {
+            profile,
+            ..ClientFactory::default()
+        }
+    }
+
+    pub async fn autoscaling(&self, scope: &AwsScope) -> aws_sdk_autoscaling::Client {
+        self.client(scope, aws_sdk_autoscaling::Client::new).await
+    }
+
+    pub async fn ec2(&self, scope: &AwsScope) -> aws_sdk_ec2::Client {
+        self.client(scope, aws_sdk_ec2::Client::new).await
+    }
+
+    pub async fn ecs(&self, scope: &AwsScope) -> aws_sdk_ecs::Client {
+        self.client(scope, aws_sdk_ecs::Client::new).await
+    }
+
+    pub async fn elb(&self, scope: &AwsScope) -> aws_sdk_elasticloadbalancingv2::Client {
+        self.client(scope, aws_sdk_elasticloadbalancingv2::Client::new).await
+    }
+
+    pub async fn lambda(&self, scope: &AwsScope) -> aws_sdk_lambda::Client {
+        self.client(scope, aws_sdk_lambda::Client::new).await
+    }
+
+    pub async fn rds(&self, scope: &AwsScope) -> aws_sdk_rds::Client {
+        self.client(scope, aws_sdk_rds::Client::new).await
+    }
+
+    pub async fn cloudwatch(&self, scope: &AwsScope) -> aws_sdk_cloudwatch::Client {
+        self.client(scope, aws_sdk_cloudwatch::Client::new).await
+    }
+
+    pub async fn cloudwatch_logs(&self, scope: &AwsScope) -> aws_sdk_cloudwatchlogs::Client {
+        self.client(scope, aws_sdk_cloudwatchlogs::Client::new).await
+    }
+
+    async fn client<C: Clone + Send + Sync + 'static>(&self, scope: &AwsScope, build: fn(&SdkConfig) -> C) -> C {
+        let scope = self.resolve(scope);
+        let key = (TypeId::of::<C>(), scope.clone());
+
+        if let Some(client) = self.clients.lock().unwrap().get(&key) {
+            return client.downcast_ref::<C>().unwrap().clone();
+        }
+
+        let client = build(&self.sdk_config(&scope).await);
+
+        // Another source may have built the same client in the meantime, keep whichever came first
+        self.clients.lock().unwrap()
+            .entry(key)
+            .or_insert_with(|| Box::new(client))
+            .downcast_ref::<C>()
+            .unwrap()
+            .clone()
+    }
+
+    async fn sdk_config(&self, scope: &AwsScope) -> SdkConfig {
+        let cell = self.sdk_configs.lock().unwrap()
+            .entry(scope.clone())
+            .or_default()
+            .clone();
+
+        cell.get_or_init(|| load_sdk_config(scope)).await.clone()
+    }
+
+    fn resolve(&self, scope: &AwsScope) -> AwsScope {
+        AwsScope {
+            profile: scope.profile.clone().or_else(|| Some(self.profile.clone())),
+            region: scope.region.clone(),
+            role_arn: scope.role_arn.clone(),
+        }
+    }
+}
+
+async fn load_sdk_config(scope: &AwsScope) -> SdkConfig {
+    let sdk_config = config_loader(scope).load().await;
+
+    match &scope.role_arn {
+        // The profile's credentials are used to assume the role
+        Some(role_arn) => {
+            let provider = AssumeRoleProvider::builder(role_arn)
+                .configure(&sdk_config)
+                .session_name(SESSION_NAME)
+                .build()
+                .await;
+
+            config_loader(scope)
+                .credentials_provider(provider)
+                .load()
+                .await
+        },
+        None => sdk_config
+    }
+}
+
+fn config_loader(scope: &AwsScope) -> ConfigLoader {
+    let region_provider = RegionProviderChain::first_try(scope.region.clone().map(Region::new))
+        .or_default_provider();
+
+    let mut loader = aws_config::defaults(BehaviorVersion::latest())
+        .region(region_provider);
+
+    if let Some(profile) = &scope.profile {
+        loader = loader.profile_name(profile);
+    }
+
+    loader
+}
+
+/// Milliseconds since the epoch of a timestamp returned by the SDK
+pub fn millis(date_time: Option<&DateTime>) -> Option<i64> {
+    date_time.and_then(|date_time| date_time.to_millis().ok())
+}
+
+/// Follows the next token (or marker) from page to page until the last one
+pub async fn fetch_all_pages<O, F>(fetch_page: impl FnMut(Option<String>) -> F, next_token: impl Fn(&O) -> Option<&str>) -> Result<Vec<O>, Box<dyn Error>>
+where
+    F: Future<Output = Result<O, Box<dyn Error>>>,
+{
+    Ok(fetch_pages_until(fetch_page, next_token, |_| false).await?.0)
+}
+
+/// Follows the next token (or marker) from page to page, until the last one or until `done` holds for the pages fetched so far.
+/// Returns whether pages were left
+pub async fn fetch_pages_until<O, F>(mut fetch_page: impl FnMut(Option<String>) -> F, next_token: impl Fn(&O) -> Option<&str>, done: impl Fn(&[O]) -> bool) -> Result<(Vec<O>, bool), Box<dyn Error>>
+where
+    F: Future<Output = Result<O, Box<dyn Error>>>,
+{
+    let mut pages = Vec::new();
+    let mut token = None;
+
+    loop {
+        let page = fetch_page(token).await?;
+        token = next_token(&page).map(str::to_string);
+        pages.push(page);
+
+        if token.is_none() || done(&pages) {
+            return Ok((pages, token.is_some()));
+        }
+    }
+}
+
+/// Looking up an unknown resource by name is an error of the API, whereas filters return nothing.
+/// The error is turned into the `empty` output, for the data source to report the resource as not found like the others
+pub fn empty_when_not_found<O, E, R>(result: Result<O, SdkError<E, R>>, is_not_found: impl Fn(&E) -> bool, empty: impl FnOnce() -> O) -> Result<O, Box<dyn Error>>
+where
+    SdkError<E, R>: Error + 'static,
+{
+    match result {
+        Err(err) if err.as_service_error().is_some_and(is_not_found) => Ok(empty()),
+        result => Ok(result?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static BUILD_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Clone)]
+    struct MockClient {}
+
+    fn build_mock_client(_: &SdkConfig) -> MockClient {
+        BUILD_COUNT.fetch_add(1, Ordering::SeqCst);
+        MockClient {}
+    }
+
+    fn preload(factory: &ClientFactory, scope: AwsScope) {
+        let sdk_config = SdkConfig::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .build();
+
+        factory.sdk_configs.lock().unwrap()
+            .insert(scope, Arc::new(OnceCell::new_with(Some(sdk_config))));
+    }
+
+    #[tokio::test]
+    async fn test_client_is_cached_per_scope() {
+        let factory = ClientFactory::new("default-profile".to_string());
+        let other_scope = AwsScope {
+            profile: Some("other-profile".to_string()),
+            region: Some("us-east-1".to_string()),
+            role_arn: None,
+        };
+        preload(&factory, factory.resolve(&AwsScope::default()));
+        preload(&factory, other_scope.clone());
+
+        factory.client(&AwsScope::default(), build_mock_client).await;
+        factory.client(&AwsScope::default(), build_mock_client).await;
+        factory.client(&other_scope, build_mock_client).await;
+        factory.client(&other_scope, build_mock_client).await;
+
+        assert_eq!(BUILD_COUNT.load(Ordering::SeqCst), 2);
+        assert_eq!(factory.clients.lock().unwrap().len(), 2);
+        assert_eq!(factory.sdk_configs.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_pages() {
+        let fetch_page = |token: Option<String>| async move {
+            let page: usize = token.map(|token| token.parse().unwrap()).unwrap_or(0);
+            let next_token = Some(page + 1).filter(|next| *next < 5).map(|next| next.to_string());
+            Ok::<_, Box<dyn Error>>((page, next_token))
+        };
+
+        let all = fetch_all_pages(fetch_page, |page: &(usize, Option<String>)| page.1.as_deref()).await.expect("Should fetch every page");
+        let (until, left) = fetch_pages_until(fetch_page, |page: &(usize, Option<String>)| page.1.as_deref(), |pages| pages.len() == 2).await.expect("Should fetch two pages");
+
+        assert_eq!(all.iter().map(|page| page.0).collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+        assert_eq!(until.iter().map(|page| page.0).collect::<Vec<_>>(), vec![0, 1]);
+        assert!(left);
+    }
+
+    #[test]
+    fn test_resolve_falls_back_to_general_profile() {
+        let factory = ClientFactory::new("default-profile".to_string());
+
+        let scope = factory.resolve(&AwsScope::default());
+
+        assert_eq!(scope.profile, Some("default-profile".to_string()));
+        assert_eq!(scope.region, None);
+        assert_eq!(scope.role_arn, None);
+    }
+
+    #[test]
+    fn test_resolve_keeps_overrides() {
+        let factory = ClientFactory::new("default-profile".to_string());
+        let overrides = AwsScope {
+            profile: Some("db-account".to_string()),
+            region: Some("us-east-1".to_string()),
+            role_arn: Some("arn:aws:iam::123456789012:role/diagnostic".to_string()),
+        };
+
+        let scope = factory.resolve(&overrides);
+
+        assert_eq!(scope, overrides);
+    }
+}
@@ -1,21 +1,33 @@
 use std::error::Error;
+use std::time::{SystemTime, UNIX_EPOCH};
 use chrono_tz::Tz;
-use crate::datasource::ds::DataSource;
-use crate::datasource::ds::DataSource::{AppDescription, CloudwatchLogInsight, CloudwatchMetric, Ec2, Rds};
+use crate::datasource::ds::{DataSource, ErrorPolicy, Registry};
 use crate::lib::args;
+use crate::lib::aws::ClientFactory;
 use crate::lib::args::Args;
 use crate::lib::config::Config;
+use tokio_util::sync::CancellationToken;
 
 #[derive(Default)]
 pub struct AppContext {
-    pub profile: String,
+    pub aws: ClientFactory,
     pub range: DateTimeRange,
-    pub data_sources: Vec<DataSource>,
+    /// The window `range` is compared with, if any
+    pub baseline: Option<DateTimeRange>,
+    /// Incident time the window is centered on, in epoch millis
+    pub event_time: Option<i64>,
+    /// Read once at start up in epoch millis, so every check of the run agrees on it
+    pub now: i64,
+    pub data_sources: Vec<Box<dyn DataSource>>,
     pub open_ai_api_key: Option<String>,
     pub open_ai_model: String,
     pub open_ai_max_token: u32,
     pub print_prompt_data: bool,
-    pub dry_run: bool
+    pub dry_run: bool,
+    pub concurrency: usize,
+    pub on_error: ErrorPolicy,
+    /// Cancelled on Ctrl-C, or when a failing data source aborts the run
+    pub cancel: CancellationToken,
 }
 
 #[derive(Default)]
@@ -25,71 +37,63 @@ pub struct DateTimeRange {
     pub time_zone: Tz,
 }
 
-pub fn build_context(args: Args, config: Config) -> Result<AppContext, Box<dyn Error>> {
-    let time_zone = match config.general.time_zone {
-        Some(tz) => tz.parse().expect("Unknown time zone"),
-        None => Tz::UTC
-    };
-
-    let (start_time, end_time) = args::build_start_and_end(&args, time_zone)?;
-
-    let mut data_sources: Vec<DataSource> = Vec::new();
-
-    if let Some(configs) = config.app_description {
-        for app_desc_config in configs {
-            data_sources.push(AppDescription {
-                config: app_desc_config
-            });
+impl DateTimeRange {
+    /// Formats epoch millis in the configured time zone, the raw millis when out of range
+    pub fn format_timestamp(&self, timestamp: i64) -> String {
+        match chrono::DateTime::from_timestamp_millis(timestamp) {
+            Some(utc_time) => format!("{}", utc_time.with_timezone(&self.time_zone)),
+            None => timestamp.to_string()
         }
     }
 
-    if let Some(configs) = config.ec2 {
-        for ec2_config in configs {
-            data_sources.push(Ec2 {
-                config: ec2_config
-            });
-        }
+    pub fn describe(&self) -> String {
+        format!("{} to {}", self.format_timestamp(self.start_time), self.format_timestamp(self.end_time))
     }
+}
 
-    if let Some(configs) = config.rds {
-        for rds_config in configs {
-            data_sources.push(Rds {
-                config: rds_config
-            });
-        }
-    }
+pub fn build_context(args: Args, config: Config) -> Result<AppContext, Box<dyn Error>> {
+    let time_zone = match config.general.time_zone {
+        Some(tz) => tz.parse().map_err(|_| format!("Unknown time zone: {tz}"))?,
+        None => Tz::UTC
+    };
 
-    if let Some(configs) = config.cloudwatch_metric {
-        for cloudwatch_config in configs {
-            data_sources.push(CloudwatchMetric {
-                config: cloudwatch_config
-            });
-        }
-    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+    let (start_time, end_time) = args::build_start_and_end(&args, time_zone, now)?;
+    let baseline = args::build_baseline(&args, time_zone, start_time, end_time, now)?;
+    let event_time = args::build_event_time(&args, time_zone, now)?;
 
-    if let Some(configs) = config.cloudwatch_log_insight {
-        for cloudwatch_config in configs {
-            data_sources.push(CloudwatchLogInsight {
-                config: cloudwatch_config
-            });
-        }
+    let registry = Registry::default();
+    let mut data_sources: Vec<Box<dyn DataSource>> = Vec::new();
+
+    for (table_name, value) in config.data_sources {
+        data_sources.extend(registry.build(&table_name, value)?);
     }
 
-    data_sources.sort();
+    data_sources.sort_by_key(|data_source| data_source.order_no());
 
     let context = AppContext {
-        profile: String::from(&config.general.profile),
+        aws: ClientFactory::new(config.general.profile),
         range: DateTimeRange {
             start_time: start_time.as_millis() as i64,
             end_time: end_time.as_millis() as i64,
             time_zone,
         },
+        baseline: baseline.map(|(start_time, end_time)| DateTimeRange {
+            start_time: start_time.as_millis() as i64,
+            end_time: end_time.as_millis() as i64,
+            time_zone,
+        }),
+        event_time: event_time.map(|event_time| event_time.as_millis() as i64),
+        now: now.as_millis() as i64,
         data_sources,
         open_ai_api_key: config.open_ai.api_key,
         open_ai_model: config.open_ai.model,
         open_ai_max_token: config.open_ai.max_token,
         print_prompt_data: args.print_prompt_data,
-        dry_run: args.dry_run
+        dry_run: args.dry_run,
+        concurrency: args.concurrency,
+        on_error: config.general.on_error.unwrap_or_default(),
+        cancel: CancellationToken::new(),
     };
 
     Ok(context)
@@ -98,86 +102,106 @@ pub fn build_context(args: Args, config: Config) -> Result<AppContext, Box<dyn E
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::lib::config::{AppDescConfig, CloudwatchLogInsightConfig, CloudwatchMetricConfig, Ec2Config, GeneralConfig, OpenAiConfig, RdsConfig};
-    use std::matches;
-    use crate::datasource::ds::DataSource::CloudwatchLogInsight;
+
+    const CONFIG: &str = r#"
+        [general]
+        profile = 'aws-profile'
+        time_zone = 'Asia/Manila'
+        on_error = 'fail'
+
+        [open_ai]
+        api_key = 'openai-api-key'
+        model = 'gpt-4o'
+        max_token = 4096
+
+        [[app_description]]
+        order_no = 5
+        description = 'App description'
+
+        [[ec2]]
+        order_no = 4
+        instance_name = 'ec2-instance'
+
+        [[rds]]
+        order_no = 3
+        db_identifier = 'rds-instance'
+
+        [[cloudwatch_metric]]
+        order_no = 2
+        dimension_name = 'dimension-name'
+        dimension_value = 'dimension-value'
+        metric_identifier = 'metric-identifier'
+        metric_namespace = 'metric-namespace'
+        metric_name = 'metric-name'
+        metric_stat = 'metric-stat'
+        metric_unit = 'metric-unit'
+
+        [[cloudwatch_log_insight]]
+        order_no = 1
+        description = 'description'
+        log_group_name = 'log-group-name'
+        query = 'query'
+        result_columns = ['col1', 'col2']
+    "#;
+
+    fn build_args() -> Args {
+        Args {
+            file: String::from("file.toml"),
+            duration: String::from("60"),
+            since: None,
+            around: Some(String::from("now-1h")),
+            padding: String::from("30m"),
+            start: None,
+            end: None,
+            print_prompt_data: true,
+            dry_run: false,
+            concurrency: 4,
+            baseline: Some(String::from("1d")),
+            baseline_start: None,
+            baseline_end: None,
+        }
+    }
 
     #[test]
     fn build_context_without_errors() {
-        let context = build_context(
-            Args {
-                file: String::from("file.toml"),
-                duration: 60,
-                start: None,
-                end: None,
-                print_prompt_data: true,
-                dry_run: false,
-            },
-            Config {
-                general: GeneralConfig {
-                    profile: "aws-profile".to_string(),
-                    time_zone: Some("Asia/Manila".to_string()),
-                },
-                open_ai: OpenAiConfig {
-                    api_key: Some("openai-api-key".to_string()),
-                    model: "gpt-4o".to_string(),
-                    max_token: 4096,
-                },
-                app_description: Some(vec![
-                    AppDescConfig {
-                        order_no: 5,
-                        description: "App description".to_string()
-                    },
-                ]),
-                ec2: Some(vec![
-                    Ec2Config {
-                        order_no: 4,
-                        instance_name: "ec2-instance".to_string()
-                    }
-                ]),
-                rds: Some(vec![
-                    RdsConfig {
-                        order_no: 3,
-                        db_identifier: "rds-instance".to_string()
-                    }
-                ]),
-                cloudwatch_metric: Some(vec![
-                    CloudwatchMetricConfig {
-                        order_no: 2,
-                        dimension_name: "dimension-name".to_string(),
-                        dimension_value: "dimension-value".to_string(),
-                        metric_identifier: "metric-identifier".to_string(),
-                        metric_namespace: "metric-namespace".to_string(),
-                        metric_name: "metric-name".to_string(),
-                        metric_stat: "metric-stat".to_string(),
-                        metric_unit: Some("metric-unit".to_string()),
-                    }
-                ]),
-                cloudwatch_log_insight: Some(vec![
-                    CloudwatchLogInsightConfig {
-                        order_no: 1,
-                        description: "description".to_string(),
-                        log_group_name: "log-group-name".to_string(),
-                        query: "query".to_string(),
-                        result_columns: vec![
-                            "col1".to_string(),
-                            "col2".to_string()
-                        ],
-                    }
-                ]),
-            }
-        ).unwrap();
-
-        assert_eq!(context.profile, "aws-profile");
+        let config: Config = toml::from_str(CONFIG).unwrap();
+        let context = build_context(build_args(), config).unwrap();
+
+        assert_eq!(context.aws.profile, "aws-profile");
         assert_eq!(context.range.time_zone, Tz::Asia__Manila);
         assert_eq!(context.open_ai_api_key, Some("openai-api-key".to_string()));
         assert_eq!(context.open_ai_model, "gpt-4o".to_string());
         assert_eq!(context.open_ai_max_token, 4096);
+        assert_eq!(context.concurrency, 4);
+        assert_eq!(context.range.end_time - context.event_time.unwrap(), 30 * 60 * 1000);
+        assert_eq!(context.now - context.range.end_time, 30 * 60 * 1000);
+        assert_eq!(context.range.start_time - context.baseline.unwrap().start_time, 86400 * 1000);
+        assert_eq!(context.on_error, ErrorPolicy::Fail);
         assert_eq!(context.data_sources.len(), 5);
-        assert!(matches!(context.data_sources[0], CloudwatchLogInsight {..}));
-        assert!(matches!(context.data_sources[1], CloudwatchMetric{..}));
-        assert!(matches!(context.data_sources[2], Rds{..}));
-        assert!(matches!(context.data_sources[3], Ec2{..}));
-        assert!(matches!(context.data_sources[4], AppDescription{..}));
+        assert_eq!(context.data_sources[0].name(), "Cloudwatch log insight");
+        assert_eq!(context.data_sources[1].name(), "Cloudwatch metric");
+        assert_eq!(context.data_sources[2].name(), "RDS instance");
+        assert_eq!(context.data_sources[3].name(), "EC2 instance");
+        assert_eq!(context.data_sources[4].name(), "App description");
+    }
+
+    #[test]
+    fn build_context_unknown_time_zone() {
+        let toml = CONFIG.replace("Asia/Manila", "Mars/Olympus_Mons");
+        let config: Config = toml::from_str(&toml).unwrap();
+
+        let err = build_context(build_args(), config).err().expect("Should return an error");
+
+        assert_eq!(err.to_string(), "Unknown time zone: Mars/Olympus_Mons");
+    }
+
+    #[test]
+    fn build_context_unknown_data_source() {
+        let toml = format!("{CONFIG}\n[[unknown]]\norder_no = 6");
+        let config: Config = toml::from_str(&toml).unwrap();
+
+        let result = build_context(build_args(), config);
+
+        assert!(result.is_err());
     }
 }
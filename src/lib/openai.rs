@@ -31,7 +31,7 @@ pub async fn send_request(client: impl OpenAiClient, context: &AppContext, input
     if let Err(NotPresent) = std::env::var(OPENAI_API_KEY) {
         let api_key = context.open_ai_api_key
             .clone()
-            .expect(format!("{OPENAI_API_KEY} variable is not set").as_str());
+            .unwrap_or_else(|| panic!("{OPENAI_API_KEY} variable is not set"));
         std::env::set_var(OPENAI_API_KEY, api_key);
     }
 
@@ -1,7 +1,10 @@
+use crate::datasource::ds::{DataSource, DataSourceError, ErrorPolicy};
 use crate::lib::context::AppContext;
-use indicatif::{ProgressBar, ProgressStyle};
+use futures::{stream, StreamExt};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use std::error::Error;
-use std::time::Duration;
+use std::fmt::Display;
+use std::time::{Duration, Instant};
 
 #[derive(Debug)]
 pub struct PromptData {
@@ -25,30 +28,135 @@ pub fn build_instruction() -> String {
 pub async fn build_prompt_data(context: &AppContext) -> Result<String, Box<dyn Error>> {
     let mut prompt = String::new();
 
-    let progress_bar = initialize_progress_bar(context);
-    for data_source in &context.data_sources {
-        progress_bar.set_message(format!("{data_source}"));
+    if let Some(event_time) = context.event_time {
+        prompt.push_str(&format!("Incident time: [{}]\n", context.range.format_timestamp(event_time)));
+        prompt.push_str("Focus on what changed before and after the incident time.\n\n");
+    }
+
+    let multi_progress = MultiProgress::new();
+    let progress_bar = multi_progress.add(initialize_progress_bar(context));
+    progress_bar.set_message("Fetching data sources");
+
+    // Create every source bar upfront, so that queued sources are displayed as waiting
+    let fetches: Vec<_> = context.data_sources.iter()
+        .enumerate()
+        .map(|(index, data_source)| {
+            let source_bar = multi_progress.add(initialize_source_bar(data_source.as_ref()));
+            let progress_bar = &progress_bar;
+
+            async move {
+                // A queued source isn't started once the run is aborted
+                if context.cancel.is_cancelled() {
+                    source_bar.finish_with_message("cancelled");
+                    return (index, Err(DataSourceError::Interrupted));
+                }
+
+                let started = Instant::now();
+                source_bar.set_message("fetching");
+                source_bar.enable_steady_tick(Duration::from_millis(100));
+
+                let result = data_source.fetch_data(context).await;
+                match &result {
+                    Ok(_) => source_bar.finish_with_message(format!("done in {:.1?}", started.elapsed())),
+                    Err(err) => source_bar.finish_with_message(format!("failed: {err}")),
+                }
+                progress_bar.inc(1);
+
+                (index, result)
+            }
+        })
+        .collect();
+
+    let mut fetched = stream::iter(fetches).buffer_unordered(context.concurrency.max(1));
+    let mut results = Vec::new();
+    let mut aborted: Option<Box<dyn Error>> = None;
+
+    // Once aborted, the remaining sources are still awaited so that they can clean up, e.g. stop their queries
+    while let Some((index, result)) = fetched.next().await {
+        if aborted.is_some() {
+            continue;
+        }
+
+        let data_source = &context.data_sources[index];
+
+        let err = match result {
+            Ok(prompt_data_vec) => {
+                results.push((index, prompt_data_vec));
+                continue;
+            },
+            Err(DataSourceError::Interrupted) => format!("{} was interrupted", data_source.name()),
+            Err(err) => match data_source.options().on_error.unwrap_or(context.on_error) {
+                ErrorPolicy::Fail => format!("{} failed: {err}", data_source.name()),
+                ErrorPolicy::Skip => {
+                    results.push((index, vec![build_unavailable(data_source.as_ref(), err)]));
+                    continue;
+                },
+            }
+        };
 
-        for prompt_data in data_source.fetch_data(context).await? {
+        context.cancel.cancel();
+        aborted = Some(err.into());
+    }
+
+    if let Some(err) = aborted {
+        return Err(err);
+    }
+
+    if context.cancel.is_cancelled() {
+        return Err("Interrupted".into());
+    }
+
+    // Sources complete in any order, restore the order_no order before building the prompt
+    results.sort_by_key(|(index, _)| *index);
+
+    progress_bar.finish_with_message("Fetched data sources");
+
+    for (_, prompt_data_vec) in results {
+        for prompt_data in prompt_data_vec {
             prompt.push_str(&prompt_data.description.join("\n"));
             prompt.push('\n');
             if let Some(data) = &prompt_data.data {
+                let fence = build_fence(data);
                 prompt.push_str("Data:\n");
-                prompt.push_str("```\n");
+                prompt.push_str(&format!("{fence}\n"));
                 prompt.push_str(data);
-                prompt.push_str("```\n");
+                prompt.push_str(&format!("{fence}\n"));
             }
             prompt.push('\n');
         }
-
-        progress_bar.inc(1);
     }
 
-    progress_bar.finish_with_message("Fetched data sources");
-
     Ok(prompt)
 }
 
+/// Longer than any run of backticks in the data (e.g. a log line quoting markdown), so that it can't close the block early
+fn build_fence(data: &str) -> String {
+    let longest = data.split(|c| c != '`').map(str::len).max().unwrap_or_default();
+    "`".repeat(longest.max(2) + 1)
+}
+
+fn build_unavailable(data_source: &dyn DataSource, err: DataSourceError) -> PromptData {
+    unavailable(data_source.name(), err)
+}
+
+fn unavailable(name: &str, err: impl Display) -> PromptData {
+    PromptData {
+        description: vec![
+            format!("Information: [{name}]"),
+            format!("Status: [unavailable: {err}]"),
+        ],
+        data: None
+    }
+}
+
+/// Data attached to a source (e.g. its metrics) is reported as unavailable, rather than failing what the source already fetched
+pub fn or_unavailable(name: &str, result: Result<Vec<PromptData>, Box<dyn Error>>) -> Vec<PromptData> {
+    match result {
+        Ok(prompt_data_vec) => prompt_data_vec,
+        Err(err) => vec![unavailable(name, err)],
+    }
+}
+
 fn initialize_progress_bar(context: &AppContext) -> ProgressBar {
     let progress_bar = ProgressBar::new(context.data_sources.len() as u64);
     progress_bar.set_style(ProgressStyle::default_bar()
@@ -60,22 +168,79 @@ fn initialize_progress_bar(context: &AppContext) -> ProgressBar {
     progress_bar
 }
 
+fn initialize_source_bar(data_source: &dyn DataSource) -> ProgressBar {
+    let source_bar = ProgressBar::new_spinner();
+    source_bar.set_style(ProgressStyle::default_spinner()
+        .template("  {spinner:.green} {prefix} {msg:.yellow}")
+        .unwrap()
+    );
+    source_bar.set_prefix(format!("[{}] {}", data_source.order_no(), data_source.name()));
+    source_bar.set_message("waiting");
+    source_bar
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::datasource::ds::DataSource::AppDescription;
-    use crate::lib::config::AppDescConfig;
+    use crate::datasource::app_description::AppDescConfig;
+    use crate::datasource::ds::SourceOptions;
+    use futures::future::LocalBoxFuture;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use tokio::time::sleep;
+
+    #[derive(Default)]
+    struct DelayedDataSource {
+        order_no: u8,
+        delay: u64,
+        fail: bool,
+        options: SourceOptions,
+        /// Set when the source saw the run being cancelled while fetching
+        stopped: Rc<Cell<bool>>,
+    }
+
+    impl DataSource for DelayedDataSource {
+        fn name(&self) -> &str {
+            "Delayed"
+        }
+
+        fn order_no(&self) -> u8 {
+            self.order_no
+        }
+
+        fn options(&self) -> &SourceOptions {
+            &self.options
+        }
+
+        fn fetch_data<'a>(&'a self, context: &'a AppContext) -> LocalBoxFuture<'a, Result<Vec<PromptData>, DataSourceError>> {
+            Box::pin(async move {
+                tokio::select! {
+                    _ = sleep(Duration::from_millis(self.delay)) => {},
+                    _ = context.cancel.cancelled() => {
+                        self.stopped.set(true);
+                        return Err(DataSourceError::Interrupted);
+                    },
+                }
+                if self.fail {
+                    return Err(DataSourceError::NotFound { resource: "thing", name: "name".to_string() });
+                }
+                Ok(vec![PromptData {
+                    description: vec![format!("Delayed {}", self.order_no)],
+                    data: None
+                }])
+            })
+        }
+    }
 
     #[tokio::test]
     async fn should_build_prompt_data_correctly() {
         let context = AppContext {
             data_sources: vec![
-                AppDescription {
-                    config: AppDescConfig {
-                        order_no: 1,
-                        description: "This is an app description".to_string()
-                    },
-                }
+                Box::new(AppDescConfig {
+                    order_no: 1,
+                    description: "This is an app description".to_string(),
+                    ..AppDescConfig::default()
+                })
             ],
             ..AppContext::default()
         };
@@ -85,4 +250,140 @@ mod test {
 
         assert_eq!(prompt_data, expected_prompt_data);
     }
+
+    #[tokio::test]
+    async fn should_start_with_incident_time() {
+        let context = AppContext {
+            data_sources: vec![
+                Box::new(DelayedDataSource { order_no: 1, ..DelayedDataSource::default() }),
+            ],
+            event_time: Some(1740837900000),
+            ..AppContext::default()
+        };
+
+        let prompt_data = build_prompt_data(&context).await.expect("Should build prompt data");
+        let expected_prompt_data = [
+            "Incident time: [2025-03-01 14:05:00 UTC]\n",
+            "Focus on what changed before and after the incident time.\n\n",
+            "Delayed 1\n\n",
+        ].join("");
+
+        assert_eq!(prompt_data, expected_prompt_data);
+    }
+
+    #[tokio::test]
+    async fn should_keep_order_when_fetching_concurrently() {
+        let context = AppContext {
+            data_sources: vec![
+                Box::new(DelayedDataSource { order_no: 1, delay: 300, ..DelayedDataSource::default() }),
+                Box::new(DelayedDataSource { order_no: 2, delay: 100, ..DelayedDataSource::default() }),
+                Box::new(DelayedDataSource { order_no: 3, delay: 200, ..DelayedDataSource::default() }),
+            ],
+            concurrency: 3,
+            ..AppContext::default()
+        };
+
+        let started = Instant::now();
+        let prompt_data = build_prompt_data(&context).await.expect("Should build prompt data");
+
+        assert_eq!(prompt_data, "Delayed 1\n\nDelayed 2\n\nDelayed 3\n\n".to_string());
+        assert!(started.elapsed() < Duration::from_millis(600));
+    }
+
+    #[tokio::test]
+    async fn should_report_skipped_source_as_unavailable() {
+        let context = AppContext {
+            data_sources: vec![
+                Box::new(DelayedDataSource { order_no: 1, fail: true, ..DelayedDataSource::default() }),
+                Box::new(DelayedDataSource { order_no: 2, ..DelayedDataSource::default() }),
+            ],
+            on_error: ErrorPolicy::Skip,
+            ..AppContext::default()
+        };
+
+        let prompt_data = build_prompt_data(&context).await.expect("Should build prompt data");
+        let expected_prompt_data = [
+            "Information: [Delayed]\n",
+            "Status: [unavailable: Unable to find thing with name: name]\n\n",
+            "Delayed 2\n\n",
+        ].join("");
+
+        assert_eq!(prompt_data, expected_prompt_data);
+    }
+
+    #[tokio::test]
+    async fn should_abort_when_failing_source_policy_is_fail() {
+        let context = AppContext {
+            data_sources: vec![
+                Box::new(DelayedDataSource {
+                    order_no: 1,
+                    fail: true,
+                    options: SourceOptions { on_error: Some(ErrorPolicy::Fail), ..SourceOptions::default() },
+                    ..DelayedDataSource::default()
+                }),
+                Box::new(DelayedDataSource { order_no: 2, ..DelayedDataSource::default() }),
+            ],
+            on_error: ErrorPolicy::Skip,
+            ..AppContext::default()
+        };
+
+        let err = build_prompt_data(&context).await.expect_err("Should abort");
+
+        assert_eq!(err.to_string(), "Delayed failed: Unable to find thing with name: name");
+    }
+
+    #[tokio::test]
+    async fn should_cancel_running_sources_when_aborting() {
+        let stopped = Rc::new(Cell::new(false));
+        let context = AppContext {
+            data_sources: vec![
+                Box::new(DelayedDataSource {
+                    order_no: 1,
+                    fail: true,
+                    options: SourceOptions { on_error: Some(ErrorPolicy::Fail), ..SourceOptions::default() },
+                    ..DelayedDataSource::default()
+                }),
+                Box::new(DelayedDataSource { order_no: 2, delay: 5000, stopped: stopped.clone(), ..DelayedDataSource::default() }),
+                Box::new(DelayedDataSource { order_no: 3, delay: 5000, ..DelayedDataSource::default() }),
+            ],
+            concurrency: 2,
+            ..AppContext::default()
+        };
+
+        let started = Instant::now();
+        let err = build_prompt_data(&context).await.expect_err("Should abort");
+
+        assert_eq!(err.to_string(), "Delayed failed: Unable to find thing with name: name");
+        assert!(stopped.get());
+        assert!(started.elapsed() < Duration::from_millis(1000));
+    }
+
+    #[tokio::test]
+    async fn should_abort_when_cancelled() {
+        let context = AppContext {
+            data_sources: vec![
+                Box::new(DelayedDataSource { order_no: 1, ..DelayedDataSource::default() }),
+            ],
+            ..AppContext::default()
+        };
+        context.cancel.cancel();
+
+        let err = build_prompt_data(&context).await.expect_err("Should abort");
+
+        assert_eq!(err.to_string(), "Delayed was interrupted");
+    }
+
+    #[test]
+    fn should_fence_data_longer_than_its_backticks() {
+        assert_eq!(build_fence("a,b\n1,2\n"), "```");
+        assert_eq!(build_fence("ERROR rendering ```json``` block\n"), "````");
+    }
+
+    #[test]
+    fn should_report_attached_data_as_unavailable() {
+        let prompt_data_vec = or_unavailable("Load balancer metrics", Err("Throttling".into()));
+
+        assert_eq!(prompt_data_vec.len(), 1);
+        assert_eq!(prompt_data_vec[0].description, vec!["Information: [Load balancer metrics]", "Status: [unavailable: Throttling]"]);
+    }
 }
@@ -0,0 +1,175 @@
+/// Statistics of a series, computed locally to keep the prompt compact
+#[derive(Debug, PartialEq)]
+pub struct Summary {
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub stddev: f64,
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+    /// Slope of the least squares line, in value per hour
+    pub trend_per_hour: f64,
+}
+
+pub const SUMMARY_HEADERS: [&str; 9] = ["count", "min", "max", "mean", "stddev", "p50", "p95", "p99", "trend_per_hour"];
+
+const HOUR_MILLIS: f64 = 60.0 * 60.0 * 1000.0;
+
+impl Summary {
+    pub fn to_record(&self) -> Vec<String> {
+        let mut record = vec![self.count.to_string()];
+        record.extend([self.min, self.max, self.mean, self.stddev, self.p50, self.p95, self.p99, self.trend_per_hour]
+            .into_iter()
+            .map(|value| round(value).to_string()));
+        record
+    }
+}
+
+/// `points` are `(timestamp millis, value)`, returns `None` for an empty series
+pub fn summarize(points: &[(i64, f64)]) -> Option<Summary> {
+    if points.is_empty() {
+        return None;
+    }
+
+    let count = points.len();
+    let mut sorted: Vec<f64> = points.iter().map(|(_, value)| *value).collect();
+    sorted.sort_by(f64::total_cmp);
+
+    let mean = sorted.iter().sum::<f64>() / count as f64;
+    let variance = sorted.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / count as f64;
+
+    Some(Summary {
+        count,
+        min: sorted[0],
+        max: sorted[count - 1],
+        mean,
+        stddev: variance.sqrt(),
+        p50: percentile(&sorted, 50.0),
+        p95: percentile(&sorted, 95.0),
+        p99: percentile(&sorted, 99.0),
+        trend_per_hour: trend(points),
+    })
+}
+
+/// Linear interpolation between the closest ranks, `sorted` must not be empty
+pub fn percentile(sorted: &[f64], percentile: f64) -> f64 {
+    let rank = percentile / 100.0 * (sorted.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
+
+fn trend(points: &[(i64, f64)]) -> f64 {
+    let origin = points[0].0;
+    let hours: Vec<f64> = points.iter().map(|(timestamp, _)| (timestamp - origin) as f64 / HOUR_MILLIS).collect();
+
+    let count = points.len() as f64;
+    let mean_x = hours.iter().sum::<f64>() / count;
+    let mean_y = points.iter().map(|(_, value)| value).sum::<f64>() / count;
+
+    let (covariance, variance) = hours.iter()
+        .zip(points)
+        .fold((0.0, 0.0), |(covariance, variance), (x, (_, y))| {
+            (covariance + (x - mean_x) * (y - mean_y), variance + (x - mean_x).powi(2))
+        });
+
+    if variance == 0.0 { 0.0 } else { covariance / variance }
+}
+
+/// The `n` highest datapoints, highest first
+pub fn top_spikes(points: &[(i64, f64)], n: usize) -> Vec<(i64, f64)> {
+    let mut spikes = points.to_vec();
+    spikes.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    spikes.truncate(n);
+    spikes
+}
+
+type Statistic = fn(&Summary) -> f64;
+
+pub const COMPARISON_HEADERS: [&str; 6] = ["column", "statistic", "current", "baseline", "delta", "delta_percent"];
+
+/// Side by side statistics of the current and baseline windows of a column
+pub fn comparison_records(column: &str, current: Option<&Summary>, baseline: Option<&Summary>) -> Vec<Vec<String>> {
+    let statistics: [(&str, Statistic); 5] = [
+        ("count", |summary| summary.count as f64),
+        ("mean", |summary| summary.mean),
+        ("min", |summary| summary.min),
+        ("max", |summary| summary.max),
+        ("p95", |summary| summary.p95),
+    ];
+
+    statistics.into_iter()
+        .map(|(name, statistic)| {
+            // An empty window has no statistics, but still counts
+            let empty = if name == "count" { Some(0.0) } else { None };
+            delta_record(column, name, current.map(statistic).or(empty), baseline.map(statistic).or(empty))
+        })
+        .collect()
+}
+
+pub fn delta_record(column: &str, statistic: &str, current: Option<f64>, baseline: Option<f64>) -> Vec<String> {
+    let delta = current.zip(baseline).map(|(current, baseline)| current - baseline);
+    let delta_percent = delta.zip(baseline)
+        .filter(|(_, baseline)| *baseline != 0.0)
+        .map(|(delta, baseline)| delta / baseline.abs() * 100.0);
+
+    let format = |value: Option<f64>| value.map(|value| round(value).to_string()).unwrap_or_default();
+    vec![column.to_string(), statistic.to_string(), format(current), format(baseline), format(delta), format(delta_percent)]
+}
+
+/// Keeps 3 decimals, enough for the model while saving tokens
+pub fn round(value: f64) -> f64 {
+    (value * 1000.0).round() / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: i64 = 60 * 60 * 1000;
+
+    #[test]
+    fn test_summarize() {
+        let points: Vec<(i64, f64)> = (0..5).map(|hour| (hour * HOUR, (hour * 2) as f64)).collect();
+
+        let summary = summarize(&points).expect("Should summarize");
+
+        assert_eq!(summary.count, 5);
+        assert_eq!(summary.min, 0.0);
+        assert_eq!(summary.max, 8.0);
+        assert_eq!(summary.mean, 4.0);
+        assert_eq!(round(summary.stddev), 2.828);
+        assert_eq!(summary.p50, 4.0);
+        assert_eq!(round(summary.p95), 7.6);
+        assert_eq!(summary.trend_per_hour, 2.0);
+        assert_eq!(summarize(&[]), None);
+    }
+
+    #[test]
+    fn test_percentile_single_value() {
+        assert_eq!(percentile(&[3.0], 99.0), 3.0);
+    }
+
+    #[test]
+    fn test_comparison_records() {
+        let current = summarize(&[(0, 3.0), (1, 5.0)]);
+        let baseline = summarize(&[(0, 2.0)]);
+
+        let records = comparison_records("value", current.as_ref(), baseline.as_ref());
+
+        assert_eq!(records[0], vec!["value", "count", "2", "1", "1", "100"]);
+        assert_eq!(records[1], vec!["value", "mean", "4", "2", "2", "100"]);
+        assert_eq!(comparison_records("value", None, baseline.as_ref())[0], vec!["value", "count", "0", "1", "-1", "-100"]);
+        assert_eq!(comparison_records("value", None, baseline.as_ref())[1], vec!["value", "mean", "", "2", "", ""]);
+    }
+
+    #[test]
+    fn test_top_spikes() {
+        let points = [(1, 5.0), (2, 9.0), (3, 1.0), (4, 9.0)];
+
+        assert_eq!(top_spikes(&points, 3), vec![(2, 9.0), (4, 9.0), (1, 5.0)]);
+    }
+}
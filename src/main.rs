@@ -1,16 +1,25 @@
 mod datasource {
     pub mod app_description;
+    pub mod autoscaling_group;
+    pub mod cloudwatch_alarm;
+    pub mod cloudwatch_log_events;
     pub mod cloudwatch_log_insight;
     pub mod cloudwatch_metric;
     pub mod ec2;
+    pub mod ecs;
+    pub mod elb;
+    pub mod lambda;
     pub mod rds;
     pub mod ds;
 }
 mod lib {
     pub mod args;
+    pub mod anomaly;
+    pub mod aws;
     pub mod config;
     pub mod context;
     pub mod prompt;
+    pub mod summary;
     pub mod openai;
 }
 
@@ -21,7 +30,7 @@ use crate::lib::{args, openai, prompt};
 use clap::Parser;
 use std::error::Error;
 use async_openai::Client;
-use tokio::fs;
+use tokio::{fs, signal};
 
 const BANNER : &str = " 🔎 auto-diagnostic - v{x.y.z} ";
 
@@ -33,6 +42,18 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     let context = build_context(args, config)?;
 
+    // Listening for Ctrl-C replaces the default handler, so every long running step watches the token instead
+    let cancel = context.cancel.clone();
+    tokio::spawn(async move {
+        if signal::ctrl_c().await.is_ok() {
+            cancel.cancel();
+        }
+        // A second Ctrl-C doesn't wait for the running queries to be stopped
+        if signal::ctrl_c().await.is_ok() {
+            std::process::exit(130);
+        }
+    });
+
     let banner = BANNER.replace("{x.y.z}", env!("CARGO_PKG_VERSION"));
     println!("{banner}");
 
@@ -46,12 +67,17 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     if !context.dry_run {
         let client = Client::new();
-        openai::send_request(client, &context, OpenAiChatInput {
+        let request = openai::send_request(client, &context, OpenAiChatInput {
             model: context.open_ai_model.clone(),
             max_tokens: context.open_ai_max_token,
             system_prompt: instructions,
             user_prompt: prompt_data
-        }).await?;
+        });
+
+        tokio::select! {
+            result = request => { result?; },
+            _ = context.cancel.cancelled() => return Err("Interrupted".into()),
+        }
     }
 
     Ok(())